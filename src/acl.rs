@@ -0,0 +1,282 @@
+use std::ffi::{CStr, CString};
+use std::io;
+use std::ops::BitOr;
+use std::ptr;
+
+use libarchive3_sys::ffi;
+use libc::c_char;
+
+use crate::error::{ArchiveError, ArchiveResult};
+
+// Which of an entry's ACLs to read or add to. POSIX.1e ACLs are the access and default ACLs of
+// Linux and most Unix filesystems, NFSv4 ACLs the ones NFS, ZFS, macOS and Windows use. An entry
+// carries either kind, not both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclType {
+    // POSIX.1e access and default ACLs.
+    Posix1e,
+    Access,
+    Default,
+    // NFSv4 allow, deny, audit and alarm entries.
+    Nfs4,
+}
+
+impl AclType {
+    fn code(&self) -> i32 {
+        match self {
+            AclType::Posix1e => {
+                ffi::ARCHIVE_ENTRY_ACL_TYPE_ACCESS | ffi::ARCHIVE_ENTRY_ACL_TYPE_DEFAULT
+            }
+            AclType::Access => ffi::ARCHIVE_ENTRY_ACL_TYPE_ACCESS,
+            AclType::Default => ffi::ARCHIVE_ENTRY_ACL_TYPE_DEFAULT,
+            AclType::Nfs4 => {
+                ffi::ARCHIVE_ENTRY_ACL_TYPE_ALLOW
+                    | ffi::ARCHIVE_ENTRY_ACL_TYPE_DENY
+                    | ffi::ARCHIVE_ENTRY_ACL_TYPE_AUDIT
+                    | ffi::ARCHIVE_ENTRY_ACL_TYPE_ALARM
+            }
+        }
+    }
+}
+
+// What a single ACL entry does. `Access` and `Default` belong to POSIX.1e ACLs, the rest to
+// NFSv4 ACLs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclEntryType {
+    Access,
+    Default,
+    Allow,
+    Deny,
+    Audit,
+    Alarm,
+}
+
+impl AclEntryType {
+    fn code(&self) -> i32 {
+        match self {
+            AclEntryType::Access => ffi::ARCHIVE_ENTRY_ACL_TYPE_ACCESS,
+            AclEntryType::Default => ffi::ARCHIVE_ENTRY_ACL_TYPE_DEFAULT,
+            AclEntryType::Allow => ffi::ARCHIVE_ENTRY_ACL_TYPE_ALLOW,
+            AclEntryType::Deny => ffi::ARCHIVE_ENTRY_ACL_TYPE_DENY,
+            AclEntryType::Audit => ffi::ARCHIVE_ENTRY_ACL_TYPE_AUDIT,
+            AclEntryType::Alarm => ffi::ARCHIVE_ENTRY_ACL_TYPE_ALARM,
+        }
+    }
+
+    fn from_code(code: i32) -> Option<AclEntryType> {
+        match code {
+            ffi::ARCHIVE_ENTRY_ACL_TYPE_ACCESS => Some(AclEntryType::Access),
+            ffi::ARCHIVE_ENTRY_ACL_TYPE_DEFAULT => Some(AclEntryType::Default),
+            ffi::ARCHIVE_ENTRY_ACL_TYPE_ALLOW => Some(AclEntryType::Allow),
+            ffi::ARCHIVE_ENTRY_ACL_TYPE_DENY => Some(AclEntryType::Deny),
+            ffi::ARCHIVE_ENTRY_ACL_TYPE_AUDIT => Some(AclEntryType::Audit),
+            ffi::ARCHIVE_ENTRY_ACL_TYPE_ALARM => Some(AclEntryType::Alarm),
+            _ => None,
+        }
+    }
+}
+
+// Who an ACL entry applies to. `User` and `Group` name a specific user or group through the
+// entry's `id` and `name`. `UserObj` and `GroupObj` are the file's owner and group (`owner@` and
+// `group@` in NFSv4), `Mask` and `Other` only appear in POSIX.1e ACLs and `Everyone` only in
+// NFSv4 ACLs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclTag {
+    User,
+    UserObj,
+    Group,
+    GroupObj,
+    Mask,
+    Other,
+    Everyone,
+}
+
+impl AclTag {
+    fn code(&self) -> i32 {
+        match self {
+            AclTag::User => ffi::ARCHIVE_ENTRY_ACL_USER,
+            AclTag::UserObj => ffi::ARCHIVE_ENTRY_ACL_USER_OBJ,
+            AclTag::Group => ffi::ARCHIVE_ENTRY_ACL_GROUP,
+            AclTag::GroupObj => ffi::ARCHIVE_ENTRY_ACL_GROUP_OBJ,
+            AclTag::Mask => ffi::ARCHIVE_ENTRY_ACL_MASK,
+            AclTag::Other => ffi::ARCHIVE_ENTRY_ACL_OTHER,
+            AclTag::Everyone => ffi::ARCHIVE_ENTRY_ACL_EVERYONE,
+        }
+    }
+
+    fn from_code(code: i32) -> Option<AclTag> {
+        match code {
+            ffi::ARCHIVE_ENTRY_ACL_USER => Some(AclTag::User),
+            ffi::ARCHIVE_ENTRY_ACL_USER_OBJ => Some(AclTag::UserObj),
+            ffi::ARCHIVE_ENTRY_ACL_GROUP => Some(AclTag::Group),
+            ffi::ARCHIVE_ENTRY_ACL_GROUP_OBJ => Some(AclTag::GroupObj),
+            ffi::ARCHIVE_ENTRY_ACL_MASK => Some(AclTag::Mask),
+            ffi::ARCHIVE_ENTRY_ACL_OTHER => Some(AclTag::Other),
+            ffi::ARCHIVE_ENTRY_ACL_EVERYONE => Some(AclTag::Everyone),
+            _ => None,
+        }
+    }
+}
+
+// Permissions granted, denied or audited by an ACL entry, combined with `|`. POSIX.1e entries
+// only use `READ`, `WRITE` and `EXECUTE`. NFSv4 entries also carry their inheritance and audit
+// flags here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AclPerms(pub u32);
+
+impl AclPerms {
+    pub const EXECUTE: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_EXECUTE as u32);
+    pub const WRITE: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_WRITE as u32);
+    pub const READ: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_READ as u32);
+    pub const READ_DATA: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_READ_DATA as u32);
+    pub const LIST_DIRECTORY: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_LIST_DIRECTORY as u32);
+    pub const WRITE_DATA: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_WRITE_DATA as u32);
+    pub const ADD_FILE: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_ADD_FILE as u32);
+    pub const APPEND_DATA: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_APPEND_DATA as u32);
+    pub const ADD_SUBDIRECTORY: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_ADD_SUBDIRECTORY as u32);
+    pub const READ_NAMED_ATTRS: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_READ_NAMED_ATTRS as u32);
+    pub const WRITE_NAMED_ATTRS: AclPerms =
+        AclPerms(ffi::ARCHIVE_ENTRY_ACL_WRITE_NAMED_ATTRS as u32);
+    pub const DELETE_CHILD: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_DELETE_CHILD as u32);
+    pub const READ_ATTRIBUTES: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_READ_ATTRIBUTES as u32);
+    pub const WRITE_ATTRIBUTES: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_WRITE_ATTRIBUTES as u32);
+    pub const DELETE: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_DELETE as u32);
+    pub const READ_ACL: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_READ_ACL as u32);
+    pub const WRITE_ACL: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_WRITE_ACL as u32);
+    pub const WRITE_OWNER: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_WRITE_OWNER as u32);
+    pub const SYNCHRONIZE: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_SYNCHRONIZE as u32);
+    pub const FILE_INHERIT: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_ENTRY_FILE_INHERIT as u32);
+    pub const DIRECTORY_INHERIT: AclPerms =
+        AclPerms(ffi::ARCHIVE_ENTRY_ACL_ENTRY_DIRECTORY_INHERIT as u32);
+    pub const NO_PROPAGATE_INHERIT: AclPerms =
+        AclPerms(ffi::ARCHIVE_ENTRY_ACL_ENTRY_NO_PROPAGATE_INHERIT as u32);
+    pub const INHERIT_ONLY: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_ENTRY_INHERIT_ONLY as u32);
+    pub const INHERITED: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_ENTRY_INHERITED as u32);
+    pub const SUCCESSFUL_ACCESS: AclPerms =
+        AclPerms(ffi::ARCHIVE_ENTRY_ACL_ENTRY_SUCCESSFUL_ACCESS as u32);
+    pub const FAILED_ACCESS: AclPerms = AclPerms(ffi::ARCHIVE_ENTRY_ACL_ENTRY_FAILED_ACCESS as u32);
+
+    pub fn contains(&self, other: AclPerms) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for AclPerms {
+    type Output = AclPerms;
+
+    fn bitor(self, other: AclPerms) -> AclPerms {
+        AclPerms(self.0 | other.0)
+    }
+}
+
+// One entry of an ACL. `id` and `name` identify the user or group of `AclTag::User` and
+// `AclTag::Group` entries and are ignored for the other tags. When both are set, extraction
+// looks the user or group up by `name` first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclEntry {
+    pub entry_type: AclEntryType,
+    pub tag: AclTag,
+    pub perms: AclPerms,
+    pub id: Option<i32>,
+    pub name: Option<String>,
+}
+
+pub(crate) fn entries(entry: *mut ffi::Struct_archive_entry, acl_type: AclType) -> Vec<AclEntry> {
+    let mut acl = Vec::new();
+    unsafe {
+        ffi::archive_entry_acl_reset(entry, acl_type.code());
+        loop {
+            let (mut entry_type, mut perms, mut tag, mut id) = (0, 0, 0, -1);
+            let mut name: *const c_char = ptr::null();
+            let result = ffi::archive_entry_acl_next(
+                entry,
+                acl_type.code(),
+                &mut entry_type,
+                &mut perms,
+                &mut tag,
+                &mut id,
+                &mut name,
+            );
+            if result != ffi::ARCHIVE_OK {
+                return acl;
+            }
+            let (entry_type, tag) =
+                match (AclEntryType::from_code(entry_type), AclTag::from_code(tag)) {
+                    (Some(entry_type), Some(tag)) => (entry_type, tag),
+                    _ => continue,
+                };
+            let name = match name.is_null() {
+                true => None,
+                false => Some(CStr::from_ptr(name).to_string_lossy().into_owned()),
+            };
+            acl.push(AclEntry {
+                entry_type,
+                tag,
+                perms: AclPerms(perms as u32),
+                id: if id >= 0 { Some(id) } else { None },
+                name,
+            });
+        }
+    }
+}
+
+pub(crate) fn add_entry(
+    entry: *mut ffi::Struct_archive_entry,
+    acl: &AclEntry,
+) -> ArchiveResult<()> {
+    let name = match acl.name {
+        Some(ref name) => Some(CString::new(name.as_str()).map_err(invalid)?),
+        None => None,
+    };
+    let result = unsafe {
+        ffi::archive_entry_acl_add_entry(
+            entry,
+            acl.entry_type.code(),
+            acl.perms.0 as i32,
+            acl.tag.code(),
+            acl.id.unwrap_or(-1),
+            name.as_ref().map_or(ptr::null(), |name| name.as_ptr()),
+        )
+    };
+    match result {
+        ffi::ARCHIVE_OK => Ok(()),
+        _ => Err(invalid(format!("invalid ACL entry {:?}", acl))),
+    }
+}
+
+// ACL in the text form of `getfacl` for POSIX.1e ACLs and of `nfs4_getfacl` (as extended by
+// FreeBSD and Solaris) for NFSv4 ACLs, one entry per line with numeric IDs appended.
+pub(crate) fn to_text(entry: *mut ffi::Struct_archive_entry, acl_type: AclType) -> Option<String> {
+    unsafe {
+        if ffi::archive_entry_acl_count(entry, acl_type.code()) == 0 {
+            return None;
+        }
+        let flags = acl_type.code() | ffi::ARCHIVE_ENTRY_ACL_STYLE_EXTRA_ID;
+        let text = ffi::archive_entry_acl_to_text(entry, ptr::null_mut(), flags);
+        if text.is_null() {
+            return None;
+        }
+        let owned = CStr::from_ptr(text).to_string_lossy().into_owned();
+        libc::free(text as *mut libc::c_void);
+        Some(owned)
+    }
+}
+
+pub(crate) fn from_text(
+    entry: *mut ffi::Struct_archive_entry,
+    acl_type: AclType,
+    text: &str,
+) -> ArchiveResult<()> {
+    let c_text = CString::new(text).map_err(invalid)?;
+    match unsafe { ffi::archive_entry_acl_from_text(entry, c_text.as_ptr(), acl_type.code()) } {
+        ffi::ARCHIVE_OK => Ok(()),
+        _ => Err(invalid(format!("invalid ACL text {:?}", text))),
+    }
+}
+
+fn invalid<E>(err: E) -> ArchiveError
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidInput, err).into()
+}
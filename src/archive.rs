@@ -1,11 +1,72 @@
+use std::cmp::Ordering;
 use std::default::Default;
 use std::ffi::{CStr, CString};
+use std::io;
 use std::path::PathBuf;
 use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::error::ErrCode;
+use crate::acl::{self, AclEntry, AclType};
+use crate::error::{ArchiveError, ArchiveResult, ErrCode};
+use crate::listing::{self, Listing};
+use crate::reader::OwnedEntry;
 use libarchive3_sys::ffi;
 
+// Version of the libarchive the crate is linked against at runtime, encoded as
+// `major * 1000000 + minor * 1000 + patch`.
+pub fn version_number() -> i32 {
+    unsafe { ffi::archive_version_number() }
+}
+
+// Version of the linked libarchive in `major.minor.patch` form.
+pub fn version_string() -> String {
+    format_version(version_number())
+}
+
+// libarchive converts header strings (pathnames, link targets, user and group names) between the
+// charset of the archive and the charset of the LC_CTYPE locale. Processes that never call
+// setlocale run in the "C" locale, where any non-ASCII name fails to convert. This switches
+// LC_CTYPE for the whole process, e.g. to "C.UTF-8", and like setlocale itself should be called
+// during startup before other threads are spawned.
+pub fn set_ctype_locale(locale: &str) -> ArchiveResult<()> {
+    let c_locale = match CString::new(locale) {
+        Ok(c_locale) => c_locale,
+        Err(_) => return Err(ArchiveError::Locale(locale.to_string())),
+    };
+    let res = unsafe { libc::setlocale(libc::LC_CTYPE, c_locale.as_ptr()) };
+    if res.is_null() {
+        Err(ArchiveError::Locale(locale.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+// Options are built from what the caller passed, e.g. `IsoBoot::image` or a header charset, which
+// may hold a NUL byte.
+pub(crate) fn option_string(value: &str) -> ArchiveResult<CString> {
+    CString::new(value).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err).into())
+}
+
+pub(crate) fn format_version(number: i32) -> String {
+    format!(
+        "{}.{}.{}",
+        number / 1000000,
+        number / 1000 % 1000,
+        number % 1000
+    )
+}
+
+// Gate for wrappers of APIs newer than the oldest supported libarchive. The symbol may be missing
+// from an older shared library even when the crate was built against a newer one, so check before
+// calling into it.
+pub(crate) fn require_version(api: &'static str, required: i32) -> ArchiveResult<()> {
+    if version_number() < required {
+        Err(ArchiveError::Unsupported(api, required))
+    } else {
+        Ok(())
+    }
+}
+
 pub enum ReadCompression {
     All,
     Bzip2,
@@ -31,9 +92,17 @@ pub enum ReadFormat {
     Iso9660,
     Lha,
     Mtree,
+    // RAR up to version 4.
     Rar,
+    // RAR 5, requires libarchive >= 3.4.0.
+    #[cfg(feature = "rar5")]
+    Rar5,
     Raw,
     Tar,
+    // Web archives. Only `resource` and `response` records become entries, with the
+    // WARC-Target-URI as pathname, the WARC-Date as modification time and the record block as
+    // data; libarchive keeps no other record headers.
+    Warc,
     Xar,
     Zip,
 }
@@ -45,6 +114,7 @@ pub enum ReadFilter {
     Gzip,
     Grzip,
     Lrzip,
+    Lz4,
     Lzip,
     Lzma,
     Lzop,
@@ -54,11 +124,38 @@ pub enum ReadFilter {
     Rpm,
     Uu,
     Xz,
+    // Requires libarchive >= 3.3.3.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+// Options of the readers commonly set, see `reader::Builder::read_option`. Anything else can be
+// set by name with `reader::Builder::set_option`.
+pub enum ReadOption {
+    // Charset the headers of every format are encoded in, e.g. "CP932".
+    Hdrcharset(String),
+    // Ignore the Joliet extension of ISO images, reading the plain ISO 9660 names.
+    Iso9660NoJoliet,
+    // Ignore the Rock Ridge extension of ISO images, i.e. POSIX names, permissions and links.
+    Iso9660NoRockridge,
+    // Check mtree entries against the files they describe on disk.
+    MtreeCheckFs,
+    // Read the AppleDouble `._` entries Mac OS tar writes as metadata of the file they belong to.
+    TarMacExt,
+    // Read on past the end-of-archive marker, see `reader::Builder::read_concatenated_archives`.
+    TarReadConcatenated,
+    // Don't check entry data against its CRC-32, see `reader::Builder::verify_checksums`.
+    ZipIgnoreCrc32,
+    // Read the Mac OS metadata stored in `__MACOSX` entries along with the file it belongs to.
+    ZipMacExt,
 }
 
 pub enum WriteFormat {
     SevenZip,
+    // BSD ar. Names longer than 16 bytes are stored with each member's data.
     ArBsd,
+    // GNU (SVR4) ar, as used by `.deb` packages and static libraries. Names longer than 15 bytes
+    // need a table written first, see `Writer::write_ar_name_table`.
     ArSvr4,
     Cpio,
     CpioNewc,
@@ -68,7 +165,11 @@ pub enum WriteFormat {
     MtreeClassic,
     Pax,
     PaxRestricted,
+    // Shell archive, a `/bin/sh` script recreating its entries when run. Text files are embedded
+    // as is and unpacked with sed, anything else is uuencoded.
     Shar,
+    // Shell archive storing every file uuencoded, so binary data and trailing whitespace survive
+    // exactly. Unpacking needs `uudecode`.
     SharDump,
     Ustar,
     V7tar,
@@ -90,6 +191,49 @@ pub enum WriteFilter {
     Program(String),
     UuEncode,
     Xz,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+#[cfg(feature = "threads")]
+impl WriteFilter {
+    // Whether the linked libarchive and compression library can compress with several threads for
+    // this filter, see `writer::Builder::set_threads`. Only `Xz` and `Zstd` ever can; threads
+    // asked for where this is false are ignored and the data is compressed by a single thread.
+    pub fn supports_threads(&self) -> bool {
+        // libarchive learned the `threads` option of each filter in these versions, and each codec
+        // has multithreaded compression built in starting with these versions.
+        let (codec, since) = match *self {
+            WriteFilter::Xz if version_number() >= 3003000 => {
+                (unsafe { ffi::archive_liblzma_version() }, (5, 2))
+            }
+            #[cfg(feature = "zstd")]
+            WriteFilter::Zstd if version_number() >= 3006000 => {
+                (unsafe { ffi::archive_libzstd_version() }, (1, 5))
+            }
+            _ => return false,
+        };
+        codec_version(codec).is_some_and(|version| version >= since)
+    }
+}
+
+// Major and minor version of a compression library as reported by `archive_*_version`, which is
+// null when libarchive was built without it.
+#[cfg(feature = "threads")]
+fn codec_version(version: *const libc::c_char) -> Option<(u32, u32)> {
+    if version.is_null() {
+        return None;
+    }
+    let version = unsafe { CStr::from_ptr(version) }.to_str().ok()?;
+    let mut parts = version.split('.').map(|part| part.parse().ok());
+    Some((parts.next()??, parts.next()??))
+}
+
+// Output formats of `ReaderHandle::dump_metadata`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    // One JSON object per line, see https://jsonlines.org.
+    JsonLines,
 }
 
 pub enum FileType {
@@ -103,6 +247,61 @@ pub enum FileType {
     RegularFile,
 }
 
+// What a symlink points at. Windows has separate file and directory symlinks, and creates the
+// wrong kind, which then fails to resolve, when this is left `Undefined`.
+#[cfg(feature = "symlink-type")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkType {
+    Undefined,
+    File,
+    Directory,
+}
+
+#[cfg(feature = "symlink-type")]
+impl SymlinkType {
+    fn code(&self) -> i32 {
+        match self {
+            SymlinkType::Undefined => ffi::AE_SYMLINK_TYPE_UNDEFINED,
+            SymlinkType::File => ffi::AE_SYMLINK_TYPE_FILE,
+            SymlinkType::Directory => ffi::AE_SYMLINK_TYPE_DIRECTORY,
+        }
+    }
+}
+
+#[cfg(feature = "digest")]
+pub enum DigestType {
+    Md5,
+    Rmd160,
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+#[cfg(feature = "digest")]
+impl DigestType {
+    fn code(&self) -> i32 {
+        match self {
+            DigestType::Md5 => ffi::ARCHIVE_ENTRY_DIGEST_MD5,
+            DigestType::Rmd160 => ffi::ARCHIVE_ENTRY_DIGEST_RMD160,
+            DigestType::Sha1 => ffi::ARCHIVE_ENTRY_DIGEST_SHA1,
+            DigestType::Sha256 => ffi::ARCHIVE_ENTRY_DIGEST_SHA256,
+            DigestType::Sha384 => ffi::ARCHIVE_ENTRY_DIGEST_SHA384,
+            DigestType::Sha512 => ffi::ARCHIVE_ENTRY_DIGEST_SHA512,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            DigestType::Md5 => 16,
+            DigestType::Rmd160 | DigestType::Sha1 => 20,
+            DigestType::Sha256 => 32,
+            DigestType::Sha384 => 48,
+            DigestType::Sha512 => 64,
+        }
+    }
+}
+
 pub trait Handle {
     unsafe fn handle(&self) -> *mut ffi::Struct_archive;
 
@@ -120,6 +319,27 @@ pub trait Handle {
     }
 }
 
+// A source of archive entries that can be used as a trait object, e.g. to let plugins provide
+// inputs of their own next to archives read by this crate. Entries are owned, so they stay valid
+// however the source moves on.
+pub trait ArchiveSource {
+    // The next entry, or `None` once there are no more.
+    fn next_entry(&mut self) -> ArchiveResult<Option<OwnedEntry>>;
+}
+
+// Receives updates from long-running operations such as extraction, so a single implementation
+// can drive a progress bar, a log or metrics for all of them. Every method defaults to doing
+// nothing.
+pub trait Progress {
+    // `total` is the entry's size in bytes, when the archive records it up front.
+    fn entry_started(&mut self, _pathname: &str, _total: Option<u64>) {}
+
+    // Called as data is processed, with the number of bytes since the previous call.
+    fn bytes_processed(&mut self, _bytes: u64) {}
+
+    fn entry_finished(&mut self, _pathname: &str) {}
+}
+
 pub trait Entry {
     unsafe fn entry(&self) -> *mut ffi::Struct_archive_entry;
 
@@ -151,22 +371,145 @@ pub trait Entry {
         Some(str::from_utf8(buf).unwrap())
     }
 
+    // Digest recorded for the entry by formats that carry one (mtree, xar). Returns `None` when the
+    // archive did not record a digest of the requested type.
+    #[cfg(feature = "digest")]
+    fn digest(&self, kind: DigestType) -> ArchiveResult<Option<Vec<u8>>> {
+        require_version("archive_entry_digest", 3007000)?;
+        let digest = unsafe {
+            let ptr = ffi::archive_entry_digest(self.entry(), kind.code());
+            if ptr.is_null() {
+                return Ok(None);
+            }
+            std::slice::from_raw_parts(ptr, kind.len())
+        };
+        if digest.iter().all(|b| *b == 0) {
+            Ok(None)
+        } else {
+            Ok(Some(digest.to_vec()))
+        }
+    }
+
+    // The entry as a line of `bsdtar -tv` output.
+    fn listing(&self) -> Listing {
+        Listing::from_entry(unsafe { self.entry() })
+    }
+
     fn pathname(&self) -> &str {
         let c_str: &CStr = unsafe { CStr::from_ptr(ffi::archive_entry_pathname(self.entry())) };
         let buf: &[u8] = c_str.to_bytes();
         str::from_utf8(buf).unwrap()
     }
 
+    // Permission bits of the entry mode, including the setuid, setgid and sticky bits.
+    fn perm(&self) -> u32 {
+        unsafe { ffi::archive_entry_perm(self.entry()) as u32 }
+    }
+
+    // The full mode, file type bits included, as `st_mode` holds it.
+    fn mode(&self) -> u32 {
+        unsafe { ffi::archive_entry_mode(self.entry()) as u32 }
+    }
+
+    // The permission bits as `std::fs::Permissions`, e.g. for `std::fs::set_permissions`.
+    #[cfg(unix)]
+    fn permissions(&self) -> std::fs::Permissions {
+        std::os::unix::fs::PermissionsExt::from_mode(self.perm())
+    }
+
+    // The mode as `ls -l` shows it, e.g. `drwxr-xr-x`.
+    fn mode_string(&self) -> String {
+        listing::mode_string(unsafe { self.entry() })
+    }
+
     fn size(&self) -> i64 {
         unsafe { ffi::archive_entry_size(self.entry()) }
     }
 
+    // Modification time, if the entry has one.
+    fn mtime(&self) -> Option<SystemTime> {
+        unsafe {
+            entry_time(
+                ffi::archive_entry_mtime_is_set(self.entry()),
+                ffi::archive_entry_mtime(self.entry()) as _,
+                ffi::archive_entry_mtime_nsec(self.entry()) as _,
+            )
+        }
+    }
+
+    // Access time, if the entry has one. Plain ustar headers don't record it.
+    fn atime(&self) -> Option<SystemTime> {
+        unsafe {
+            entry_time(
+                ffi::archive_entry_atime_is_set(self.entry()),
+                ffi::archive_entry_atime(self.entry()) as _,
+                ffi::archive_entry_atime_nsec(self.entry()) as _,
+            )
+        }
+    }
+
+    // Inode change time, if the entry has one.
+    fn ctime(&self) -> Option<SystemTime> {
+        unsafe {
+            entry_time(
+                ffi::archive_entry_ctime_is_set(self.entry()),
+                ffi::archive_entry_ctime(self.entry()) as _,
+                ffi::archive_entry_ctime_nsec(self.entry()) as _,
+            )
+        }
+    }
+
+    // Creation time, if the entry has one. Only pax, xar and a few other formats record it.
+    fn birthtime(&self) -> Option<SystemTime> {
+        unsafe {
+            entry_time(
+                ffi::archive_entry_birthtime_is_set(self.entry()),
+                ffi::archive_entry_birthtime(self.entry()) as _,
+                ffi::archive_entry_birthtime_nsec(self.entry()) as _,
+            )
+        }
+    }
+
     fn symlink(&self) -> &str {
         let c_str: &CStr = unsafe { CStr::from_ptr(ffi::archive_entry_symlink(self.entry())) };
         let buf: &[u8] = c_str.to_bytes();
         str::from_utf8(buf).unwrap()
     }
 
+    // Entries of the entry's ACL of the given type. Entries read from pax, star and xar archives
+    // or from disk carry ACLs.
+    fn acl(&self, acl_type: AclType) -> Vec<AclEntry> {
+        acl::entries(unsafe { self.entry() }, acl_type)
+    }
+
+    // The ACL of the given type as text, e.g. `user::rw-` lines for POSIX.1e and
+    // `owner@:rwxp--aARWcCos:-------:allow` lines for NFSv4. `None` if the entry has none.
+    fn acl_text(&self, acl_type: AclType) -> Option<String> {
+        acl::to_text(unsafe { self.entry() }, acl_type)
+    }
+
+    // Kind of symlink recorded for the entry. Only pax archives store it, other formats read as
+    // `SymlinkType::Undefined`.
+    #[cfg(feature = "symlink-type")]
+    fn symlink_type(&self) -> ArchiveResult<SymlinkType> {
+        require_version("archive_entry_symlink_type", 3006000)?;
+        match unsafe { ffi::archive_entry_symlink_type(self.entry()) } {
+            ffi::AE_SYMLINK_TYPE_FILE => Ok(SymlinkType::File),
+            ffi::AE_SYMLINK_TYPE_DIRECTORY => Ok(SymlinkType::Directory),
+            _ => Ok(SymlinkType::Undefined),
+        }
+    }
+
+    // Whether the entry data is encrypted, in which case reading it needs a passphrase.
+    fn is_data_encrypted(&self) -> bool {
+        unsafe { ffi::archive_entry_is_data_encrypted(self.entry()) != 0 }
+    }
+
+    // Whether the entry metadata is encrypted, as 7-Zip archives with encrypted headers are.
+    fn is_metadata_encrypted(&self) -> bool {
+        unsafe { ffi::archive_entry_is_metadata_encrypted(self.entry()) != 0 }
+    }
+
     fn set_filetype(&mut self, file_type: FileType) {
         unsafe {
             let file_type = match file_type {
@@ -183,6 +526,83 @@ pub trait Entry {
         }
     }
 
+    fn set_mtime(&mut self, time: SystemTime) {
+        let (secs, nsecs) = unix_time(time);
+        unsafe {
+            ffi::archive_entry_set_mtime(self.entry(), secs as _, nsecs as _);
+        }
+    }
+
+    fn set_atime(&mut self, time: SystemTime) {
+        let (secs, nsecs) = unix_time(time);
+        unsafe {
+            ffi::archive_entry_set_atime(self.entry(), secs as _, nsecs as _);
+        }
+    }
+
+    // Recorded by pax archives, but never restored on extraction.
+    fn set_ctime(&mut self, time: SystemTime) {
+        let (secs, nsecs) = unix_time(time);
+        unsafe {
+            ffi::archive_entry_set_ctime(self.entry(), secs as _, nsecs as _);
+        }
+    }
+
+    // Restored on extraction with `ExtractOptions::preserve_times` where the platform supports
+    // setting it (macOS, FreeBSD and Windows).
+    fn set_birthtime(&mut self, time: SystemTime) {
+        let (secs, nsecs) = unix_time(time);
+        unsafe {
+            ffi::archive_entry_set_birthtime(self.entry(), secs as _, nsecs as _);
+        }
+    }
+
+    // Adds an entry to the entry's ACL. POSIX.1e and NFSv4 entries can't be mixed.
+    fn add_acl_entry(&mut self, entry: &AclEntry) -> ArchiveResult<()> {
+        acl::add_entry(unsafe { self.entry() }, entry)
+    }
+
+    // Adds the entries of an ACL in the text form returned by `acl_text`.
+    fn add_acl_text(&mut self, acl_type: AclType, text: &str) -> ArchiveResult<()> {
+        acl::from_text(unsafe { self.entry() }, acl_type, text)
+    }
+
+    fn clear_acl(&mut self) {
+        unsafe { ffi::archive_entry_acl_clear(self.entry()) }
+    }
+
+    // Honored when extracting on Windows, see `SymlinkType`.
+    #[cfg(feature = "symlink-type")]
+    fn set_symlink_type(&mut self, symlink_type: SymlinkType) -> ArchiveResult<()> {
+        require_version("archive_entry_set_symlink_type", 3006000)?;
+        unsafe { ffi::archive_entry_set_symlink_type(self.entry(), symlink_type.code()) };
+        Ok(())
+    }
+
+    fn set_uid(&mut self, uid: i64) {
+        unsafe {
+            ffi::archive_entry_set_uid(self.entry(), uid);
+        }
+    }
+
+    fn set_gid(&mut self, gid: i64) {
+        unsafe {
+            ffi::archive_entry_set_gid(self.entry(), gid);
+        }
+    }
+
+    fn set_perm(&mut self, perm: u32) {
+        unsafe {
+            ffi::archive_entry_set_perm(self.entry(), perm as _);
+        }
+    }
+
+    fn set_size(&mut self, size: i64) {
+        unsafe {
+            ffi::archive_entry_set_size(self.entry(), size);
+        }
+    }
+
     fn set_link(&mut self, path: &PathBuf) {
         unsafe {
             let c_str = CString::new(path.to_str().unwrap()).unwrap();
@@ -198,6 +618,40 @@ pub trait Entry {
     }
 }
 
+// A timestamp read from an entry, `None` unless libarchive's `*_is_set` reported it.
+pub(crate) fn entry_time(is_set: libc::c_int, secs: i64, nsecs: i64) -> Option<SystemTime> {
+    match is_set {
+        0 => None,
+        _ => Some(system_time(secs, nsecs)),
+    }
+}
+
+// Time `secs` and `nsecs` after the epoch, either of which may be negative.
+pub(crate) fn system_time(secs: i64, nsecs: i64) -> SystemTime {
+    let time = match secs {
+        secs if secs >= 0 => UNIX_EPOCH + Duration::from_secs(secs as u64),
+        secs => UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()),
+    };
+    match nsecs {
+        nsecs if nsecs >= 0 => time + Duration::from_nanos(nsecs as u64),
+        nsecs => time - Duration::from_nanos(nsecs.unsigned_abs()),
+    }
+}
+
+// Seconds and non-negative nanoseconds since the epoch, as libarchive stores times.
+pub(crate) fn unix_time(time: SystemTime) -> (i64, i64) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => (after.as_secs() as i64, after.subsec_nanos() as i64),
+        Err(err) => {
+            let before = err.duration();
+            match before.subsec_nanos() {
+                0 => (-(before.as_secs() as i64), 0),
+                nsecs => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nsecs as i64),
+            }
+        }
+    }
+}
+
 pub enum ExtractOption {
     // The user and group IDs should be set on the restored file. By default, the user and group
     // IDs are not restored.
@@ -211,7 +665,8 @@ pub enum ExtractOption {
     // is obeyed.
     Permissions,
     // The timestamps (mtime, ctime, and atime) should be restored. By default, they are ignored.
-    // Note that restoring of atime is not currently supported.
+    // Note that restoring of atime is not currently supported. Times keep the nanoseconds the
+    // archive recorded, and the birthtime is restored too on macOS, FreeBSD and Windows.
     Time,
     // Existing files on disk will not be overwritten. By default, existing regular files are
     // truncated and overwritten; existing directories will have their permissions updated; other
@@ -222,7 +677,8 @@ pub enum ExtractOption {
     // truncated and rewritten, but the file is not recreated. In particular, the default behavior
     // does not break existing hard links.
     Unlink,
-    // Attempt to restore ACLs. By default, extended ACLs are ignored.
+    // Attempt to restore POSIX.1e or NFSv4 ACLs, whichever the entry carries and the filesystem
+    // supports. By default, extended ACLs are ignored.
     ACL,
     // Attempt to restore extended file flags. By default, file flags are ignored.
     FFlags,
@@ -261,6 +717,140 @@ pub enum ExtractOption {
     ClearNoChangeFFlags,
 }
 
+// Whether symlinks found while archiving from disk are stored as links or replaced by what they
+// point to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FollowSymlinks {
+    // Store symlinks as links. This is the default.
+    Never,
+    // Store the target of every symlink in place of the link.
+    Always,
+    // Follow a symlink given as the path to archive, but store the links found below it.
+    CommandLineOnly,
+}
+
+// Order in which files found while archiving from disk are written.
+pub enum EntryOrder {
+    // As the filesystem lists them, which varies between filesystems and runs. This is the
+    // default.
+    Walk,
+    // Sorted by pathname, byte by byte, so a directory comes before its contents.
+    Path,
+    // Sorted by a comparison of pathnames.
+    Custom(PathComparator),
+}
+
+pub type PathComparator = Box<dyn Fn(&str, &str) -> Ordering>;
+
+// Owner information stored for entries written to an archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ownership {
+    // Store user and group ids and names as found. This is the default.
+    Preserve,
+    // Store the ids but leave out user and group names.
+    NumericOnly,
+    // Store every entry as owned by `uid` and `gid`, without names. `Fixed { uid: 0, gid: 0 }`
+    // keeps the build user out of distribution tarballs and container layers.
+    Fixed { uid: i64, gid: i64 },
+}
+
+// El Torito boot record making an ISO image bootable, see `writer::Builder::set_iso_boot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IsoBoot {
+    // Path of the boot image inside the ISO. It has to be written to the archive like any other
+    // file.
+    pub image: String,
+    // Path the boot catalog is written to inside the ISO, `boot.catalog` if not set.
+    pub catalog: Option<String>,
+    pub emulation: BootEmulation,
+    // Number of 512-byte sectors the BIOS loads from a no-emulation image, 4 if not set.
+    pub load_size: Option<u32>,
+    // Patch a boot info table into the image, as isolinux expects.
+    pub info_table: bool,
+}
+
+impl IsoBoot {
+    // Boots `image` without emulation, as isolinux and GRUB images do.
+    pub fn new(image: &str) -> Self {
+        IsoBoot {
+            image: image.to_string(),
+            catalog: None,
+            emulation: BootEmulation::NoEmulation,
+            load_size: None,
+            info_table: false,
+        }
+    }
+}
+
+// What the BIOS presents a boot image as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootEmulation {
+    NoEmulation,
+    // A 1.2, 1.44 or 2.88 MB floppy, picked from the size of the image.
+    Floppy,
+    // A hard disk, the image holding its MBR.
+    HardDisk,
+}
+
+// What a `writer::Disk::set_create_hook` callback decides for an entry about to be extracted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    // Leave the entry out and carry on with the next one.
+    Skip,
+    // Stop extracting, failing with `ArchiveError::Aborted`.
+    Abort,
+}
+
+// Entry to move to with `reader::ReaderHandle::skip_to`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryTarget<'a> {
+    // The first entry with exactly this pathname.
+    Name(&'a str),
+    // The entry at this position, counting from 0 at the first entry of the archive.
+    Index(usize),
+}
+
+impl<'a> From<&'a str> for EntryTarget<'a> {
+    fn from(name: &'a str) -> Self {
+        EntryTarget::Name(name)
+    }
+}
+
+impl<'a> From<usize> for EntryTarget<'a> {
+    fn from(index: usize) -> Self {
+        EntryTarget::Index(index)
+    }
+}
+
+// What to do when an extracted file already exists on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overwrite {
+    // Replace existing files. This is the default.
+    Always,
+    // Only replace existing files that are older than the archive entry.
+    IfOlder,
+    // Never replace existing files.
+    Never,
+}
+
+// Whether to use HFS+ compression for extracted files. Has no effect except on Mac OS v10.6 or
+// later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HfsCompression {
+    // Compress files that were compressed when archived. This is the default.
+    Preserve,
+    Never,
+    Always,
+}
+
+// Options for extracting to disk, lowered to libarchive's `ARCHIVE_EXTRACT_*` flag word:
+//
+//     let opts = ExtractOptions::new()
+//         .preserve_times(true)
+//         .preserve_permissions(true)
+//         .secure(true);
+#[derive(Debug, Clone, Copy)]
 pub struct ExtractOptions {
     pub flags: i32,
 }
@@ -270,6 +860,7 @@ impl ExtractOptions {
         ExtractOptions::default()
     }
 
+    #[deprecated(note = "use the typed builder methods such as `preserve_times`")]
     pub fn add(&mut self, opt: ExtractOption) -> &mut Self {
         let flag = match opt {
             ExtractOption::Owner => ffi::ARCHIVE_EXTRACT_OWNER,
@@ -294,6 +885,118 @@ impl ExtractOptions {
         self.flags |= flag;
         self
     }
+
+    // Restore the user and group IDs. See `ExtractOption::Owner`.
+    pub fn preserve_owner(self, on: bool) -> Self {
+        self.set(ffi::ARCHIVE_EXTRACT_OWNER, on)
+    }
+
+    // Restore full permissions including SGID, SUID and sticky bits, ignoring the umask. See
+    // `ExtractOption::Permissions`.
+    pub fn preserve_permissions(self, on: bool) -> Self {
+        self.set(ffi::ARCHIVE_EXTRACT_PERM, on)
+    }
+
+    // Restore mtime and ctime to the nanosecond, and the birthtime where the platform allows it.
+    // See `ExtractOption::Time`.
+    pub fn preserve_times(self, on: bool) -> Self {
+        self.set(ffi::ARCHIVE_EXTRACT_TIME, on)
+    }
+
+    // Restore POSIX.1e and NFSv4 ACLs. See `ExtractOption::ACL`.
+    pub fn preserve_acls(self, on: bool) -> Self {
+        self.set(ffi::ARCHIVE_EXTRACT_ACL, on)
+    }
+
+    pub fn preserve_fflags(self, on: bool) -> Self {
+        self.set(ffi::ARCHIVE_EXTRACT_FFLAGS, on)
+    }
+
+    pub fn preserve_xattrs(self, on: bool) -> Self {
+        self.set(ffi::ARCHIVE_EXTRACT_XATTR, on)
+    }
+
+    // Restore Mac extended metadata. Has no effect except on Mac OS.
+    pub fn preserve_mac_metadata(self, on: bool) -> Self {
+        self.set(ffi::ARCHIVE_EXTRACT_MAC_METADATA, on)
+    }
+
+    pub fn overwrite(self, overwrite: Overwrite) -> Self {
+        let flags = ffi::ARCHIVE_EXTRACT_NO_OVERWRITE | ffi::ARCHIVE_EXTRACT_NO_OVERWRITE_NEWER;
+        let flag = match overwrite {
+            Overwrite::Always => 0,
+            Overwrite::IfOlder => ffi::ARCHIVE_EXTRACT_NO_OVERWRITE_NEWER,
+            Overwrite::Never => ffi::ARCHIVE_EXTRACT_NO_OVERWRITE,
+        };
+        ExtractOptions {
+            flags: self.flags & !flags | flag,
+        }
+    }
+
+    // Unlink existing files before creating them instead of truncating them. See
+    // `ExtractOption::Unlink`.
+    pub fn unlink(self, on: bool) -> Self {
+        self.set(ffi::ARCHIVE_EXTRACT_UNLINK, on)
+    }
+
+    // Enables all of `secure_symlinks`, `secure_no_dot_dot` and `secure_no_absolute_paths`, which
+    // together keep extraction inside the destination directory.
+    pub fn secure(self, on: bool) -> Self {
+        self.secure_symlinks(on)
+            .secure_no_dot_dot(on)
+            .secure_no_absolute_paths(on)
+    }
+
+    // Refuse to extract through symlinks on disk. See `ExtractOption::SecureSymlinks`.
+    pub fn secure_symlinks(self, on: bool) -> Self {
+        self.set(ffi::ARCHIVE_EXTRACT_SECURE_SYMLINKS, on)
+    }
+
+    // Refuse to extract paths containing `..`. See `ExtractOption::SecureNoDotDot`.
+    pub fn secure_no_dot_dot(self, on: bool) -> Self {
+        self.set(ffi::ARCHIVE_EXTRACT_SECURE_NODOTDOT, on)
+    }
+
+    pub fn secure_no_absolute_paths(self, on: bool) -> Self {
+        self.set(ffi::ARCHIVE_EXTRACT_SECURE_NOABSOLUTEPATHS, on)
+    }
+
+    // Create missing parent directories. Enabled by default.
+    pub fn create_parent_dirs(self, on: bool) -> Self {
+        self.set(ffi::ARCHIVE_EXTRACT_NO_AUTODIR, !on)
+    }
+
+    // Recreate runs of NUL bytes as holes. See `ExtractOption::Sparse`.
+    pub fn sparse(self, on: bool) -> Self {
+        self.set(ffi::ARCHIVE_EXTRACT_SPARSE, on)
+    }
+
+    pub fn hfs_compression(self, compression: HfsCompression) -> Self {
+        let flags =
+            ffi::ARCHIVE_EXTRACT_NO_HFS_COMPRESSION | ffi::ARCHIVE_EXTRACT_HFS_COMPRESSION_FORCED;
+        let flag = match compression {
+            HfsCompression::Preserve => 0,
+            HfsCompression::Never => ffi::ARCHIVE_EXTRACT_NO_HFS_COMPRESSION,
+            HfsCompression::Always => ffi::ARCHIVE_EXTRACT_HFS_COMPRESSION_FORCED,
+        };
+        ExtractOptions {
+            flags: self.flags & !flags | flag,
+        }
+    }
+
+    // Clear no-change file flags when unlinking objects.
+    pub fn clear_nochange_fflags(self, on: bool) -> Self {
+        self.set(ffi::ARCHIVE_EXTRACT_CLEAR_NOCHANGE_FFLAGS, on)
+    }
+
+    fn set(self, flag: i32, on: bool) -> Self {
+        let flags = if on {
+            self.flags | flag
+        } else {
+            self.flags & !flag
+        };
+        ExtractOptions { flags }
+    }
 }
 
 impl Default for ExtractOptions {
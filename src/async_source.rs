@@ -0,0 +1,38 @@
+// Async sources for the reader. libarchive pulls data through a blocking read callback, so an
+// `AsyncRead` is driven to completion on the tokio runtime each time it asks for more. The thread
+// reading the archive blocks meanwhile: on a worker of the multi-threaded runtime the worker hands
+// its other tasks off first (`block_in_place`), elsewhere, e.g. in `spawn_blocking`, it simply
+// waits.
+
+use std::io::{self, Read};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::task;
+
+pub(crate) struct AsyncSource<R> {
+    src: R,
+    runtime: Handle,
+}
+
+impl<R: AsyncRead + Unpin> AsyncSource<R> {
+    // Fails outside of a tokio runtime, or on a current-thread runtime, which has no other thread
+    // to run its tasks while the reader blocks.
+    pub(crate) fn new(src: R) -> io::Result<Self> {
+        let runtime = Handle::try_current().map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        if runtime.runtime_flavor() == RuntimeFlavor::CurrentThread {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "async sources need the multi-threaded tokio runtime",
+            ));
+        }
+        Ok(AsyncSource { src, runtime })
+    }
+}
+
+impl<R: AsyncRead + Unpin> Read for AsyncSource<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let AsyncSource { src, runtime } = self;
+        task::block_in_place(|| runtime.block_on(src.read(buf)))
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashSet;
+
+use libarchive3_sys::ffi;
+
+use crate::listing::lossy;
+use crate::paths::{self, climbs};
+
+// Something potentially dangerous about an entry, as reported by `ReaderHandle::audit`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    AbsolutePath,
+    // `..` components climbing above the directory the archive is extracted to.
+    ParentTraversal,
+    // A symlink or hardlink whose target lies outside the extraction directory.
+    EscapingLink(String),
+    // The path runs through a symlink stored earlier in the archive, so extracting it writes
+    // wherever that symlink points.
+    BelowSymlink,
+    Device,
+    Fifo,
+    Setuid,
+    Setgid,
+    // An earlier non-directory entry has the same path and is overwritten on extraction.
+    Duplicate,
+    // The declared size is above the limit given to `audit`.
+    Oversized(i64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditIssue {
+    pub pathname: String,
+    pub finding: Finding,
+}
+
+// State carried across the entries of one archive.
+#[derive(Default)]
+pub(crate) struct Auditor {
+    max_size: Option<u64>,
+    seen: HashSet<String>,
+    symlinks: HashSet<String>,
+}
+
+impl Auditor {
+    pub(crate) fn new(max_size: Option<u64>) -> Self {
+        Auditor {
+            max_size,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn check(
+        &mut self,
+        entry: *mut ffi::Struct_archive_entry,
+        issues: &mut Vec<AuditIssue>,
+    ) {
+        let pathname = lossy(unsafe { ffi::archive_entry_pathname(entry) }).unwrap_or_default();
+        let mut report = |finding| {
+            issues.push(AuditIssue {
+                pathname: pathname.clone(),
+                finding,
+            })
+        };
+
+        let path = paths::convert_separators(&pathname);
+        if paths::strip_root(&path).len() != path.len() {
+            report(Finding::AbsolutePath);
+        }
+        if climbs(&path) {
+            report(Finding::ParentTraversal);
+        }
+        let key = paths::sanitize(&path);
+        let mut parent = key.as_str();
+        while let Some(i) = parent.rfind('/') {
+            parent = &parent[..i];
+            if self.symlinks.contains(parent) {
+                report(Finding::BelowSymlink);
+                break;
+            }
+        }
+
+        let (filetype, mode, size) = unsafe {
+            (
+                ffi::archive_entry_filetype(entry) as u32,
+                ffi::archive_entry_mode(entry) as u32,
+                ffi::archive_entry_size(entry),
+            )
+        };
+        let hardlink = lossy(unsafe { ffi::archive_entry_hardlink(entry) });
+        let symlink = lossy(unsafe { ffi::archive_entry_symlink(entry) });
+        if let Some(target) = hardlink {
+            // Hardlink targets are named from the root, like entry paths.
+            let target_path = paths::convert_separators(&target);
+            if paths::strip_root(&target_path).len() != target_path.len() || climbs(&target_path) {
+                report(Finding::EscapingLink(target));
+            }
+        } else if let Some(target) = symlink {
+            // Symlink targets are resolved from the directory holding the link.
+            let target_path = paths::convert_separators(&target);
+            let dir = match key.rfind('/') {
+                Some(i) => &key[..i],
+                None => "",
+            };
+            if paths::strip_root(&target_path).len() != target_path.len()
+                || climbs(&format!("{}/{}", dir, target_path))
+            {
+                report(Finding::EscapingLink(target));
+            }
+            self.symlinks.insert(key.clone());
+        }
+
+        match filetype {
+            ffi::AE_IFCHR | ffi::AE_IFBLK => report(Finding::Device),
+            ffi::AE_IFIFO => report(Finding::Fifo),
+            _ => (),
+        }
+        if filetype != ffi::AE_IFDIR {
+            if mode & 0o4000 != 0 {
+                report(Finding::Setuid);
+            }
+            if mode & 0o2000 != 0 {
+                report(Finding::Setgid);
+            }
+            if !self.seen.insert(key) {
+                report(Finding::Duplicate);
+            }
+        }
+        if let Some(max) = self.max_size {
+            if size > 0 && size as u64 > max {
+                report(Finding::Oversized(size));
+            }
+        }
+    }
+}
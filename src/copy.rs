@@ -0,0 +1,56 @@
+// In-kernel copying of stored entry data from the archive file to the extracted file, so the data
+// never passes through user space.
+
+use std::fs::File;
+use std::io;
+
+// Copies `len` bytes at `offset` in `src` to the current position of `dst`. Returns `Ok(false)`
+// without having copied anything when neither `copy_file_range` nor `sendfile` works for the two
+// files, e.g. on kernels lacking them, so callers can fall back to copying through user space.
+#[cfg(target_os = "linux")]
+pub(crate) fn copy_range(src: &File, offset: u64, dst: &File, len: u64) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+
+    let (src, dst) = (src.as_raw_fd(), dst.as_raw_fd());
+    let mut off_in = offset as libc::off64_t;
+    let mut remaining = len;
+    let mut use_sendfile = false;
+    while remaining > 0 {
+        let chunk = remaining.min(1 << 30) as usize;
+        let copied = unsafe {
+            if use_sendfile {
+                libc::sendfile64(dst, src, &mut off_in, chunk)
+            } else {
+                libc::copy_file_range(src, &mut off_in, dst, ptr::null_mut(), chunk, 0)
+            }
+        };
+        if copied < 0 {
+            let err = io::Error::last_os_error();
+            let unsupported = matches!(
+                err.raw_os_error(),
+                Some(libc::ENOSYS | libc::EXDEV | libc::EINVAL | libc::EOPNOTSUPP)
+            );
+            if unsupported && remaining == len {
+                // copy_file_range can't cross filesystems before Linux 5.3 and isn't supported
+                // on every filesystem, sendfile handles those.
+                if use_sendfile {
+                    return Ok(false);
+                }
+                use_sendfile = true;
+                continue;
+            }
+            return Err(err);
+        }
+        if copied == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        remaining -= copied as u64;
+    }
+    Ok(true)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn copy_range(_src: &File, _offset: u64, _dst: &File, _len: u64) -> io::Result<bool> {
+    Ok(false)
+}
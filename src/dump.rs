@@ -0,0 +1,97 @@
+use std::ffi::CStr;
+use std::io::{self, Write};
+
+use libarchive3_sys::ffi;
+use libc::c_char;
+
+// Writes an entry header as a single-line JSON object:
+//
+//     {"path":"hello.txt","type":"file","size":14,"mode":33188,"mtime":1670630400,"atime":null,
+//      "ctime":null,"uid":1000,"gid":1000,"uname":"user","gname":"user","link":null}
+//
+// `mode` is the full mode, file type bits included. Times are seconds since the epoch, `null`
+// when the archive doesn't store them. `link` is the target of symlinks and hardlinks. Names that
+// aren't valid UTF-8 are converted lossily.
+pub(crate) fn write_json_line<W: Write>(
+    out: &mut W,
+    entry: *mut ffi::Struct_archive_entry,
+) -> io::Result<()> {
+    unsafe {
+        let hardlink = ffi::archive_entry_hardlink(entry);
+        let (kind, link) = if !hardlink.is_null() {
+            ("hardlink", hardlink)
+        } else {
+            let kind = match ffi::archive_entry_filetype(entry) as u32 {
+                ffi::AE_IFREG => "file",
+                ffi::AE_IFDIR => "directory",
+                ffi::AE_IFLNK => "symlink",
+                ffi::AE_IFCHR => "character-device",
+                ffi::AE_IFBLK => "block-device",
+                ffi::AE_IFIFO => "fifo",
+                ffi::AE_IFSOCK => "socket",
+                _ => "unknown",
+            };
+            (kind, ffi::archive_entry_symlink(entry))
+        };
+
+        out.write_all(b"{\"path\":")?;
+        write_string(out, ffi::archive_entry_pathname(entry))?;
+        write!(out, ",\"type\":\"{}\"", kind)?;
+        write!(out, ",\"size\":{}", ffi::archive_entry_size(entry))?;
+        write!(out, ",\"mode\":{}", ffi::archive_entry_mode(entry))?;
+        let times = [
+            (
+                "mtime",
+                ffi::archive_entry_mtime_is_set(entry),
+                ffi::archive_entry_mtime(entry),
+            ),
+            (
+                "atime",
+                ffi::archive_entry_atime_is_set(entry),
+                ffi::archive_entry_atime(entry),
+            ),
+            (
+                "ctime",
+                ffi::archive_entry_ctime_is_set(entry),
+                ffi::archive_entry_ctime(entry),
+            ),
+        ];
+        for &(name, is_set, time) in times.iter() {
+            if is_set != 0 {
+                write!(out, ",\"{}\":{}", name, time)?;
+            } else {
+                write!(out, ",\"{}\":null", name)?;
+            }
+        }
+        write!(out, ",\"uid\":{}", ffi::archive_entry_uid(entry))?;
+        write!(out, ",\"gid\":{}", ffi::archive_entry_gid(entry))?;
+        out.write_all(b",\"uname\":")?;
+        write_string(out, ffi::archive_entry_uname(entry))?;
+        out.write_all(b",\"gname\":")?;
+        write_string(out, ffi::archive_entry_gname(entry))?;
+        out.write_all(b",\"link\":")?;
+        write_string(out, link)?;
+        out.write_all(b"}\n")
+    }
+}
+
+// A JSON string, or `null` for a null pointer.
+fn write_string<W: Write>(out: &mut W, ptr: *const c_char) -> io::Result<()> {
+    if ptr.is_null() {
+        return out.write_all(b"null");
+    }
+    let value = unsafe { CStr::from_ptr(ptr) }.to_string_lossy();
+    out.write_all(b"\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    out.write_all(b"\"")
+}
@@ -1,5 +1,6 @@
 use std::error;
 use std::fmt;
+use std::io;
 
 pub type ArchiveResult<T> = Result<T, ArchiveError>;
 
@@ -12,19 +13,99 @@ impl fmt::Display for ErrCode {
     }
 }
 
+// A configured limit and its value.
+#[derive(Debug)]
+pub enum Limit {
+    TotalSize(u64),
+    EntrySize(u64),
+    PathnameLength(usize),
+    LinknameLength(usize),
+    Xattrs(usize),
+    CompressionRatio(u64),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Limit::TotalSize(max) => write!(fmt, "total size of {} bytes", max),
+            &Limit::EntrySize(max) => write!(fmt, "entry size of {} bytes", max),
+            &Limit::PathnameLength(max) => write!(fmt, "pathname length of {} bytes", max),
+            &Limit::LinknameLength(max) => write!(fmt, "link target length of {} bytes", max),
+            &Limit::Xattrs(max) => write!(fmt, "{} extended attributes", max),
+            &Limit::CompressionRatio(max) => write!(fmt, "compression ratio of {}:1", max),
+        }
+    }
+}
+
+// A problem libarchive recovered from, e.g. an unknown pax attribute or a header field it had to
+// guess, collected by `ReaderHandle::take_warnings`.
+#[derive(Debug, Clone)]
+pub struct ArchiveWarning {
+    // The errno libarchive reported, if any.
+    pub code: i32,
+    pub message: String,
+    // Entry the warning is about, unless it concerns the archive as a whole.
+    pub pathname: Option<String>,
+}
+
+impl fmt::Display for ArchiveWarning {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.pathname {
+            Some(ref pathname) => write!(fmt, "{}: {}", pathname, self.message),
+            None => write!(fmt, "{}", self.message),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ArchiveError {
+    // A callback stopped the operation at the named entry.
+    Aborted(String),
+    // Entry data doesn't match the checksum stored in the archive.
+    ChecksumMismatch {
+        path: String,
+    },
     Consumed,
     HeaderPosition,
+    Io(io::Error),
+    LimitExceeded(Limit),
+    Locale(String),
+    // Stopped through the token given to `reader::Builder::cancel_token`.
+    Cancelled,
+    // Several operations failed, e.g. enabling a list of formats.
+    Multiple(Vec<ArchiveError>),
+    // The reader's source can't be read again, see `ReaderHandle::rewind`.
+    NotRewindable,
+    // An `ArchiveEntry` was used after its iterator moved on to the next entry.
+    StaleEntry,
     Sys(ErrCode, String),
+    Unsupported(&'static str, i32),
+    // The archive is not of the format given to `reader::Builder::expect_format`. `name` is what
+    // libarchive calls the format found.
+    WrongFormat {
+        expected: i32,
+        found: i32,
+        name: String,
+    },
 }
 
 impl error::Error for ArchiveError {
     fn description(&self) -> &str {
         match self {
+            &ArchiveError::Aborted(_) => "Aborted by callback",
+            &ArchiveError::Cancelled => "Cancelled",
+            &ArchiveError::ChecksumMismatch { .. } => "Checksum mismatch",
             &ArchiveError::Consumed => "Builder already consumed",
             &ArchiveError::HeaderPosition => "Header position expected to be 0",
+            &ArchiveError::Io(_) => "I/O error",
+            &ArchiveError::LimitExceeded(_) => "Limit exceeded",
+            &ArchiveError::Locale(_) => "Locale not available",
+            &ArchiveError::Multiple(_) => "Multiple errors",
+            &ArchiveError::NotRewindable => "Reader cannot be rewound",
+            &ArchiveError::StaleEntry => "Entry no longer current",
             &ArchiveError::Sys(_, _) => "libarchive system error",
+            &ArchiveError::Unsupported(_, _) => "Not supported by the linked libarchive",
+            &ArchiveError::WrongFormat { .. } => "Unexpected archive format",
         }
     }
 }
@@ -32,18 +113,61 @@ impl error::Error for ArchiveError {
 impl fmt::Display for ArchiveError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            &ArchiveError::Aborted(ref pathname) => write!(fmt, "Aborted at {}", pathname),
+            &ArchiveError::ChecksumMismatch { ref path } => {
+                write!(fmt, "Checksum mismatch in {}", path)
+            }
+            &ArchiveError::Cancelled => write!(fmt, "Cancelled"),
             &ArchiveError::Consumed => write!(fmt, "Builder already consumed"),
             &ArchiveError::HeaderPosition => write!(fmt, "Header position expected to be 0"),
+            &ArchiveError::Io(ref err) => write!(fmt, "{}", err),
+            &ArchiveError::LimitExceeded(ref limit) => write!(fmt, "Exceeded {}", limit),
+            &ArchiveError::Locale(ref locale) => write!(fmt, "Locale {} not available", locale),
+            &ArchiveError::Multiple(ref errors) => {
+                let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+                write!(fmt, "{}", messages.join("; "))
+            }
+            &ArchiveError::NotRewindable => write!(fmt, "Reader cannot be rewound"),
+            &ArchiveError::StaleEntry => write!(
+                fmt,
+                "ArchiveEntry can only be used on current iterator item"
+            ),
             &ArchiveError::Sys(ref code, ref msg) => {
                 write!(fmt, "{} (libarchive err_code={})", msg, code)
             }
+            &ArchiveError::Unsupported(api, required) => write!(
+                fmt,
+                "{} requires libarchive {} (linked {})",
+                api,
+                crate::archive::format_version(required),
+                crate::archive::version_string()
+            ),
+            &ArchiveError::WrongFormat {
+                expected,
+                found,
+                ref name,
+            } => write!(
+                fmt,
+                "Archive is {} (format {:#x}), expected format {:#x}",
+                name, found, expected
+            ),
         }
     }
 }
 
+impl From<io::Error> for ArchiveError {
+    fn from(err: io::Error) -> ArchiveError {
+        ArchiveError::Io(err)
+    }
+}
+
 impl<'a> From<&'a dyn crate::archive::Handle> for ArchiveError {
     fn from(handle: &'a dyn crate::archive::Handle) -> ArchiveError {
-        ArchiveError::Sys(handle.err_code(), handle.err_msg())
+        match handle.err_code() {
+            // Set by the read callbacks once a cancel token is set.
+            ErrCode(libc::ECANCELED) => ArchiveError::Cancelled,
+            code => ArchiveError::Sys(code, handle.err_msg()),
+        }
     }
 }
 
@@ -1,7 +1,41 @@
 extern crate libarchive3_sys;
 extern crate libc;
 
+#[macro_use]
+mod trace;
+
+mod acl;
+#[cfg(feature = "tokio")]
+mod async_source;
+mod audit;
+mod copy;
+mod dump;
+mod listing;
+#[cfg(feature = "manifest")]
+mod manifest;
+mod pool;
+mod probe;
+mod program;
+mod rpm;
+mod simple;
+#[cfg(feature = "futures")]
+mod stream;
+
 pub mod archive;
 pub mod error;
+pub mod paths;
 pub mod reader;
 pub mod writer;
+
+pub use crate::acl::{AclEntry, AclEntryType, AclPerms, AclTag, AclType};
+pub use crate::audit::{AuditIssue, Finding};
+pub use crate::listing::Listing;
+pub use crate::pool::BufferPool;
+pub use crate::probe::{
+    detect_mime, detect_mime_stream, probe, probe_stream, ArchiveInfo, FilterInfo, Mime,
+};
+pub use crate::program::FilterProgram;
+pub use crate::rpm::{open_rpm, open_rpm_stream};
+pub use crate::simple::Archive;
+#[cfg(feature = "futures")]
+pub use crate::stream::ArchiveEntryStream;
@@ -0,0 +1,144 @@
+use std::ffi::CStr;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use libarchive3_sys::ffi;
+use libc::c_char;
+
+const HALF_YEAR: i64 = 365 * 24 * 60 * 60 / 2;
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// An entry rendered as a line of `bsdtar -tv` output: mode, link count, owner, group, size (or
+// device numbers), modification time, name and link target. Times are shown in UTC.
+#[derive(Debug, Clone)]
+pub struct Listing {
+    mode: String,
+    nlink: u32,
+    owner: String,
+    group: String,
+    size: String,
+    mtime: i64,
+    pathname: String,
+    hardlink: Option<String>,
+    symlink: Option<String>,
+}
+
+impl Listing {
+    pub(crate) fn from_entry(entry: *mut ffi::Struct_archive_entry) -> Listing {
+        unsafe {
+            let owner = lossy(ffi::archive_entry_uname(entry))
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| ffi::archive_entry_uid(entry).to_string());
+            let group = lossy(ffi::archive_entry_gname(entry))
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| ffi::archive_entry_gid(entry).to_string());
+            let size = match ffi::archive_entry_filetype(entry) as u32 {
+                ffi::AE_IFCHR | ffi::AE_IFBLK => format!(
+                    "{},{}",
+                    ffi::archive_entry_rdevmajor(entry),
+                    ffi::archive_entry_rdevminor(entry)
+                ),
+                _ => ffi::archive_entry_size(entry).to_string(),
+            };
+            Listing {
+                mode: lossy(ffi::archive_entry_strmode(entry)).unwrap_or_default(),
+                nlink: ffi::archive_entry_nlink(entry) as u32,
+                owner,
+                group,
+                size,
+                mtime: ffi::archive_entry_mtime(entry) as i64,
+                pathname: lossy(ffi::archive_entry_pathname(entry)).unwrap_or_default(),
+                hardlink: lossy(ffi::archive_entry_hardlink(entry)),
+                symlink: lossy(ffi::archive_entry_symlink(entry)),
+            }
+        }
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        // `strmode` leaves a trailing column for an ACL marker.
+        write!(fmt, "{} {} ", self.mode, self.nlink)?;
+        write!(fmt, "{:<6} ", self.owner)?;
+        // Group and size share a column, growing when they don't fit.
+        let width = 13.max(self.group.len() + self.size.len() + 1);
+        write!(
+            fmt,
+            "{}{:>2$}",
+            self.group,
+            self.size,
+            width - self.group.len()
+        )?;
+        write!(fmt, " {} {}", Date(self.mtime, now), self.pathname)?;
+        if let Some(ref target) = self.hardlink {
+            write!(fmt, " link to {}", target)?;
+        } else if let Some(ref target) = self.symlink {
+            write!(fmt, " -> {}", target)?;
+        }
+        Ok(())
+    }
+}
+
+// `ls` style date: time of day for recent files, year for those more than half a year away.
+struct Date(i64, i64);
+
+impl fmt::Display for Date {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let Date(time, now) = *self;
+        let (year, month, day) = civil_from_days(time.div_euclid(86400));
+        let month = MONTHS[month as usize - 1];
+        if time < now - HALF_YEAR || time > now + HALF_YEAR {
+            write!(fmt, "{} {:>2}  {}", month, day, year)
+        } else {
+            let seconds = time.rem_euclid(86400);
+            write!(
+                fmt,
+                "{} {:>2} {:02}:{:02}",
+                month,
+                day,
+                seconds / 3600,
+                seconds % 3600 / 60
+            )
+        }
+    }
+}
+
+// The entry mode as `ls -l` shows it, e.g. `-rw-r--r--`, with a trailing `+` when the entry has
+// an ACL.
+pub(crate) fn mode_string(entry: *mut ffi::Struct_archive_entry) -> String {
+    let mode = lossy(unsafe { ffi::archive_entry_strmode(entry) }).unwrap_or_default();
+    mode.trim_end().to_string()
+}
+
+// Gregorian date of a day count since 1970-01-01, after Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// A C string from libarchive, converting names that aren't valid UTF-8 lossily.
+pub(crate) fn lossy(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(
+            unsafe { CStr::from_ptr(ptr) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+// Accumulates a `sha256sum` compatible manifest of extracted files, one entry at a time.
+pub(crate) struct Manifest {
+    out: Box<dyn Write>,
+    current: Option<(String, Sha256, u64)>,
+}
+
+impl Manifest {
+    pub(crate) fn new(out: Box<dyn Write>) -> Manifest {
+        Manifest { out, current: None }
+    }
+
+    // Opens the manifest at `path` for appending, creating it if needed, and returns it along with
+    // the paths it already lists. A last line left unfinished by a crash is cut off, as the path
+    // on it may be truncated.
+    pub(crate) fn resume(path: &Path) -> io::Result<(Manifest, HashSet<String>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        let complete = contents.rfind('\n').map_or(0, |i| i + 1);
+        if complete < contents.len() {
+            file.set_len(complete as u64)?;
+        }
+        file.seek(SeekFrom::Start(complete as u64))?;
+        let paths = contents[..complete]
+            .lines()
+            .filter_map(parse_line)
+            .collect();
+        // Lines go straight to the file, so each finished entry is recorded even if the process
+        // dies right after.
+        Ok((Manifest::new(Box::new(file)), paths))
+    }
+
+    // Starts hashing a file, abandoning any entry that was never finished.
+    pub(crate) fn start(&mut self, pathname: &str) {
+        self.current = Some((pathname.to_string(), Sha256::new(), 0));
+    }
+
+    // Feeds a data block read at `offset`. Holes skipped by sparse entries hash as zeros.
+    pub(crate) fn update(&mut self, offset: u64, data: &[u8]) {
+        if let Some((_, ref mut hasher, ref mut len)) = self.current {
+            zero_fill(hasher, len, offset);
+            hasher.update(data);
+            *len += data.len() as u64;
+        }
+    }
+
+    // Writes the line for the current entry, which is `size` bytes long.
+    pub(crate) fn finish(&mut self, size: u64) -> io::Result<()> {
+        let (pathname, mut hasher, mut len) = match self.current.take() {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+        zero_fill(&mut hasher, &mut len, size);
+        let hex: String = hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        // Like GNU coreutils, names containing a backslash or newline are escaped and the line is
+        // marked with a leading backslash.
+        if pathname.contains(['\\', '\n']) {
+            let escaped = pathname.replace('\\', "\\\\").replace('\n', "\\n");
+            writeln!(self.out, "\\{}  {}", hex, escaped)
+        } else {
+            writeln!(self.out, "{}  {}", hex, pathname)
+        }
+    }
+
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// Path of a `sha256sum` line, undoing the escaping `finish` applies.
+fn parse_line(line: &str) -> Option<String> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+    let (_, pathname) = line.split_once("  ")?;
+    if !escaped {
+        return Some(pathname.to_string());
+    }
+    let mut unescaped = String::with_capacity(pathname.len());
+    let mut chars = pathname.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => unescaped.push('\n'),
+                Some(c) => unescaped.push(c),
+                None => return None,
+            },
+            c => unescaped.push(c),
+        }
+    }
+    Some(unescaped)
+}
+
+fn zero_fill(hasher: &mut Sha256, len: &mut u64, until: u64) {
+    const ZEROS: [u8; 4096] = [0; 4096];
+    while *len < until {
+        let n = (until - *len).min(ZEROS.len() as u64);
+        hasher.update(&ZEROS[..n as usize]);
+        *len += n;
+    }
+}
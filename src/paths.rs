@@ -0,0 +1,83 @@
+// Lexical clean-up of entry pathnames taken from untrusted archives. The functions only look at
+// the string, except `is_safe` which also checks the filesystem below its root for symlinks.
+
+use std::path::Path;
+
+// Replaces Windows `\` separators with `/`.
+pub fn convert_separators(path: &str) -> String {
+    path.replace('\\', "/")
+}
+
+// Strips a leading drive letter (`C:`) and any leading `/`, making the path relative.
+pub fn strip_root(path: &str) -> &str {
+    let bytes = path.as_bytes();
+    let path = if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        &path[2..]
+    } else {
+        path
+    };
+    path.trim_start_matches('/')
+}
+
+// Resolves `.` and `..` components and drops empty ones. `..` never climbs above the start of the
+// path, so the result stays below wherever it is joined onto.
+pub fn collapse(path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => (),
+            ".." => {
+                components.pop();
+            }
+            _ => components.push(component),
+        }
+    }
+    components.join("/")
+}
+
+// Applies all of the above. The result is a relative path without `.` or `..` components, and is
+// empty when nothing is left (e.g. for `/` or `../..`).
+pub fn sanitize(path: &str) -> String {
+    collapse(strip_root(&convert_separators(path)))
+}
+
+// Whether `..` components take the path above where it starts.
+pub(crate) fn climbs(path: &str) -> bool {
+    let mut depth = 0usize;
+    for component in path.split('/') {
+        match component {
+            "" | "." => (),
+            ".." => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return true,
+            },
+            _ => depth += 1,
+        }
+    }
+    false
+}
+
+// Whether extracting `path` below `root` stays inside `root`: the path is relative, never climbs
+// above `root` through `..`, and none of its parent directories that already exist below `root`
+// is a symlink.
+pub fn is_safe<T: AsRef<Path>>(root: T, path: &str) -> bool {
+    let path = convert_separators(path);
+    if strip_root(&path).len() != path.len() {
+        return false;
+    }
+    if climbs(&path) {
+        return false;
+    }
+
+    let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+    let mut current = root.as_ref().to_path_buf();
+    for component in components.iter().take(components.len().saturating_sub(1)) {
+        current.push(component);
+        match current.symlink_metadata() {
+            Ok(metadata) if metadata.file_type().is_symlink() => return false,
+            Ok(_) => (),
+            Err(_) => break,
+        }
+    }
+    true
+}
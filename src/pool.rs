@@ -0,0 +1,39 @@
+use std::sync::{Arc, Mutex};
+
+// Buffers handed out for entry data read into memory and given back once the caller is done with
+// them, so processing many archives doesn't allocate a fresh buffer for every entry. Clones share
+// the same buffers and may be used from any thread.
+#[derive(Debug, Clone)]
+pub struct BufferPool {
+    buffers: Arc<Mutex<Vec<Vec<u8>>>>,
+    max_buffers: usize,
+}
+
+impl BufferPool {
+    // A pool keeping at most `max_buffers` buffers for reuse.
+    pub fn new(max_buffers: usize) -> Self {
+        BufferPool {
+            buffers: Arc::new(Mutex::new(Vec::new())),
+            max_buffers,
+        }
+    }
+
+    // An empty buffer, with the capacity of an earlier one if any was given back.
+    pub fn take(&self) -> Vec<u8> {
+        self.buffers.lock().unwrap().pop().unwrap_or_default()
+    }
+
+    // Gives a buffer back for reuse. It is dropped if the pool is full.
+    pub fn put(&self, mut buffer: Vec<u8>) {
+        buffer.clear();
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < self.max_buffers {
+            buffers.push(buffer);
+        }
+    }
+
+    // Number of buffers waiting to be reused.
+    pub fn available(&self) -> usize {
+        self.buffers.lock().unwrap().len()
+    }
+}
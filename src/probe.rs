@@ -0,0 +1,144 @@
+use std::io::Read;
+use std::path::Path;
+use std::ptr;
+
+use libarchive3_sys::ffi;
+
+use crate::archive::Handle;
+use crate::error::{ArchiveError, ArchiveResult};
+use crate::reader::{c_string, Builder, ReaderHandle};
+
+// Format and filter chain of an archive, as detected by libarchive.
+#[derive(Debug, Clone)]
+pub struct ArchiveInfo {
+    // One of the `ARCHIVE_FORMAT_*` codes, e.g. `ARCHIVE_FORMAT_TAR_USTAR`.
+    pub format: i32,
+    pub format_name: String,
+    // Filters wrapping the archive, outermost first. Uncompressed archives have none.
+    pub filters: Vec<FilterInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FilterInfo {
+    // One of the `ARCHIVE_FILTER_*` codes, e.g. `ARCHIVE_FILTER_XZ`.
+    pub code: i32,
+    pub name: String,
+}
+
+// MIME type of an archive, split the way HTTP labels compressed content: `content_type` names the
+// archive format and `content_encoding` the compression applied on top of it, in the order
+// applied. A `.tar.gz` is `application/x-tar` with encoding `gzip`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mime {
+    pub content_type: &'static str,
+    pub content_encoding: Vec<&'static str>,
+}
+
+impl ArchiveInfo {
+    // Maps the detected format and filters to a MIME type. Combinations without a registered or
+    // widely used type are reported as `application/octet-stream`.
+    pub fn mime(&self) -> Mime {
+        let unknown = Mime {
+            content_type: "application/octet-stream",
+            content_encoding: Vec::new(),
+        };
+        let content_type = match format_mime(self.format) {
+            Some(content_type) => content_type,
+            None => return unknown,
+        };
+        // Filters are listed outermost first, encodings are listed in the order applied.
+        let mut content_encoding = Vec::new();
+        for filter in self.filters.iter().rev() {
+            match filter_encoding(filter.code) {
+                Some(encoding) => content_encoding.push(encoding),
+                None => return unknown,
+            }
+        }
+        Mime {
+            content_type,
+            content_encoding,
+        }
+    }
+}
+
+// MIME type of the archive at `path`. See `ArchiveInfo::mime`.
+pub fn detect_mime<T: AsRef<Path>>(path: T) -> ArchiveResult<Mime> {
+    Ok(probe(path)?.mime())
+}
+
+// MIME type of the archive read from `src`. See `ArchiveInfo::mime`.
+pub fn detect_mime_stream<T: Read + Send>(src: T) -> ArchiveResult<Mime> {
+    Ok(probe_stream(src)?.mime())
+}
+
+fn format_mime(format: i32) -> Option<&'static str> {
+    match format & ffi::ARCHIVE_FORMAT_BASE_MASK {
+        ffi::ARCHIVE_FORMAT_7ZIP => Some("application/x-7z-compressed"),
+        ffi::ARCHIVE_FORMAT_AR => Some("application/x-archive"),
+        ffi::ARCHIVE_FORMAT_CAB => Some("application/vnd.ms-cab-compressed"),
+        ffi::ARCHIVE_FORMAT_CPIO => Some("application/x-cpio"),
+        ffi::ARCHIVE_FORMAT_ISO9660 => Some("application/x-iso9660-image"),
+        ffi::ARCHIVE_FORMAT_LHA => Some("application/x-lzh-compressed"),
+        ffi::ARCHIVE_FORMAT_RAR | ffi::ARCHIVE_FORMAT_RAR_V5 => Some("application/vnd.rar"),
+        ffi::ARCHIVE_FORMAT_SHAR => Some("application/x-shar"),
+        ffi::ARCHIVE_FORMAT_TAR => Some("application/x-tar"),
+        ffi::ARCHIVE_FORMAT_WARC => Some("application/warc"),
+        ffi::ARCHIVE_FORMAT_XAR => Some("application/x-xar"),
+        ffi::ARCHIVE_FORMAT_ZIP => Some("application/zip"),
+        _ => None,
+    }
+}
+
+// Registered HTTP content codings where they exist, the customary `x-` names otherwise.
+fn filter_encoding(code: i32) -> Option<&'static str> {
+    match code {
+        ffi::ARCHIVE_FILTER_BZIP2 => Some("x-bzip2"),
+        ffi::ARCHIVE_FILTER_COMPRESS => Some("compress"),
+        ffi::ARCHIVE_FILTER_GZIP => Some("gzip"),
+        ffi::ARCHIVE_FILTER_LZ4 => Some("x-lz4"),
+        ffi::ARCHIVE_FILTER_LZIP => Some("x-lzip"),
+        ffi::ARCHIVE_FILTER_LZMA => Some("x-lzma"),
+        ffi::ARCHIVE_FILTER_XZ => Some("x-xz"),
+        ffi::ARCHIVE_FILTER_ZSTD => Some("zstd"),
+        _ => None,
+    }
+}
+
+// Identifies the archive at `path` by reading only up to its first header.
+pub fn probe<T: AsRef<Path>>(path: T) -> ArchiveResult<ArchiveInfo> {
+    probe_reader(Builder::new().support_all()?.open_file(path)?)
+}
+
+// Identifies the archive read from `src` by consuming only up to its first header.
+pub fn probe_stream<T: Read + Send>(src: T) -> ArchiveResult<ArchiveInfo> {
+    probe_reader(Builder::new().support_all()?.open_stream(src)?)
+}
+
+fn probe_reader(reader: ReaderHandle<'_>) -> ArchiveResult<ArchiveInfo> {
+    unsafe {
+        let handle = reader.handle();
+        // Format detection happens while reading the first header. An archive without entries
+        // still reports its format at EOF.
+        let mut entry = ptr::null_mut();
+        match ffi::archive_read_next_header(handle, &mut entry) {
+            ffi::ARCHIVE_OK | ffi::ARCHIVE_WARN | ffi::ARCHIVE_EOF => (),
+            _ => return Err(ArchiveError::from(&reader as &dyn Handle)),
+        }
+
+        // Filter 0 is the one closest to the format, the last is the raw input.
+        let filters = (0..ffi::archive_filter_count(handle))
+            .rev()
+            .filter(|&i| ffi::archive_filter_code(handle, i) != ffi::ARCHIVE_FILTER_NONE)
+            .map(|i| FilterInfo {
+                code: ffi::archive_filter_code(handle, i),
+                name: c_string(ffi::archive_filter_name(handle, i)).unwrap_or_default(),
+            })
+            .collect();
+
+        Ok(ArchiveInfo {
+            format: ffi::archive_format(handle),
+            format_name: c_string(ffi::archive_format_name(handle)).unwrap_or_default(),
+            filters,
+        })
+    }
+}
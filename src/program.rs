@@ -0,0 +1,195 @@
+// External filter programs run and supervised by the crate. `ReadFilter::Program` and
+// `WriteFilter::Program` leave the child process to libarchive, which waits on it without a time
+// limit and only warns about a failed exit. Here the child is a pipe stage in front of (or behind)
+// libarchive: it is killed when it stalls past a timeout or when the reader or writer is dropped,
+// and exiting unsuccessfully fails the read or `Writer::close`.
+
+use std::io::{self, Read};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A program and its arguments, e.g. `FilterProgram::new("zstd").arg("-dc")`. It reads the data to
+// filter from stdin and writes the result to stdout.
+#[derive(Debug, Clone)]
+pub struct FilterProgram {
+    program: String,
+    args: Vec<String>,
+    timeout: Option<Duration>,
+}
+
+impl FilterProgram {
+    pub fn new(program: &str) -> Self {
+        FilterProgram {
+            program: program.to_string(),
+            args: Vec::new(),
+            timeout: None,
+        }
+    }
+
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    // Longest the program may go without producing output while reading, or take to exit once
+    // its input has been closed while writing. It is killed then and the operation fails with
+    // `io::ErrorKind::TimedOut`. Without a timeout it may take as long as it likes.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    fn spawn(&self, stdin: Stdio, stdout: Stdio) -> io::Result<Child> {
+        Command::new(&self.program)
+            .args(&self.args)
+            .stdin(stdin)
+            .stdout(stdout)
+            .spawn()
+    }
+
+    // Waits for the program to exit, killing it if that takes longer than the timeout.
+    fn wait(&self, child: &mut Child) -> io::Result<()> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if matches!(deadline, Some(deadline) if Instant::now() >= deadline) {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(self.timed_out());
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        if status.success() {
+            Ok(())
+        } else {
+            let msg = format!("filter program `{}` failed: {}", self.program, status);
+            Err(io::Error::new(io::ErrorKind::Other, msg))
+        }
+    }
+
+    fn timed_out(&self) -> io::Error {
+        let msg = format!("filter program `{}` timed out", self.program);
+        io::Error::new(io::ErrorKind::TimedOut, msg)
+    }
+}
+
+// Output of a program fed from `stdin`, read by a helper thread so reads can time out.
+pub(crate) struct ProgramOutput {
+    program: FilterProgram,
+    child: Child,
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl ProgramOutput {
+    pub(crate) fn spawn(program: &FilterProgram, stdin: Stdio) -> io::Result<Self> {
+        let mut child = program.spawn(stdin, Stdio::piped())?;
+        let mut stdout = child.stdout.take().unwrap();
+        let (sender, chunks) = mpsc::sync_channel(4);
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; 64 * 1024];
+            let result = stdout.read(&mut chunk).map(|size| {
+                chunk.truncate(size);
+                chunk
+            });
+            let stop = !matches!(result, Ok(ref chunk) if !chunk.is_empty());
+            if sender.send(result).is_err() || stop {
+                return;
+            }
+        });
+        Ok(ProgramOutput {
+            program: program.clone(),
+            child,
+            chunks,
+            chunk: Vec::new(),
+            pos: 0,
+            done: false,
+        })
+    }
+}
+
+impl Read for ProgramOutput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            if self.done {
+                return Ok(0);
+            }
+            let chunk = match self.program.timeout {
+                Some(timeout) => match self.chunks.recv_timeout(timeout) {
+                    Ok(chunk) => chunk,
+                    Err(RecvTimeoutError::Timeout) => {
+                        let _ = self.child.kill();
+                        self.done = true;
+                        return Err(self.program.timed_out());
+                    }
+                    Err(RecvTimeoutError::Disconnected) => Ok(Vec::new()),
+                },
+                None => self.chunks.recv().unwrap_or_else(|_| Ok(Vec::new())),
+            }?;
+            if chunk.is_empty() {
+                // The output ends when the program exits, which has to be successful.
+                self.done = true;
+                self.program.wait(&mut self.child)?;
+            }
+            self.chunk = chunk;
+            self.pos = 0;
+        }
+        let size = buf.len().min(self.chunk.len() - self.pos);
+        buf[..size].copy_from_slice(&self.chunk[self.pos..self.pos + size]);
+        self.pos += size;
+        Ok(size)
+    }
+}
+
+impl Drop for ProgramOutput {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// A program writing to `stdout`, fed by libarchive through the file descriptor of its stdin.
+pub(crate) struct ProgramInput {
+    program: FilterProgram,
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+
+impl ProgramInput {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) fn spawn(program: &FilterProgram, stdout: Stdio) -> io::Result<Self> {
+        let mut child = program.spawn(Stdio::piped(), stdout)?;
+        let stdin = child.stdin.take();
+        Ok(ProgramInput {
+            program: program.clone(),
+            child,
+            stdin,
+        })
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn fd(&self) -> std::os::unix::io::RawFd {
+        use std::os::unix::io::AsRawFd;
+        self.stdin.as_ref().unwrap().as_raw_fd()
+    }
+
+    // Closes the program's input and waits for it to exit.
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        self.stdin.take();
+        self.program.wait(&mut self.child)
+    }
+}
+
+impl Drop for ProgramInput {
+    fn drop(&mut self) {
+        self.stdin.take();
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
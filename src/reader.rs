@@ -1,21 +1,54 @@
-use std::any::Any;
 use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::default::Default;
-use std::ffi::CString;
-use std::io::{self, Read};
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
 use std::mem;
-use std::path::Path;
+use std::ops::{BitOr, Deref};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
 use std::ptr;
 use std::rc::Rc;
 use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
 
+#[cfg(feature = "bytes")]
+use bytes::Bytes;
 use libarchive3_sys::ffi::{self};
-use libc::{c_void, ssize_t};
+use libc::{c_char, c_int, c_void, ssize_t};
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
 
-use crate::archive::{Entry, Handle, ReadCompression, ReadFilter, ReadFormat};
-use crate::error::{ArchiveError, ArchiveResult};
+use crate::archive::{
+    entry_time, option_string, require_version, ArchiveSource, DumpFormat, Entry, EntryTarget,
+    ExtractOptions, Handle, ReadCompression, ReadFilter, ReadFormat, ReadOption,
+};
+#[cfg(feature = "tokio")]
+use crate::async_source::AsyncSource;
+use crate::audit::{AuditIssue, Auditor};
+use crate::dump;
+use crate::error::{ArchiveError, ArchiveResult, ArchiveWarning, Limit};
+use crate::listing::{self, Listing};
+use crate::pool::BufferPool;
+use crate::program::{FilterProgram, ProgramOutput};
+
+pub mod raw;
 
 const BLOCK_SIZE: usize = 10240;
+const STREAM_BUFFER_SIZE: usize = 8192;
+// Decompressed bytes before `Builder::max_compression_ratio` applies, as small inputs such as a
+// run of zeros legitimately compress far better than whole archives.
+const RATIO_MIN_BYTES: u64 = 1 << 20;
+// Decompressed bytes between calls to the `Builder::on_progress` callback unless configured with
+// `Builder::progress_interval`.
+const PROGRESS_INTERVAL: u64 = 1 << 20;
+// Most `ArchiveEntry::read_to_vec` allocates up front on the word of an entry's declared size.
+const PREALLOCATE_LIMIT: u64 = 16 << 20;
 
 unsafe extern "C" fn stream_read_callback(
     handle: *mut ffi::Struct_archive,
@@ -23,9 +56,16 @@ unsafe extern "C" fn stream_read_callback(
     buff: *mut *const c_void,
 ) -> ssize_t {
     let pipe: &mut Pipe = &mut *(data as *mut Pipe);
-    *buff = pipe.buffer.as_mut_ptr() as *mut c_void;
+    if is_cancelled(&pipe.cancel) {
+        let desc = CString::new("Cancelled").unwrap();
+        ffi::archive_set_error(handle, libc::ECANCELED, desc.as_ptr());
+        return -1;
+    }
     match pipe.read_bytes() {
-        Ok(size) => size as ssize_t,
+        Ok(bytes) => {
+            *buff = bytes.as_ptr() as *const c_void;
+            bytes.len() as ssize_t
+        }
         Err(e) => {
             let desc = CString::new(e.to_string()).unwrap();
             ffi::archive_set_error(handle, e.raw_os_error().unwrap_or(0), desc.as_ptr());
@@ -34,6 +74,91 @@ unsafe extern "C" fn stream_read_callback(
     }
 }
 
+unsafe extern "C" fn stream_skip_callback(
+    handle: *mut ffi::Struct_archive,
+    data: *mut c_void,
+    request: i64,
+) -> i64 {
+    let pipe: &mut Pipe = &mut *(data as *mut Pipe);
+    match pipe.seek(SeekFrom::Current(request)) {
+        Ok(_) => request,
+        Err(e) => {
+            let desc = CString::new(e.to_string()).unwrap();
+            ffi::archive_set_error(handle, e.raw_os_error().unwrap_or(0), desc.as_ptr());
+            ffi::ARCHIVE_FATAL as i64
+        }
+    }
+}
+
+unsafe extern "C" fn stream_seek_callback(
+    handle: *mut ffi::Struct_archive,
+    data: *mut c_void,
+    offset: i64,
+    whence: c_int,
+) -> i64 {
+    let pipe: &mut Pipe = &mut *(data as *mut Pipe);
+    let pos = match whence {
+        libc::SEEK_SET => SeekFrom::Start(offset as u64),
+        libc::SEEK_CUR => SeekFrom::Current(offset),
+        _ => SeekFrom::End(offset),
+    };
+    match pipe.seek(pos) {
+        Ok(pos) => pos as i64,
+        Err(e) => {
+            let desc = CString::new(e.to_string()).unwrap();
+            ffi::archive_set_error(handle, e.raw_os_error().unwrap_or(0), desc.as_ptr());
+            ffi::ARCHIVE_FATAL as i64
+        }
+    }
+}
+
+unsafe extern "C" fn passphrase_callback(
+    _: *mut ffi::Struct_archive,
+    data: *mut c_void,
+) -> *const c_char {
+    let passphrase: &mut PassphraseCallback = &mut *(data as *mut PassphraseCallback);
+    // A passphrase with a NUL byte can't be passed on, and can't be right either.
+    passphrase.current = (passphrase.callback)().and_then(|p| CString::new(p).ok());
+    match passphrase.current {
+        Some(ref p) => p.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+fn is_cancelled(token: &Option<Arc<AtomicBool>>) -> bool {
+    token
+        .as_ref()
+        .is_some_and(|token| token.load(Ordering::Relaxed))
+}
+
+// Lossy pathname of an entry for diagnostics, which must not fail on names the current locale
+// cannot represent.
+fn entry_pathname(entry: *mut ffi::Struct_archive_entry) -> Option<String> {
+    unsafe {
+        let ptr = ffi::archive_entry_pathname(entry);
+        if ptr.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(ptr).to_string_lossy().into_owned())
+        }
+    }
+}
+
+// Error for a failed read of the data of `entry`. The zip, 7-Zip, RAR, LHA and CAB readers all
+// mention the CRC when data doesn't match its stored checksum, which gets a variant of its own.
+fn data_error(handle: &dyn Handle, entry: *mut ffi::Struct_archive_entry) -> ArchiveError {
+    let msg = handle.err_msg();
+    if handle.err_code().0 == libc::ECANCELED {
+        ArchiveError::Cancelled
+    } else if msg.contains("CRC") {
+        ArchiveError::ChecksumMismatch {
+            path: entry_pathname(entry).unwrap_or_default(),
+        }
+    } else {
+        ArchiveError::Sys(handle.err_code(), msg)
+    }
+}
+
 pub trait Reader: Handle + Sized {
     fn entry(&mut self) -> &mut ReaderEntryHandle;
 
@@ -52,16 +177,56 @@ pub trait Reader: Handle + Sized {
     }
 }
 
-pub struct ArchiveIterator {
-    reader: Rc<ReaderHandle>,
+pub struct ArchiveIterator<'a> {
+    reader: Rc<ReaderHandle<'a>>,
     entry: *mut ffi::Struct_archive_entry,
     current: std::rc::Rc<std::cell::Cell<Option<usize>>>,
+    done: bool,
 }
 
-impl Iterator for ArchiveIterator {
-    type Item = ArchiveResult<ArchiveEntry>;
+impl<'a> ArchiveIterator<'a> {
+    // The reader being iterated, for querying archive-wide state such as the detected format.
+    pub fn reader(&self) -> &ReaderHandle<'a> {
+        &self.reader
+    }
+
+    // Skips the next `n` entries without handing them out, skipping rather than decompressing
+    // their data where the format allows. Returns how many were skipped, fewer than `n` when the
+    // archive ends first. Errors are those `next` would have returned.
+    pub fn skip_entries(&mut self, n: usize) -> ArchiveResult<usize> {
+        for skipped in 0..n {
+            if self.done {
+                return Ok(skipped);
+            }
+            let current = self.current.get().map_or(0, |v| v + 1);
+            self.current.set(Some(current));
+            match unsafe { ffi::archive_read_next_header(self.reader.handle, &mut self.entry) } {
+                ffi::ARCHIVE_OK => self.reader.check_header(self.entry)?,
+                ffi::ARCHIVE_WARN => {
+                    self.reader.warn(self.entry);
+                    self.reader.check_header(self.entry)?
+                }
+                ffi::ARCHIVE_EOF => {
+                    self.done = true;
+                    return Ok(skipped);
+                }
+                res => {
+                    self.done = res == ffi::ARCHIVE_FATAL;
+                    return Err(ArchiveError::from(self.reader.as_ref() as &dyn Handle));
+                }
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl<'a> Iterator for ArchiveIterator<'a> {
+    type Item = ArchiveResult<ArchiveEntry<'a>>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
         unsafe {
             let current = match self.current.get() {
                 Some(v) => v + 1,
@@ -69,78 +234,816 @@ impl Iterator for ArchiveIterator {
             };
             self.current.set(Some(current));
 
+            let _span = span!("next_header", index = current);
             match ffi::archive_read_next_header(self.reader.handle, &mut self.entry) {
-                ffi::ARCHIVE_OK => Some(Ok(ArchiveEntry::new(
-                    self.reader.clone(),
-                    self.entry,
-                    self.current.clone(),
-                    current,
-                ))),
-                ffi::ARCHIVE_EOF => None,
-                _ => Some(Err(ArchiveError::from(self.reader.as_ref() as &dyn Handle))),
+                res @ (ffi::ARCHIVE_OK | ffi::ARCHIVE_WARN) => {
+                    if res == ffi::ARCHIVE_WARN {
+                        self.reader.warn(self.entry);
+                    }
+                    event!(DEBUG, pathname = ?entry_pathname(self.entry), "read header");
+                    if let Err(err) = self.reader.check_header(self.entry) {
+                        event!(ERROR, pathname = ?entry_pathname(self.entry), error = %err, "header rejected");
+                        return Some(Err(err));
+                    }
+                    Some(Ok(ArchiveEntry::new(
+                        self.reader.clone(),
+                        self.entry,
+                        self.current.clone(),
+                        current,
+                    )))
+                }
+                ffi::ARCHIVE_EOF => {
+                    self.done = true;
+                    None
+                }
+                res => {
+                    // Errors reading an entry's data leave the archive usable, so callers may
+                    // carry on with the next entry. A fatal error ends the iteration instead of
+                    // repeating forever.
+                    self.done = res == ffi::ARCHIVE_FATAL;
+                    let err = ArchiveError::from(self.reader.as_ref() as &dyn Handle);
+                    event!(ERROR, error = %err, "failed to read header");
+                    Some(Err(err))
+                }
             }
         }
     }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        match self.skip_entries(n) {
+            Ok(skipped) if skipped == n => self.next(),
+            Ok(_) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
 }
 
-pub struct ReaderHandle {
+pub struct ReaderHandle<'a> {
     handle: *mut ffi::Struct_archive,
     entry: ReaderEntryHandle,
-    _pipe: Option<Box<Pipe>>,
+    limits: HeaderLimits,
+    max_entry_size: Option<u64>,
+    max_total_size: Option<u64>,
+    max_ratio: Option<u64>,
+    cancel: Option<Arc<AtomicBool>>,
+    progress: Option<RefCell<ProgressCallback>>,
+    expected_format: Option<i32>,
+    // File the archive was opened from, if any.
+    source: Option<PathBuf>,
+    pool: Option<BufferPool>,
+    // Headers read so far, i.e. the index of the current entry plus one.
+    headers: usize,
+    _pipe: Option<Box<Pipe<'a>>>,
+    // Buffer libarchive reads from when opened with `Builder::open_memory`.
+    _memory: Option<Box<dyn AsRef<[u8]> + Send>>,
+    // Descriptor closed with the reader when opened with `Builder::open_owned_fd`.
+    #[cfg(unix)]
+    _fd: Option<OwnedFd>,
+    // Closure libarchive asks for passphrases, see `Builder::passphrase_callback`.
+    passphrase: Option<Box<PassphraseCallback>>,
+    // Set for readers `rewind` can open again.
+    reopen: Option<Reopen>,
+    // Index of the first entry with each pathname, see `build_index`.
+    index: Option<HashMap<String, usize>>,
+    warnings: RefCell<Vec<ArchiveWarning>>,
 }
 
-impl Handle for ReaderHandle {
+// Builder settings `ReaderHandle::rewind` applies when opening the archive again.
+struct Reopen {
+    header_charset: Option<String>,
+    verify_checksums: bool,
+    concatenated: bool,
+    options: Vec<ReaderOption>,
+    passphrases: Vec<CString>,
+    block_size: usize,
+}
+
+// The archive and entry objects are only ever used by one thread at a time, whichever owns the
+// handle, and libarchive keeps no thread-local state for them. Everything else the handle owns
+// that callbacks reach, the stream source, the buffer of `open_memory` and the passphrase and
+// progress closures, is required to be `Send` when handed to the `Builder`. Iterating shares the
+// handle between the iterator and its entries through an `Rc`, which keeps those on one thread.
+unsafe impl Send for ReaderHandle<'_> {}
+
+impl Handle for ReaderHandle<'_> {
     unsafe fn handle(&self) -> *mut ffi::Struct_archive {
         self.handle
     }
 }
 
-impl ReaderHandle {
-    fn new_file(handle: *mut ffi::Struct_archive) -> ReaderHandle {
+impl<'a> ReaderHandle<'a> {
+    fn new_file(handle: *mut ffi::Struct_archive, limits: HeaderLimits) -> ReaderHandle<'a> {
         Self {
             handle,
             entry: Default::default(),
+            limits,
+            max_entry_size: None,
+            max_total_size: None,
+            max_ratio: None,
+            cancel: None,
+            progress: None,
+            expected_format: None,
+            source: None,
+            pool: None,
+            headers: 0,
             _pipe: None,
+            _memory: None,
+            #[cfg(unix)]
+            _fd: None,
+            passphrase: None,
+            reopen: None,
+            index: None,
+            warnings: RefCell::new(Vec::new()),
         }
     }
 
-    fn new_stream(handle: *mut ffi::Struct_archive, pipe: Box<Pipe>) -> ReaderHandle {
+    fn new_stream(
+        handle: *mut ffi::Struct_archive,
+        pipe: Box<Pipe<'a>>,
+        limits: HeaderLimits,
+    ) -> ReaderHandle<'a> {
         Self {
             handle,
             entry: Default::default(),
+            limits,
+            max_entry_size: None,
+            max_total_size: None,
+            max_ratio: None,
+            cancel: None,
+            progress: None,
+            expected_format: None,
+            source: None,
+            pool: None,
+            headers: 0,
             _pipe: Some(pipe),
+            _memory: None,
+            #[cfg(unix)]
+            _fd: None,
+            passphrase: None,
+            reopen: None,
+            index: None,
+            warnings: RefCell::new(Vec::new()),
+        }
+    }
+
+    // Checks a header just read against `Builder::expect_format` and `Builder::limits`.
+    fn check_header(&self, entry: *mut ffi::Struct_archive_entry) -> ArchiveResult<()> {
+        if is_cancelled(&self.cancel) {
+            return Err(ArchiveError::Cancelled);
+        }
+        self.report_progress(true);
+        if let Some(expected) = self.expected_format {
+            let found = unsafe { ffi::archive_format(self.handle) };
+            // A base code, e.g. `ARCHIVE_FORMAT_TAR`, accepts any of its variants.
+            let matches = match expected & !ffi::ARCHIVE_FORMAT_BASE_MASK {
+                0 => found & ffi::ARCHIVE_FORMAT_BASE_MASK == expected,
+                _ => found == expected,
+            };
+            if !matches {
+                let name = unsafe { CStr::from_ptr(ffi::archive_format_name(self.handle)) };
+                return Err(ArchiveError::WrongFormat {
+                    expected,
+                    found,
+                    name: name.to_string_lossy().into_owned(),
+                });
+            }
+        }
+        self.limits.check(entry)
+    }
+
+    // Calls the `Builder::on_progress` callback for every header, and for data once another
+    // `Builder::progress_interval` bytes have been decompressed.
+    fn report_progress(&self, header: bool) {
+        let mut progress = match self.progress {
+            Some(ref progress) => progress.borrow_mut(),
+            None => return,
+        };
+        let uncompressed = self.bytes_read_uncompressed();
+        if !header && uncompressed < progress.reported + progress.interval {
+            return;
+        }
+        progress.reported = uncompressed;
+        (progress.callback)(Progress {
+            entries: unsafe { ffi::archive_file_count(self.handle) }.max(0) as usize,
+            bytes_read_raw: self.bytes_read_raw(),
+            bytes_read_uncompressed: uncompressed,
+        });
+    }
+
+    // Records the warning libarchive just returned, about `entry`.
+    fn warn(&self, entry: *mut ffi::Struct_archive_entry) {
+        self.warnings.borrow_mut().push(ArchiveWarning {
+            code: self.err_code().0,
+            message: self.err_msg(),
+            pathname: entry_pathname(entry),
+        });
+    }
+
+    // Problems libarchive recovered from while reading headers and data since the last call,
+    // e.g. unknown pax attributes or minor header damage. The entries concerned are read as
+    // usual.
+    pub fn take_warnings(&self) -> Vec<ArchiveWarning> {
+        self.warnings.take()
+    }
+
+    // Error for a failed read of the current entry's data, see `data_error`.
+    pub(crate) fn data_error(&self) -> ArchiveError {
+        data_error(self, self.entry.handle)
+    }
+
+    // Fails once the data of the current entry reaches past `end` bytes and that is more than the
+    // configured `Builder::max_entry_size`, or the archive as a whole exceeds
+    // `Builder::max_total_size` or `Builder::max_compression_ratio`. Also fails once the
+    // `Builder::cancel_token` is set, as it is checked after every block of data.
+    pub(crate) fn check_data(&self, end: u64) -> ArchiveResult<()> {
+        if is_cancelled(&self.cancel) {
+            return Err(ArchiveError::Cancelled);
+        }
+        self.report_progress(false);
+        if let Some(max) = self.max_entry_size {
+            if end > max {
+                return Err(ArchiveError::LimitExceeded(Limit::EntrySize(max)));
+            }
+        }
+        if self.max_total_size.is_none() && self.max_ratio.is_none() {
+            return Ok(());
+        }
+        let uncompressed = self.bytes_read_uncompressed();
+        if let Some(max) = self.max_total_size {
+            if uncompressed > max {
+                return Err(ArchiveError::LimitExceeded(Limit::TotalSize(max)));
+            }
+        }
+        if let Some(max) = self.max_ratio {
+            let raw = self.bytes_read_raw().max(1);
+            if uncompressed > RATIO_MIN_BYTES && uncompressed / raw > max {
+                return Err(ArchiveError::LimitExceeded(Limit::CompressionRatio(max)));
+            }
+        }
+        Ok(())
+    }
+
+    // Where the data of the current entry lies in the archive file, as (file, offset, length),
+    // when it is stored there byte for byte: uncompressed tar opened with `Builder::open_file`,
+    // a regular file that is neither sparse nor encrypted, and no data read yet.
+    pub(crate) fn stored_range(&self) -> Option<(&Path, u64, u64)> {
+        let source = self.source.as_deref()?;
+        let entry = self.entry.handle;
+        unsafe {
+            // Only the client filter reading the file, i.e. no decompression.
+            if ffi::archive_filter_count(self.handle) != 1
+                || ffi::archive_format(self.handle) & ffi::ARCHIVE_FORMAT_BASE_MASK
+                    != ffi::ARCHIVE_FORMAT_TAR
+                || ffi::archive_entry_filetype(entry) != ffi::AE_IFREG
+                || !ffi::archive_entry_hardlink(entry).is_null()
+                || ffi::archive_entry_sparse_count(entry) != 0
+                || ffi::archive_entry_is_encrypted(entry) != 0
+                || ffi::archive_entry_size(entry) <= 0
+            {
+                return None;
+            }
+            // Tar consumes the headers of an entry, and nothing of its data, when reading them.
+            let offset = ffi::archive_filter_bytes(self.handle, 0);
+            Some((source, offset as u64, ffi::archive_entry_size(entry) as u64))
         }
     }
 
+    // Closes the archive, waiting for any filter program reading it to exit and reporting errors
+    // from shutting down the filters. Reading fails afterwards. Dropping the reader closes it too,
+    // but without a way to report errors.
+    pub fn close(&mut self) -> ArchiveResult<()> {
+        unsafe {
+            match ffi::archive_read_close(self.handle) {
+                ffi::ARCHIVE_OK => Ok(()),
+                _ => ArchiveResult::from(self as &dyn Handle),
+            }
+        }
+    }
+
+    // Starts over at the first entry, e.g. for a second listing or extraction pass. Only readers
+    // opened from a file or from memory can, others fail with `ArchiveError::NotRewindable`. The
+    // archive is opened again with the format and filters detected the first time, and the same
+    // options. Until a header was read there is nothing to go back to and it does nothing.
+    pub fn rewind(&mut self) -> ArchiveResult<()> {
+        let reopen = self.reopen.as_ref().ok_or(ArchiveError::NotRewindable)?;
+        let format = unsafe { ffi::archive_format(self.handle) };
+        if format == 0 {
+            return Ok(());
+        }
+
+        let mut builder = Builder::new();
+        builder.header_charset = reopen.header_charset.clone();
+        builder.verify_checksums = reopen.verify_checksums;
+        builder.concatenated = reopen.concatenated;
+        builder.options = reopen.options.clone();
+        builder.passphrases = reopen.passphrases.clone();
+        if let Some(ref mut passphrase) = self.passphrase {
+            passphrase.register(builder.handle);
+        }
+        unsafe {
+            if ffi::archive_read_support_format_by_code(builder.handle, format) != ffi::ARCHIVE_OK {
+                return Err(ArchiveError::from(&builder as &dyn Handle));
+            }
+            // Innermost first, leaving out the last filter, which reads the source itself.
+            let count = ffi::archive_filter_count(self.handle);
+            for i in (0..count - 1).rev() {
+                let code = ffi::archive_filter_code(self.handle, i);
+                if ffi::archive_read_append_filter(builder.handle, code) != ffi::ARCHIVE_OK {
+                    return Err(ArchiveError::from(&builder as &dyn Handle));
+                }
+            }
+        }
+        builder.apply_options()?;
+
+        let res = match (&self.source, &self._memory) {
+            (Some(path), _) => {
+                let c_file = CString::new(path.to_string_lossy().as_bytes()).unwrap();
+                unsafe {
+                    ffi::archive_read_open_filename(
+                        builder.handle,
+                        c_file.as_ptr(),
+                        reopen.block_size,
+                    )
+                }
+            }
+            (None, Some(data)) => {
+                let buf = (**data).as_ref();
+                unsafe {
+                    ffi::archive_read_open_memory(builder.handle, buf.as_ptr() as _, buf.len())
+                }
+            }
+            (None, None) => return Err(ArchiveError::NotRewindable),
+        };
+        if res != ffi::ARCHIVE_OK {
+            return Err(ArchiveError::from(&builder as &dyn Handle));
+        }
+
+        builder.consume();
+        unsafe {
+            ffi::archive_read_free(self.handle);
+        }
+        self.handle = builder.handle;
+        self.entry = Default::default();
+        self.headers = 0;
+        Ok(())
+    }
+
     pub fn header_position(&self) -> i64 {
         unsafe { ffi::archive_read_header_position(self.handle) }
     }
 
+    // `None` at the end of the archive, but also on any error; use `try_next_header` to tell them
+    // apart. Warnings are collected for `take_warnings`.
     pub fn next_header(&mut self) -> Option<&mut ReaderEntryHandle> {
+        let _span = span!("next_header");
         let res = unsafe { ffi::archive_read_next_header(self.handle, &mut self.entry.handle) };
-        if res == 0 {
-            Some(&mut self.entry)
-        } else {
-            None
+        match res {
+            ffi::ARCHIVE_OK | ffi::ARCHIVE_WARN => {
+                self.headers += 1;
+                if res == ffi::ARCHIVE_WARN {
+                    event!(WARN, pathname = ?entry_pathname(self.entry.handle), error = %self.err_msg(), "header warning");
+                    self.warn(self.entry.handle);
+                }
+                event!(DEBUG, pathname = ?entry_pathname(self.entry.handle), "read header");
+                if let Err(_err) = self.check_header(self.entry.handle) {
+                    event!(ERROR, pathname = ?entry_pathname(self.entry.handle), error = %_err, "header rejected");
+                    return None;
+                }
+                Some(&mut self.entry)
+            }
+            ffi::ARCHIVE_EOF => None,
+            _ => {
+                event!(ERROR, error = %self.err_msg(), "failed to read header");
+                None
+            }
+        }
+    }
+
+    // Like `next_header`, with `None` only at the end of the archive. Headers libarchive warns
+    // about, e.g. for an unknown pax attribute, are returned like any other, while truncated or
+    // corrupt archives fail.
+    pub fn try_next_header(&mut self) -> ArchiveResult<Option<&mut ReaderEntryHandle>> {
+        match self.read_header()? {
+            true => Ok(Some(&mut self.entry)),
+            false => Ok(None),
+        }
+    }
+
+    // Moves on to the entry `target` names, e.g. `skip_to("docs/README")` or `skip_to(42)`,
+    // skipping the data of every entry before it. libarchive seeks past data where the source and
+    // format allow, so finding one file in a large uncompressed tar costs little more than reading
+    // its headers. Only entries after the current one are considered; `None` means the archive
+    // ended first. Read the data of the entry found with `read_entry_data`.
+    pub fn skip_to<'t, T: Into<EntryTarget<'t>>>(
+        &mut self,
+        target: T,
+    ) -> ArchiveResult<Option<&mut ReaderEntryHandle>> {
+        let target = target.into();
+        if let EntryTarget::Index(index) = target {
+            if index < self.headers {
+                return Ok(None);
+            }
+        }
+        while self.read_header()? {
+            let found = match target {
+                EntryTarget::Name(name) => {
+                    let pathname = unsafe { ffi::archive_entry_pathname(self.entry.handle) };
+                    !pathname.is_null()
+                        && unsafe { CStr::from_ptr(pathname) }.to_bytes() == name.as_bytes()
+                }
+                EntryTarget::Index(index) => self.headers == index + 1,
+            };
+            if found {
+                return Ok(Some(&mut self.entry));
+            }
+        }
+        Ok(None)
+    }
+
+    // Moves on to the first remaining entry whose metadata `predicate` accepts, like `skip_to`,
+    // and returns it for reading its data. `None` means the archive ended first.
+    pub fn find_entry<F: FnMut(&EntryMetadata) -> bool>(
+        &mut self,
+        mut predicate: F,
+    ) -> ArchiveResult<Option<CurrentEntry<'_, 'a>>> {
+        while self.read_header()? {
+            if predicate(&EntryMetadata::from_entry(self.entry.handle)) {
+                return Ok(Some(CurrentEntry {
+                    reader: self,
+                    position: 0,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    // Reads every header once to look up entries by name in `entry_by_name`, then starts over.
+    // Returns the number of entries. Needs a reader `rewind` works on.
+    pub fn build_index(&mut self) -> ArchiveResult<usize> {
+        self.rewind()?;
+        let mut index = HashMap::new();
+        while self.read_header()? {
+            if let Some(pathname) =
+                c_string(unsafe { ffi::archive_entry_pathname(self.entry.handle) })
+            {
+                index.entry(pathname).or_insert(self.headers - 1);
+            }
+        }
+        let count = self.headers;
+        self.rewind()?;
+        self.index = Some(index);
+        Ok(count)
+    }
+
+    // The entry at `index`, counting from 0 at the first entry of the archive, in any order:
+    // entries before the current one are served by starting over with `rewind`, so going back
+    // needs a reader opened from a file or memory. `None` past the last entry.
+    pub fn entry_at(&mut self, index: usize) -> ArchiveResult<Option<CurrentEntry<'_, 'a>>> {
+        // The current entry itself is read again too, its data may have been consumed.
+        if index < self.headers {
+            self.rewind()?;
+        }
+        if self.skip_to(index)?.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(CurrentEntry {
+            reader: self,
+            position: 0,
+        }))
+    }
+
+    // The first entry named `name`, in any order like `entry_at`. Builds the index on first use.
+    pub fn entry_by_name(&mut self, name: &str) -> ArchiveResult<Option<CurrentEntry<'_, 'a>>> {
+        if self.index.is_none() {
+            self.build_index()?;
+        }
+        match self.index.as_ref().and_then(|index| index.get(name)) {
+            Some(&index) => self.entry_at(index),
+            None => Ok(None),
+        }
+    }
+
+    // Reads the whole data of the current entry, e.g. one found with `skip_to`, subject to
+    // `Builder::max_entry_size`.
+    pub fn read_entry_data(&mut self) -> ArchiveResult<Vec<u8>> {
+        self.read_data(&mut 0, None)
+    }
+
+    // Extracts the current entry, e.g. one found with `skip_to`, to `path` instead of its own
+    // pathname. Directories leading to `path` are created unless `options` says otherwise.
+    pub fn extract_entry_to<T: AsRef<Path>>(
+        &mut self,
+        path: T,
+        options: &ExtractOptions,
+    ) -> ArchiveResult<()> {
+        let c_path = CString::new(path.as_ref().to_string_lossy().as_bytes()).unwrap();
+        let entry = self.entry.handle;
+        unsafe {
+            // Put the entry's own pathname back afterwards, it is still the current entry.
+            let pathname = ffi::archive_entry_pathname(entry);
+            let pathname = (!pathname.is_null()).then(|| CStr::from_ptr(pathname).to_owned());
+            ffi::archive_entry_set_pathname(entry, c_path.as_ptr());
+            let res = ffi::archive_read_extract(self.handle, entry, options.flags);
+            ffi::archive_entry_set_pathname(
+                entry,
+                pathname.as_ref().map_or(ptr::null(), |p| p.as_ptr()),
+            );
+            match res {
+                ffi::ARCHIVE_OK | ffi::ARCHIVE_WARN => Ok(()),
+                _ => Err(ArchiveError::from(self as &dyn Handle)),
+            }
+        }
+    }
+
+    // Skips the rest of the current entry's data without decompressing it where the format
+    // allows, so only headers are decoded when listing. Reading the next header does the same.
+    pub fn skip_data(&mut self) -> ArchiveResult<()> {
+        match unsafe { ffi::archive_read_data_skip(self.handle) } {
+            ffi::ARCHIVE_OK => Ok(()),
+            _ => Err(ArchiveError::from(self as &dyn Handle)),
+        }
+    }
+
+    // What the detected format is able to store. The format is only guaranteed to be known once the
+    // first header has been read; until then no capabilities may be reported.
+    pub fn format_capabilities(&self) -> Capabilities {
+        Capabilities(unsafe { ffi::archive_read_format_capabilities(self.handle) })
+    }
+
+    // Whether the archive holds encrypted entries, as far as the headers read so far tell. `None`
+    // when it isn't known yet or the format doesn't support encryption at all.
+    pub fn has_encrypted_entries(&self) -> Option<bool> {
+        match unsafe { ffi::archive_read_has_encrypted_entries(self.handle) } {
+            0 => Some(false),
+            res if res > 0 => Some(true),
+            _ => None,
+        }
+    }
+
+    // Name of the detected format, e.g. "ZIP 2.0 (deflation)" or "GNU tar format". Like
+    // `format_capabilities`, only known once the first header has been read.
+    pub fn format_name(&self) -> Option<String> {
+        c_string(unsafe { ffi::archive_format_name(self.handle) })
+    }
+
+    // `ARCHIVE_FORMAT_*` code of the detected format, 0 until the first header has been read.
+    // Mask with `ARCHIVE_FORMAT_BASE_MASK` to ignore the variant, e.g. ustar versus pax tar.
+    pub fn format_code(&self) -> i32 {
+        unsafe { ffi::archive_format(self.handle) }
+    }
+
+    // Number of filters the archive is read through. Filter 0 is the outermost, e.g. "xz" for a
+    // `.tar.xz`, and the last one is always "none", the raw source.
+    pub fn filter_count(&self) -> usize {
+        unsafe { ffi::archive_filter_count(self.handle) as usize }
+    }
+
+    // Bytes read from the source so far, before any decompression.
+    pub fn bytes_read_raw(&self) -> u64 {
+        unsafe { ffi::archive_filter_bytes(self.handle, -1).max(0) as u64 }
+    }
+
+    // Bytes the filters have decompressed so far, i.e. read by the format. Divided by
+    // `bytes_read_raw` this gives the compression ratio.
+    pub fn bytes_read_uncompressed(&self) -> u64 {
+        unsafe { ffi::archive_filter_bytes(self.handle, 0).max(0) as u64 }
+    }
+
+    // Name of filter `index` as counted by `filter_count`, `None` past the last one.
+    pub fn filter_name(&self, index: usize) -> Option<String> {
+        if index >= self.filter_count() {
+            return None;
+        }
+        c_string(unsafe { ffi::archive_filter_name(self.handle, index as c_int) })
+    }
+
+    // Checks the headers of every remaining entry for content that is dangerous to extract,
+    // without reading any entry data. Declared sizes above `max_size` are reported as
+    // `Finding::Oversized`. An archive passing the audit may still fail to extract, e.g. when
+    // its data is corrupt.
+    pub fn audit(&mut self, max_size: Option<u64>) -> ArchiveResult<Vec<AuditIssue>> {
+        let mut auditor = Auditor::new(max_size);
+        let mut issues = Vec::new();
+        while self.read_header()? {
+            auditor.check(self.entry.handle, &mut issues);
+        }
+        Ok(issues)
+    }
+
+    // Writes the metadata of every remaining entry to `out` as it is read, skipping the entry
+    // data, so archives of any size are dumped in constant memory. Returns the number of entries.
+    pub fn dump_metadata<W: io::Write>(
+        &mut self,
+        mut out: W,
+        format: DumpFormat,
+    ) -> ArchiveResult<usize> {
+        let mut count = 0;
+        while self.read_header()? {
+            match format {
+                DumpFormat::JsonLines => dump::write_json_line(&mut out, self.entry.handle)?,
+            }
+            count += 1;
+        }
+        out.flush()?;
+        Ok(count)
+    }
+
+    // Like `read_all`, with the data of each entry as `Bytes`, which can be shared and sliced
+    // without copying. The buffers are not given back to a configured `BufferPool`.
+    #[cfg(feature = "bytes")]
+    pub fn read_all_bytes(
+        &mut self,
+        max_size: Option<u64>,
+    ) -> ArchiveResult<Vec<(EntryMetadata, Bytes)>> {
+        let entries = self.read_all(max_size)?;
+        Ok(entries
+            .into_iter()
+            .map(|(metadata, data)| (metadata, Bytes::from(data)))
+            .collect())
+    }
+
+    // The remaining entries, borrowing the reader rather than consuming it like `into_iter`, so
+    // it can still be used once done with them, e.g. to `rewind` it.
+    pub fn entries(&mut self) -> Entries<'_, 'a> {
+        Entries { reader: self }
+    }
+
+    // Metadata of every remaining entry, without reading any entry data. Data is skipped rather
+    // than decompressed where the format allows.
+    pub fn list_entries(&mut self) -> ArchiveResult<Vec<EntryMetadata>> {
+        let mut entries = Vec::new();
+        while self.read_header()? {
+            entries.push(EntryMetadata::from_entry(self.entry.handle));
+        }
+        Ok(entries)
+    }
+
+    // Short for `list_entries`.
+    pub fn list(&mut self) -> ArchiveResult<Vec<EntryMetadata>> {
+        self.list_entries()
+    }
+
+    // Reads every remaining entry into memory. Meant for small archives such as configuration
+    // bundles; `max_size` caps the total number of bytes held so an unexpectedly large archive
+    // fails with `ArchiveError::LimitExceeded` instead of exhausting memory.
+    pub fn read_all(
+        &mut self,
+        max_size: Option<u64>,
+    ) -> ArchiveResult<Vec<(EntryMetadata, Vec<u8>)>> {
+        let mut entries = Vec::new();
+        let mut total: u64 = 0;
+        while self.read_header()? {
+            let metadata = EntryMetadata::from_entry(self.entry.handle);
+            let data = self.read_data(&mut total, max_size)?;
+            entries.push((metadata, data));
+        }
+        Ok(entries)
+    }
+
+    // Like `next_header`, failing on errors instead of ending.
+    pub(crate) fn read_next(&mut self) -> ArchiveResult<Option<&mut ReaderEntryHandle>> {
+        match self.read_header()? {
+            true => Ok(Some(&mut self.entry)),
+            false => Ok(None),
+        }
+    }
+
+    // Metadata of the next entry, `None` at the end of the archive.
+    #[cfg_attr(not(feature = "futures"), allow(dead_code))]
+    pub(crate) fn next_metadata(&mut self) -> ArchiveResult<Option<EntryMetadata>> {
+        match self.read_header()? {
+            true => Ok(Some(EntryMetadata::from_entry(self.entry.handle))),
+            false => Ok(None),
+        }
+    }
+
+    // The next block of the current entry's data, preceded by zeros for a hole of a sparse entry
+    // before it. `position` is the number of bytes returned so far.
+    #[cfg_attr(not(feature = "futures"), allow(dead_code))]
+    pub(crate) fn read_chunk(&mut self, position: &mut u64) -> ArchiveResult<Option<Vec<u8>>> {
+        loop {
+            let mut buff = ptr::null();
+            let mut size = 0;
+            let mut offset = 0;
+            let res = unsafe {
+                ffi::archive_read_data_block(self.handle, &mut buff, &mut size, &mut offset)
+            };
+            match res {
+                ffi::ARCHIVE_EOF => return Ok(None),
+                ffi::ARCHIVE_OK | ffi::ARCHIVE_WARN => {
+                    if res == ffi::ARCHIVE_WARN {
+                        self.warn(self.entry.handle);
+                    }
+                    let end = offset as u64 + size as u64;
+                    self.check_data(end)?;
+                    let mut chunk = vec![0; (offset as u64).saturating_sub(*position) as usize];
+                    // The last block may be empty, without a buffer. Unless it ends a hole, there
+                    // is nothing to return and the next call reaches the end.
+                    if size > 0 {
+                        chunk.extend_from_slice(unsafe {
+                            slice::from_raw_parts(buff as *const u8, size)
+                        });
+                    }
+                    *position = end;
+                    if !chunk.is_empty() {
+                        return Ok(Some(chunk));
+                    }
+                }
+                _ => return Err(self.data_error()),
+            }
+        }
+    }
+
+    // Moves on to the next header, returning false at the end of the archive.
+    fn read_header(&mut self) -> ArchiveResult<bool> {
+        match unsafe { ffi::archive_read_next_header(self.handle, &mut self.entry.handle) } {
+            ffi::ARCHIVE_OK => self.headers += 1,
+            ffi::ARCHIVE_WARN => {
+                self.headers += 1;
+                self.warn(self.entry.handle);
+            }
+            ffi::ARCHIVE_EOF => return Ok(false),
+            _ => return Err(ArchiveError::from(self as &dyn Handle)),
         }
+        self.check_header(self.entry.handle)?;
+        Ok(true)
+    }
+
+    // Reads the data of the current entry into a buffer, from the pool if one is configured.
+    // `total` counts the bytes read so far against `max_size`.
+    fn read_data(&mut self, total: &mut u64, max_size: Option<u64>) -> ArchiveResult<Vec<u8>> {
+        let mut data = match self.pool {
+            Some(ref pool) => pool.take(),
+            None => Vec::new(),
+        };
+        loop {
+            let mut buff = ptr::null();
+            let mut size = 0;
+            let mut offset = 0;
+            let res = unsafe {
+                ffi::archive_read_data_block(self.handle, &mut buff, &mut size, &mut offset)
+            };
+            match res {
+                ffi::ARCHIVE_EOF => return Ok(data),
+                ffi::ARCHIVE_OK | ffi::ARCHIVE_WARN => {
+                    if res == ffi::ARCHIVE_WARN {
+                        self.warn(self.entry.handle);
+                    }
+                    // Sparse entries skip over holes, which read back as zeros.
+                    let end = offset as u64 + size as u64;
+                    self.check_data(end)?;
+                    let grown = end.saturating_sub(data.len() as u64);
+                    if let Some(max) = max_size {
+                        if *total + grown > max {
+                            return Err(ArchiveError::LimitExceeded(Limit::TotalSize(max)));
+                        }
+                    }
+                    *total += grown;
+                    if offset as usize > data.len() {
+                        data.resize(offset as usize, 0);
+                    }
+                    // The last block may be empty, without a buffer.
+                    if size > 0 {
+                        data.extend_from_slice(unsafe {
+                            slice::from_raw_parts(buff as *const u8, size)
+                        });
+                    }
+                }
+                _ => return Err(self.data_error()),
+            }
+        }
+    }
+}
+
+// Entries are read into memory whole, the limits set with `Builder::max_entry_size` and
+// `Builder::limits` applying as usual.
+impl ArchiveSource for ReaderHandle<'_> {
+    fn next_entry(&mut self) -> ArchiveResult<Option<OwnedEntry>> {
+        if !self.read_header()? {
+            return Ok(None);
+        }
+        let metadata = EntryMetadata::from_entry(self.entry.handle);
+        let data = self.read_data(&mut 0, None)?;
+        Ok(Some(OwnedEntry { metadata, data }))
     }
 }
 
-impl IntoIterator for ReaderHandle {
-    type Item = ArchiveResult<ArchiveEntry>;
+impl<'a> IntoIterator for ReaderHandle<'a> {
+    type Item = ArchiveResult<ArchiveEntry<'a>>;
 
-    type IntoIter = ArchiveIterator;
+    type IntoIter = ArchiveIterator<'a>;
 
     fn into_iter(self) -> Self::IntoIter {
         ArchiveIterator {
             reader: Rc::new(self),
             entry: unsafe { ffi::archive_entry_new() },
             current: Default::default(),
+            done: false,
         }
     }
 }
 
-impl Drop for ReaderHandle {
+impl Drop for ReaderHandle<'_> {
     fn drop(&mut self) {
         unsafe {
             ffi::archive_read_free(self.handle);
@@ -148,17 +1051,18 @@ impl Drop for ReaderHandle {
     }
 }
 
-pub struct ArchiveEntry {
+pub struct ArchiveEntry<'a> {
     handle: *mut ffi::Struct_archive_entry,
-    reader: Rc<ReaderHandle>,
+    reader: Rc<ReaderHandle<'a>>,
     iterator_current: std::rc::Rc<std::cell::Cell<Option<usize>>>,
     current: usize,
-
+    // Bytes of data read so far, checked against `Builder::max_entry_size`.
+    position: u64,
 }
 
-impl ArchiveEntry {
+impl<'a> ArchiveEntry<'a> {
     pub fn new(
-        reader: Rc<ReaderHandle>,
+        reader: Rc<ReaderHandle<'a>>,
         handle: *mut ffi::Struct_archive_entry,
         iterator_current: Rc<Cell<Option<usize>>>,
         current: usize,
@@ -168,6 +1072,7 @@ impl ArchiveEntry {
             reader,
             iterator_current,
             current,
+            position: 0,
         }
     }
     pub fn is_current(&self) -> bool {
@@ -180,115 +1085,887 @@ impl ArchiveEntry {
         );
     }
 
-    pub fn pathname(&self) -> Option<String> {
-        self.check_current();
+    // Like `check_current`, failing with `ArchiveError::StaleEntry` instead of panicking.
+    pub fn ensure_current(&self) -> ArchiveResult<()> {
+        match self.is_current() {
+            true => Ok(()),
+            false => Err(ArchiveError::StaleEntry),
+        }
+    }
+
+    // Like `pathname`, failing instead of panicking once the iterator has moved on.
+    pub fn try_pathname(&self) -> ArchiveResult<Option<String>> {
+        self.ensure_current()?;
+        Ok(self.pathname())
+    }
+
+    // Like `size`, failing instead of panicking once the iterator has moved on.
+    pub fn try_size(&self) -> ArchiveResult<i64> {
+        self.ensure_current()?;
+        Ok(self.size())
+    }
+
+    // Like `filetype`, failing instead of panicking once the iterator has moved on.
+    pub fn try_filetype(&self) -> ArchiveResult<ArchiveEntryFiletype> {
+        self.ensure_current()?;
+        Ok(self.filetype())
+    }
+
+    // Like `metadata`, failing instead of panicking once the iterator has moved on.
+    pub fn try_metadata(&self) -> ArchiveResult<EntryMetadata> {
+        self.ensure_current()?;
+        Ok(self.metadata())
+    }
+
+    pub fn pathname(&self) -> Option<String> {
+        self.check_current();
+
+        let pathname = unsafe { ffi::archive_entry_pathname(self.handle) };
+
+        if pathname.is_null() {
+            return None;
+        }
+
+        let pathname = unsafe { std::ffi::CStr::from_ptr(pathname) };
+
+        let string = pathname.to_str().ok().map(|it| it.to_string())?;
+
+        Some(string)
+    }
+
+    pub(crate) fn entry_ptr(&self) -> *mut ffi::Struct_archive_entry {
+        self.check_current();
+        self.handle
+    }
+
+    // Copy of the header that stays valid once the iterator has moved on, unlike the entry.
+    pub fn metadata(&self) -> EntryMetadata {
+        self.check_current();
+        EntryMetadata::from_entry(self.handle)
+    }
+
+    // The entry as a line of `bsdtar -tv` output.
+    pub fn listing(&self) -> Listing {
+        self.check_current();
+        Listing::from_entry(self.handle)
+    }
+
+    pub fn size(&self) -> i64 {
+        self.check_current();
+        unsafe { ffi::archive_entry_size(self.handle) }
+    }
+
+    pub fn filetype(&self) -> ArchiveEntryFiletype {
+        self.check_current();
+        ArchiveEntryFiletype::from_entry(self.handle)
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.check_current();
+        matches!(self.filetype(), ArchiveEntryFiletype::Directory)
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.check_current();
+        matches!(self.filetype(), ArchiveEntryFiletype::RegularFile)
+    }
+
+    // The full mode, file type bits included, as `st_mode` holds it.
+    pub fn mode(&self) -> u32 {
+        self.check_current();
+        unsafe { ffi::archive_entry_mode(self.handle) as u32 }
+    }
+
+    // Permission bits of the mode, including the setuid, setgid and sticky bits.
+    pub fn perm(&self) -> u32 {
+        self.check_current();
+        unsafe { ffi::archive_entry_perm(self.handle) as u32 }
+    }
+
+    #[cfg(unix)]
+    pub fn permissions(&self) -> std::fs::Permissions {
+        std::os::unix::fs::PermissionsExt::from_mode(self.perm())
+    }
+
+    // The mode as `ls -l` shows it, e.g. `-rw-r--r--`.
+    pub fn mode_string(&self) -> String {
+        self.check_current();
+        listing::mode_string(self.handle)
+    }
+
+    // Modification time, if the entry has one.
+    pub fn mtime(&self) -> Option<SystemTime> {
+        self.check_current();
+        unsafe {
+            entry_time(
+                ffi::archive_entry_mtime_is_set(self.handle),
+                ffi::archive_entry_mtime(self.handle) as _,
+                ffi::archive_entry_mtime_nsec(self.handle) as _,
+            )
+        }
+    }
+
+    // Access time, if the entry has one.
+    pub fn atime(&self) -> Option<SystemTime> {
+        self.check_current();
+        unsafe {
+            entry_time(
+                ffi::archive_entry_atime_is_set(self.handle),
+                ffi::archive_entry_atime(self.handle) as _,
+                ffi::archive_entry_atime_nsec(self.handle) as _,
+            )
+        }
+    }
+
+    // Inode change time, if the entry has one.
+    pub fn ctime(&self) -> Option<SystemTime> {
+        self.check_current();
+        unsafe {
+            entry_time(
+                ffi::archive_entry_ctime_is_set(self.handle),
+                ffi::archive_entry_ctime(self.handle) as _,
+                ffi::archive_entry_ctime_nsec(self.handle) as _,
+            )
+        }
+    }
+
+    // Creation time, if the format records one.
+    pub fn birthtime(&self) -> Option<SystemTime> {
+        self.check_current();
+        unsafe {
+            entry_time(
+                ffi::archive_entry_birthtime_is_set(self.handle),
+                ffi::archive_entry_birthtime(self.handle) as _,
+                ffi::archive_entry_birthtime_nsec(self.handle) as _,
+            )
+        }
+    }
+
+    // Whether reading the data needs a passphrase, see `Builder::add_passphrase`.
+    pub fn is_data_encrypted(&self) -> bool {
+        self.check_current();
+        unsafe { ffi::archive_entry_is_data_encrypted(self.handle) != 0 }
+    }
+
+    pub fn is_metadata_encrypted(&self) -> bool {
+        self.check_current();
+        unsafe { ffi::archive_entry_is_metadata_encrypted(self.handle) != 0 }
+    }
+
+    // The data as the blocks libarchive decodes, without copying them. Unlike `Read`, holes of
+    // sparse entries are not filled in, see `Blocks::next_block`.
+    pub fn blocks(&mut self) -> Blocks<'_, 'a> {
+        self.check_current();
+        Blocks { entry: self }
+    }
+
+    // Writes the rest of the data to `file` at its current position, see `copy_to_fd`.
+    #[cfg(unix)]
+    pub fn copy_to_file(&mut self, file: &File) -> ArchiveResult<()> {
+        self.copy_to_fd(file.as_raw_fd())
+    }
+
+    // Writes the rest of the data straight from libarchive's buffers to `fd`, which may be a
+    // file, pipe or socket. Holes of sparse entries are seeked over where `fd` allows. The data
+    // is not checked against `Builder::max_entry_size`.
+    #[cfg(unix)]
+    pub fn copy_to_fd(&mut self, fd: RawFd) -> ArchiveResult<()> {
+        self.check_current();
+        match unsafe { ffi::archive_read_data_into_fd(self.reader.handle, fd) } {
+            ffi::ARCHIVE_OK | ffi::ARCHIVE_WARN => Ok(()),
+            _ => Err(data_error(self, self.handle)),
+        }
+    }
+
+    // Reads the data into a buffer allocated for the entry's declared size, failing with
+    // `Limit::EntrySize` once the data exceeds `max_size`. Declared sizes can't be trusted, so no
+    // more than `max_size` or 16 MiB is allocated before data actually arrives. The data is read
+    // in blocks, which libarchive can't continue where `Read` left off, so this fails with
+    // `io::ErrorKind::InvalidInput` if any of it was read already.
+    pub fn read_to_vec(&mut self, max_size: Option<u64>) -> ArchiveResult<Vec<u8>> {
+        self.check_current();
+        if self.position > 0 {
+            let msg = "read_to_vec needs an entry none of whose data was read";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into());
+        }
+        let declared = unsafe { ffi::archive_entry_size(self.handle) }.max(0) as u64;
+        let capacity = declared
+            .min(max_size.unwrap_or(u64::MAX))
+            .min(PREALLOCATE_LIMIT);
+        let mut data = Vec::with_capacity(capacity as usize);
+        let mut blocks = self.blocks();
+        while let Some(block) = blocks.next_block() {
+            let (offset, block) = block?;
+            // Sparse entries skip over holes, which read back as zeros.
+            let offset = offset as usize;
+            let end = offset as u64 + block.len() as u64;
+            if let Some(max) = max_size {
+                if end > max {
+                    return Err(ArchiveError::LimitExceeded(Limit::EntrySize(max)));
+                }
+            }
+            if offset > data.len() {
+                data.resize(offset, 0);
+            }
+            data.extend_from_slice(block);
+        }
+        Ok(data)
+    }
+
+    // Skips the rest of the data, see `ReaderHandle::skip_data`.
+    pub fn skip(&mut self) -> ArchiveResult<()> {
+        self.check_current();
+        match unsafe { ffi::archive_read_data_skip(self.reader.handle) } {
+            ffi::ARCHIVE_OK => Ok(()),
+            _ => Err(ArchiveError::from(self as &dyn Handle)),
+        }
+    }
+}
+
+// Blocks of the data of an entry, see `ArchiveEntry::blocks`. A block points into a buffer of
+// libarchive that the next call reuses, which is why this is not an `Iterator`.
+pub struct Blocks<'e, 'a> {
+    entry: &'e mut ArchiveEntry<'a>,
+}
+
+impl Blocks<'_, '_> {
+    // The next block with its offset into the entry, `None` after the last one. A block starting
+    // past the end of the previous one follows a hole of a sparse entry, which reads as zeros.
+    pub fn next_block(&mut self) -> Option<ArchiveResult<(u64, &[u8])>> {
+        let entry = &mut *self.entry;
+        let mut buff = ptr::null();
+        let mut size = 0;
+        let mut offset = 0;
+        let res = unsafe {
+            ffi::archive_read_data_block(entry.reader.handle, &mut buff, &mut size, &mut offset)
+        };
+        match res {
+            ffi::ARCHIVE_EOF => None,
+            ffi::ARCHIVE_OK | ffi::ARCHIVE_WARN => {
+                if res == ffi::ARCHIVE_WARN {
+                    entry.reader.warn(entry.handle);
+                }
+                let end = offset as u64 + size as u64;
+                if let Err(err) = entry.reader.check_data(end) {
+                    return Some(Err(err));
+                }
+                entry.position = end;
+                // The last block may be empty, without a buffer.
+                let block = match size {
+                    0 => &[][..],
+                    _ => unsafe { slice::from_raw_parts(buff as *const u8, size) },
+                };
+                Some(Ok((offset as u64, block)))
+            }
+            _ => Some(Err(data_error(entry, entry.handle))),
+        }
+    }
+}
+
+impl Read for ArchiveEntry<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check_current();
+
+        let size = unsafe {
+            ffi::archive_read_data(
+                self.reader.handle,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len(),
+            )
+        };
+
+        if size < 0 {
+            let err = data_error(self, self.handle);
+            event!(ERROR, pathname = ?entry_pathname(self.handle), error = %err, "failed to read data");
+
+            return Err(io::Error::new(io::ErrorKind::Other, err));
+        }
+
+        self.position += size as u64;
+        if let Err(err) = self.reader.check_data(self.position) {
+            event!(ERROR, pathname = ?entry_pathname(self.handle), error = %err, "entry too large");
+            return Err(io::Error::new(io::ErrorKind::Other, err));
+        }
+
+        Ok(size.try_into().unwrap())
+    }
+}
+
+// Entries of a reader, see `ReaderHandle::entries`. An entry borrows the reader until the next one
+// is read, which is why this is not an `Iterator`.
+pub struct Entries<'r, 'a> {
+    reader: &'r mut ReaderHandle<'a>,
+}
+
+impl<'a> Entries<'_, 'a> {
+    // Reads the next header, `None` at the end of the archive. Data of the previous entry not
+    // read is skipped.
+    pub fn next_entry(&mut self) -> Option<ArchiveResult<CurrentEntry<'_, 'a>>> {
+        match self.reader.read_header() {
+            Ok(true) => Some(Ok(CurrentEntry {
+                reader: &mut *self.reader,
+                position: 0,
+            })),
+            Ok(false) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+// The entry last read by `Entries::next_entry`. Its metadata is available through `Entry`, its
+// data through `Read`.
+pub struct CurrentEntry<'r, 'a> {
+    reader: &'r mut ReaderHandle<'a>,
+    // Bytes of data read so far, checked against `Builder::max_entry_size`.
+    position: u64,
+}
+
+impl CurrentEntry<'_, '_> {
+    // Copy of the header that stays valid once the next entry is read.
+    pub fn metadata(&self) -> EntryMetadata {
+        EntryMetadata::from_entry(self.reader.entry.handle)
+    }
+}
+
+impl Deref for CurrentEntry<'_, '_> {
+    type Target = ReaderEntryHandle;
+
+    fn deref(&self) -> &ReaderEntryHandle {
+        &self.reader.entry
+    }
+}
+
+impl Read for CurrentEntry<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = unsafe {
+            ffi::archive_read_data(
+                self.reader.handle,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len(),
+            )
+        };
+        if size < 0 {
+            let err = self.reader.data_error();
+            return Err(io::Error::new(io::ErrorKind::Other, err));
+        }
+        self.position += size as u64;
+        if let Err(err) = self.reader.check_data(self.position) {
+            return Err(io::Error::new(io::ErrorKind::Other, err));
+        }
+        Ok(size as usize)
+    }
+}
+
+// Random access within the entry data. libarchive only implements it for some formats (currently
+// uncompressed RAR entries), and only when the archive was opened from a seekable source such as
+// `Builder::open_file` or `Builder::open_seekable_stream`. Other formats fail with an error and
+// leave the read position unchanged.
+impl Seek for ArchiveEntry<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.check_current();
+
+        let (offset, whence) = match pos {
+            SeekFrom::Start(offset) => (offset as i64, libc::SEEK_SET),
+            SeekFrom::Current(offset) => (offset, libc::SEEK_CUR),
+            SeekFrom::End(offset) => (offset, libc::SEEK_END),
+        };
+        let res = unsafe { ffi::archive_seek_data(self.reader.handle, offset, whence) };
+
+        if res < 0 {
+            let err = ArchiveError::from(self as &dyn Handle);
+            return Err(io::Error::new(io::ErrorKind::Other, err));
+        }
+
+        self.position = res as u64;
+        Ok(res as u64)
+    }
+}
+
+impl Handle for ArchiveEntry<'_> {
+    unsafe fn handle(&self) -> *mut ffi::Struct_archive {
+        self.reader.handle
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveEntryFiletype {
+    RegularFile,
+    SymbolicLink,
+    Socket,
+    CharacterDevice,
+    Directory,
+    NamedPipe,
+    Unkown,
+}
+
+impl ArchiveEntryFiletype {
+    fn from_entry(entry: *mut ffi::Struct_archive_entry) -> Self {
+        Self::from_mode(unsafe { ffi::archive_entry_filetype(entry) })
+    }
+
+    fn from_mode(mode: libc::mode_t) -> Self {
+        match mode & ffi::AE_IFMT {
+            ffi::AE_IFREG => ArchiveEntryFiletype::RegularFile,
+            ffi::AE_IFLNK => ArchiveEntryFiletype::SymbolicLink,
+            ffi::AE_IFSOCK => ArchiveEntryFiletype::Socket,
+            ffi::AE_IFCHR => ArchiveEntryFiletype::CharacterDevice,
+            ffi::AE_IFDIR => ArchiveEntryFiletype::Directory,
+            ffi::AE_IFIFO => ArchiveEntryFiletype::NamedPipe,
+            _ => ArchiveEntryFiletype::Unkown,
+        }
+    }
+}
+
+// Features of an archive format, as reported by `ReaderHandle::format_capabilities`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities(i32);
+
+impl Capabilities {
+    // The format can store encrypted entry data.
+    pub const ENCRYPT_DATA: Capabilities = Capabilities(ffi::ARCHIVE_READ_FORMAT_CAPS_ENCRYPT_DATA);
+    // The format can store encrypted entry metadata such as pathnames.
+    pub const ENCRYPT_METADATA: Capabilities =
+        Capabilities(ffi::ARCHIVE_READ_FORMAT_CAPS_ENCRYPT_METADATA);
+
+    pub fn bits(&self) -> i32 {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == ffi::ARCHIVE_READ_FORMAT_CAPS_NONE
+    }
+
+    pub fn contains(&self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Capabilities;
+
+    fn bitor(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+}
+
+// How far reading has got, as passed to the `Builder::on_progress` callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    // Headers read so far.
+    pub entries: usize,
+    // See `ReaderHandle::bytes_read_raw`.
+    pub bytes_read_raw: u64,
+    // See `ReaderHandle::bytes_read_uncompressed`.
+    pub bytes_read_uncompressed: u64,
+}
+
+// Closure given to `Builder::on_progress`.
+struct ProgressCallback {
+    callback: Box<dyn FnMut(Progress) + Send>,
+    interval: u64,
+    // Decompressed bytes at the last call.
+    reported: u64,
+}
+
+// Upper bounds for header fields of untrusted archives. A header exceeding any of them fails with
+// `ArchiveError::LimitExceeded` before the entry is handed out. `None` leaves a field unchecked.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderLimits {
+    // Length of the entry pathname in bytes.
+    pub max_pathname: Option<usize>,
+    // Length of the symlink or hardlink target in bytes.
+    pub max_linkname: Option<usize>,
+    // Number of extended attributes, which pax headers can carry in unbounded numbers.
+    pub max_xattrs: Option<usize>,
+}
+
+impl HeaderLimits {
+    fn check(&self, entry: *mut ffi::Struct_archive_entry) -> ArchiveResult<()> {
+        unsafe {
+            if let Some(max) = self.max_pathname {
+                let len = c_len(ffi::archive_entry_pathname(entry))
+                    .or_else(|| c_len(ffi::archive_entry_pathname_utf8(entry)));
+                if len.unwrap_or(0) > max {
+                    return Err(ArchiveError::LimitExceeded(Limit::PathnameLength(max)));
+                }
+            }
+            if let Some(max) = self.max_linkname {
+                let len = c_len(ffi::archive_entry_symlink(entry))
+                    .or_else(|| c_len(ffi::archive_entry_symlink_utf8(entry)))
+                    .into_iter()
+                    .chain(
+                        c_len(ffi::archive_entry_hardlink(entry))
+                            .or_else(|| c_len(ffi::archive_entry_hardlink_utf8(entry))),
+                    )
+                    .max();
+                if len.unwrap_or(0) > max {
+                    return Err(ArchiveError::LimitExceeded(Limit::LinknameLength(max)));
+                }
+            }
+            if let Some(max) = self.max_xattrs {
+                if ffi::archive_entry_xattr_count(entry) as usize > max {
+                    return Err(ArchiveError::LimitExceeded(Limit::Xattrs(max)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn c_len(ptr: *const c_char) -> Option<usize> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(unsafe { CStr::from_ptr(ptr) }.to_bytes().len())
+    }
+}
+
+// Owned copy of an entry header, which stays valid after the reader moves on to the next entry.
+#[derive(Debug, Clone)]
+pub struct EntryMetadata {
+    pub pathname: Option<String>,
+    pub size: i64,
+    pub filetype: ArchiveEntryFiletype,
+    // The full mode, file type bits included, like `Entry::mode`.
+    pub mode: u32,
+    // Permission bits, including setuid, setgid and sticky.
+    pub perm: u32,
+    // Modification time in seconds since the epoch.
+    pub mtime: i64,
+    pub symlink: Option<String>,
+    pub hardlink: Option<String>,
+}
+
+impl EntryMetadata {
+    // Takes the numeric fields from a single `archive_entry_stat` call rather than one call per
+    // field, which adds up when snapshotting every entry of a large archive.
+    fn from_entry(entry: *mut ffi::Struct_archive_entry) -> Self {
+        unsafe {
+            let stat = ffi::archive_entry_stat(entry);
+            if stat.is_null() {
+                panic!("Allocation error");
+            }
+            let mode = (*stat).st_mode as libc::mode_t;
+            EntryMetadata {
+                pathname: c_string(ffi::archive_entry_pathname(entry)),
+                size: (*stat).st_size as i64,
+                filetype: ArchiveEntryFiletype::from_mode(mode),
+                mode: mode as u32,
+                perm: (mode & 0o7777) as u32,
+                mtime: (*stat).st_mtime as i64,
+                symlink: c_string(ffi::archive_entry_symlink(entry)),
+                hardlink: c_string(ffi::archive_entry_hardlink(entry)),
+            }
+        }
+    }
+}
+
+// An entry together with its data, independent of the reader it came from.
+#[derive(Debug, Clone)]
+pub struct OwnedEntry {
+    pub metadata: EntryMetadata,
+    pub data: Vec<u8>,
+}
+
+pub(crate) fn c_string(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    let c_str = unsafe { CStr::from_ptr(ptr) };
+    c_str.to_str().ok().map(|it| it.to_string())
+}
+
+pub struct Builder {
+    handle: *mut ffi::Struct_archive,
+    consumed: bool,
+    header_charset: Option<String>,
+    verify_checksums: bool,
+    concatenated: bool,
+    options: Vec<ReaderOption>,
+    passphrases: Vec<CString>,
+    passphrase: Option<Box<PassphraseCallback>>,
+    limits: HeaderLimits,
+    max_entry_size: Option<u64>,
+    max_total_size: Option<u64>,
+    max_ratio: Option<u64>,
+    cancel: Option<Arc<AtomicBool>>,
+    progress: Option<ProgressCallback>,
+    progress_interval: u64,
+    expected_format: Option<i32>,
+    pool: Option<BufferPool>,
+    block_size: usize,
+    stream_buffer_size: usize,
+}
+
+// Modules an option set with `Builder::set_option` and friends is meant for.
+#[derive(Clone, Copy)]
+enum OptionScope {
+    Any,
+    Format,
+    Filter,
+}
+
+// Closure given to `Builder::passphrase_callback`, boxed so its address stays valid while
+// libarchive holds it.
+struct PassphraseCallback {
+    callback: Box<dyn FnMut() -> Option<String> + Send>,
+    // Last passphrase returned, which libarchive reads after the callback has returned.
+    current: Option<CString>,
+}
+
+impl PassphraseCallback {
+    fn register(&mut self, handle: *mut ffi::Struct_archive) {
+        unsafe {
+            ffi::archive_read_set_passphrase_callback(
+                handle,
+                self as *mut PassphraseCallback as *mut c_void,
+                Some(passphrase_callback),
+            );
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ReaderOption {
+    scope: OptionScope,
+    module: String,
+    key: String,
+    value: String,
+}
+
+pub struct ReaderEntryHandle {
+    handle: *mut ffi::Struct_archive_entry,
+}
+
+trait ReadSeek: Read + Seek {}
 
-        let pathname = unsafe { ffi::archive_entry_pathname(self.handle) };
+impl<T: Read + Seek> ReadSeek for T {}
 
-        if pathname.is_null() {
-            return None;
-        }
+enum PipeSource<'a> {
+    Read(Box<dyn Read + Send + 'a>),
+    Seek(Box<dyn ReadSeek + Send + 'a>),
+    // Read in place from the reader's own buffer, `buffer` stays empty.
+    Buffered(Box<dyn BufRead + Send + 'a>),
+    // Chunks handed over as they are, `buffer` stays empty.
+    Chunks(Box<dyn ChunkSource + Send + 'a>),
+}
 
-        let pathname = unsafe { std::ffi::CStr::from_ptr(pathname) };
+trait ChunkSource {
+    // The next chunk that isn't empty, an empty one at the end.
+    fn next_chunk(&mut self) -> &[u8];
+}
 
-        let string = pathname.to_str().ok().map(|it| it.to_string())?;
+// Keeps the chunk last handed to libarchive, which reads from it until asking for the next one.
+struct Chunks<I: Iterator> {
+    iter: I,
+    current: Option<I::Item>,
+}
 
-        Some(string)
+impl<I> ChunkSource for Chunks<I>
+where
+    I: Iterator,
+    I::Item: AsRef<[u8]>,
+{
+    fn next_chunk(&mut self) -> &[u8] {
+        // An empty chunk would read as the end of the archive.
+        self.current = self.iter.find(|chunk| !chunk.as_ref().is_empty());
+        match self.current {
+            Some(ref chunk) => chunk.as_ref(),
+            None => &[],
+        }
     }
+}
 
-    pub fn size(&self) -> i64 {
-        self.check_current();
-        unsafe { ffi::archive_entry_size(self.handle) }
-    }
+struct Pipe<'a> {
+    reader: PipeSource<'a>,
+    buffer: Vec<u8>,
+    // Bytes of the `Buffered` source handed to libarchive by the last read. They have to stay
+    // valid until the next read, so they are only consumed then.
+    pending: usize,
+    // `Builder::cancel_token`, checked before every read from the source.
+    cancel: Option<Arc<AtomicBool>>,
+}
 
-    pub fn filetype(&self) -> ArchiveEntryFiletype {
-        self.check_current();
-        let it = unsafe {
-            match ffi::archive_entry_filetype(self.handle) {
-                ffi::AE_IFREG => ArchiveEntryFiletype::RegularFile,
-                ffi::AE_IFLNK => ArchiveEntryFiletype::SymbolicLink,
-                ffi::AE_IFSOCK => ArchiveEntryFiletype::Socket,
-                ffi::AE_IFCHR => ArchiveEntryFiletype::CharacterDevice,
-                ffi::AE_IFDIR => ArchiveEntryFiletype::Directory,
-                ffi::AE_IFIFO => ArchiveEntryFiletype::NamedPipe,
-                i => {
-                    ArchiveEntryFiletype::Unkown
-                }
-            }
-        };
-        it
+impl<'a> Pipe<'a> {
+    fn new<T: Read + Send + 'a>(src: T, buffer_size: usize) -> Self {
+        Pipe {
+            reader: PipeSource::Read(Box::new(src)),
+            buffer: vec![0; buffer_size],
+            pending: 0,
+            cancel: None,
+        }
     }
 
-    pub fn is_directory(&self) -> bool {
-        self.check_current();
-        matches!(self.filetype(), ArchiveEntryFiletype::Directory)
+    fn new_seekable<T: Read + Seek + Send + 'a>(src: T, buffer_size: usize) -> Self {
+        Pipe {
+            reader: PipeSource::Seek(Box::new(src)),
+            buffer: vec![0; buffer_size],
+            pending: 0,
+            cancel: None,
+        }
     }
 
-    pub fn is_file(&self) -> bool {
-        self.check_current();
-        matches!(self.filetype(), ArchiveEntryFiletype::RegularFile)
+    fn new_buffered<T: BufRead + Send + 'a>(src: T) -> Self {
+        Pipe {
+            reader: PipeSource::Buffered(Box::new(src)),
+            buffer: Vec::new(),
+            pending: 0,
+            cancel: None,
+        }
     }
-}
 
-impl Read for ArchiveEntry {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.check_current();
+    fn new_chunks<I>(chunks: I) -> Self
+    where
+        I: Iterator + Send + 'a,
+        I::Item: AsRef<[u8]> + Send + 'a,
+    {
+        Pipe {
+            reader: PipeSource::Chunks(Box::new(Chunks {
+                iter: chunks,
+                current: None,
+            })),
+            buffer: Vec::new(),
+            pending: 0,
+            cancel: None,
+        }
+    }
 
-        let size = unsafe {
-            ffi::archive_read_data(self.reader.handle, buf.as_mut_ptr() as *mut c_void, buf.len())
+    fn read_bytes(&mut self) -> io::Result<&[u8]> {
+        let size = match self.reader {
+            PipeSource::Read(ref mut reader) => reader.read(&mut self.buffer[..])?,
+            PipeSource::Seek(ref mut reader) => reader.read(&mut self.buffer[..])?,
+            PipeSource::Buffered(ref mut reader) => {
+                reader.consume(mem::take(&mut self.pending));
+                let bytes = reader.fill_buf()?;
+                self.pending = bytes.len();
+                return Ok(bytes);
+            }
+            PipeSource::Chunks(ref mut chunks) => return Ok(chunks.next_chunk()),
         };
+        Ok(&self.buffer[..size])
+    }
 
-        if size < 0 {
-            let err = ArchiveError::from(self as &dyn Handle);
-
-            return Err(io::Error::new(io::ErrorKind::Other, err));
+    // Only reachable for seekable sources, the callbacks calling it aren't registered otherwise.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self.reader {
+            PipeSource::Read(_) | PipeSource::Buffered(_) | PipeSource::Chunks(_) => Err(
+                io::Error::new(io::ErrorKind::Unsupported, "stream is not seekable"),
+            ),
+            PipeSource::Seek(ref mut reader) => reader.seek(pos),
         }
-
-        Ok(size.try_into().unwrap())
     }
 }
 
-impl Handle for ArchiveEntry {
-    unsafe fn handle(&self) -> *mut ffi::Struct_archive {
-        self.reader.handle
-    }
-}
+// Compression suffixes, checked before the archive suffix they follow.
+const FILTER_SUFFIXES: &[(&str, ReadFilter)] = &[
+    (".bz2", ReadFilter::Bzip2),
+    (".gz", ReadFilter::Gzip),
+    (".lz", ReadFilter::Lzip),
+    (".lz4", ReadFilter::Lz4),
+    (".lzma", ReadFilter::Lzma),
+    (".lzo", ReadFilter::Lzop),
+    (".xz", ReadFilter::Xz),
+    (".z", ReadFilter::Compress),
+    #[cfg(feature = "zstd")]
+    (".zst", ReadFilter::Zstd),
+];
 
-#[derive(Debug)]
-pub enum ArchiveEntryFiletype {
-    RegularFile,
-    SymbolicLink,
-    Socket,
-    CharacterDevice,
-    Directory,
-    NamedPipe,
-    Unkown,
-}
+// Short forms of compressed tarballs.
+const TAR_SUFFIXES: &[(&str, ReadFilter)] = &[
+    (".taz", ReadFilter::Compress),
+    (".tb2", ReadFilter::Bzip2),
+    (".tbz", ReadFilter::Bzip2),
+    (".tbz2", ReadFilter::Bzip2),
+    (".tgz", ReadFilter::Gzip),
+    (".tlz", ReadFilter::Lzip),
+    (".txz", ReadFilter::Xz),
+    #[cfg(feature = "zstd")]
+    (".tzst", ReadFilter::Zstd),
+];
 
-pub struct Builder {
-    handle: *mut ffi::Struct_archive,
-    consumed: bool,
-}
+// Archive suffixes. A `.rar` file may be in either version of the format.
+const FORMAT_SUFFIXES: &[(&str, &[ReadFormat])] = &[
+    (".7z", &[ReadFormat::SevenZip]),
+    (".a", &[ReadFormat::Ar]),
+    (".ar", &[ReadFormat::Ar]),
+    (".cab", &[ReadFormat::Cab]),
+    (".cpio", &[ReadFormat::Cpio]),
+    (".deb", &[ReadFormat::Ar]),
+    (".iso", &[ReadFormat::Iso9660]),
+    (".jar", &[ReadFormat::Zip]),
+    (".lha", &[ReadFormat::Lha]),
+    (".lzh", &[ReadFormat::Lha]),
+    (".mtree", &[ReadFormat::Mtree]),
+    (
+        ".rar",
+        &[
+            ReadFormat::Rar,
+            #[cfg(feature = "rar5")]
+            ReadFormat::Rar5,
+        ],
+    ),
+    (".tar", &[ReadFormat::Tar]),
+    (".warc", &[ReadFormat::Warc]),
+    (".xar", &[ReadFormat::Xar]),
+    (".zip", &[ReadFormat::Zip]),
+];
 
-pub struct ReaderEntryHandle {
-    handle: *mut ffi::Struct_archive_entry,
+fn find_suffix<'a, T>(name: &'a str, table: &'static [(&str, T)]) -> Option<(&'a str, &'static T)> {
+    table
+        .iter()
+        .find(|(suffix, _)| name.len() > suffix.len() && name.ends_with(suffix))
+        .map(|(suffix, support)| (&name[..name.len() - suffix.len()], support))
 }
 
-struct Pipe {
-    reader: Box<dyn Read>,
-    buffer: Vec<u8>,
+// Formats and filter the file name `name` implies, if any. A compressed file that isn't an
+// archive is read as a single raw entry.
+fn support_for_name(name: &str) -> Option<(&'static [ReadFormat], Option<&'static ReadFilter>)> {
+    let name = name.to_lowercase();
+    if let Some((_, filter)) = find_suffix(&name, TAR_SUFFIXES) {
+        return Some((&[ReadFormat::Tar], Some(filter)));
+    }
+    match find_suffix(&name, FILTER_SUFFIXES) {
+        Some((stem, filter)) => match find_suffix(stem, FORMAT_SUFFIXES) {
+            Some((_, formats)) => Some((formats, Some(filter))),
+            None => Some((&[ReadFormat::Raw], Some(filter))),
+        },
+        None => find_suffix(&name, FORMAT_SUFFIXES).map(|(_, formats)| (*formats, None)),
+    }
 }
 
-impl Pipe {
-    fn new<T: Any + Read>(src: T) -> Self {
-        Pipe {
-            reader: Box::new(src),
-            buffer: vec![0; 8192],
-        }
-    }
+// Format of the name libarchive gives it, as in `archive_format_name` without the variant, e.g.
+// "tar" for both "POSIX ustar format" and "GNU tar format".
+fn format_for_name(name: &str) -> Option<ReadFormat> {
+    let format = match name.to_lowercase().as_str() {
+        "7zip" => ReadFormat::SevenZip,
+        "all" => ReadFormat::All,
+        "ar" => ReadFormat::Ar,
+        "cab" => ReadFormat::Cab,
+        "cpio" => ReadFormat::Cpio,
+        "empty" => ReadFormat::Empty,
+        "gnutar" => ReadFormat::Gnutar,
+        "iso9660" => ReadFormat::Iso9660,
+        "lha" => ReadFormat::Lha,
+        "mtree" => ReadFormat::Mtree,
+        "rar" => ReadFormat::Rar,
+        #[cfg(feature = "rar5")]
+        "rar5" => ReadFormat::Rar5,
+        "raw" => ReadFormat::Raw,
+        "tar" => ReadFormat::Tar,
+        "warc" => ReadFormat::Warc,
+        "xar" => ReadFormat::Xar,
+        "zip" => ReadFormat::Zip,
+        _ => return None,
+    };
+    Some(format)
+}
 
-    fn read_bytes(&mut self) -> io::Result<usize> {
-        self.reader.read(&mut self.buffer[..])
+// A single error as is, several as `ArchiveError::Multiple`.
+fn combined(mut errors: Vec<ArchiveError>) -> ArchiveResult<()> {
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(ArchiveError::Multiple(errors)),
     }
 }
 
@@ -344,8 +2021,138 @@ impl Builder {
             .support_compression(ReadCompression::All)
     }
 
+    // Enables only the format and filter the file name of `path` implies, e.g. tar and zstd for
+    // `backup.tar.zst`, leaving the code for every other format out of reach of the input. Names
+    // without a known extension fall back to `support_all`. Only worth it when the name can be
+    // trusted, as an archive named after another format fails to open.
+    pub fn support_for_path<T: AsRef<Path>>(self, path: T) -> ArchiveResult<Self> {
+        let name = match path.as_ref().file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => return self.support_all(),
+        };
+        let (formats, filter) = match support_for_name(&name) {
+            Some(support) => support,
+            None => return self.support_all(),
+        };
+        for format in formats {
+            self.enable_format(format)?;
+        }
+        if let Some(filter) = filter {
+            self.enable_filter(filter)?;
+        }
+        // Like with `support_all`, a zero-byte file opens as an archive without entries.
+        self.enable_format(&ReadFormat::Empty)?;
+        Ok(self)
+    }
+
     pub fn support_filter(self, filter: ReadFilter) -> ArchiveResult<Self> {
-        let result = match filter {
+        self.enable_filter(&filter)?;
+        Ok(self)
+    }
+
+    pub fn support_format(self, format: ReadFormat) -> ArchiveResult<Self> {
+        self.enable_format(&format)?;
+        Ok(self)
+    }
+
+    // Enables the format with the `ARCHIVE_FORMAT_*` code `code`, for formats `ReadFormat` has no
+    // variant for. Codes of a variant, like `ARCHIVE_FORMAT_TAR_USTAR`, enable the whole format.
+    pub fn support_format_by_code(self, code: i32) -> ArchiveResult<Self> {
+        match unsafe { ffi::archive_read_support_format_by_code(self.handle, code) } {
+            ffi::ARCHIVE_OK => Ok(self),
+            _ => Err(ArchiveError::from(&self as &dyn Handle)),
+        }
+    }
+
+    // Enables a format by name, e.g. "tar", "zip" or "7zip" from the application's configuration.
+    // Names are case-insensitive; unknown ones fail with `io::ErrorKind::InvalidInput`.
+    pub fn support_format_by_name(self, name: &str) -> ArchiveResult<Self> {
+        match format_for_name(name) {
+            Some(format) => self.support_format(format),
+            None => {
+                let msg = format!("unknown archive format {}", name);
+                Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into())
+            }
+        }
+    }
+
+    // Decodes the input with `filter` rather than with the filters detected, pinning the chain for
+    // ambiguous or malformed input. The filter appended first reads the input, e.g.
+    // `append_filter(ReadFilter::Uu)?.append_filter(ReadFilter::Gzip)` for uuencoded gzip data.
+    // `ReadFilter::All` fails with `io::ErrorKind::InvalidInput`.
+    pub fn append_filter(self, filter: ReadFilter) -> ArchiveResult<Self> {
+        let code = match filter {
+            ReadFilter::All => {
+                let msg = "ReadFilter::All cannot be appended";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into());
+            }
+            ReadFilter::Bzip2 => ffi::ARCHIVE_FILTER_BZIP2,
+            ReadFilter::Compress => ffi::ARCHIVE_FILTER_COMPRESS,
+            ReadFilter::Grzip => ffi::ARCHIVE_FILTER_GRZIP,
+            ReadFilter::Gzip => ffi::ARCHIVE_FILTER_GZIP,
+            ReadFilter::Lrzip => ffi::ARCHIVE_FILTER_LRZIP,
+            ReadFilter::Lz4 => ffi::ARCHIVE_FILTER_LZ4,
+            ReadFilter::Lzip => ffi::ARCHIVE_FILTER_LZIP,
+            ReadFilter::Lzma => ffi::ARCHIVE_FILTER_LZMA,
+            ReadFilter::Lzop => ffi::ARCHIVE_FILTER_LZOP,
+            ReadFilter::None => ffi::ARCHIVE_FILTER_NONE,
+            ReadFilter::Program(ref prog) => {
+                let c_prog = CString::new(prog.as_str()).unwrap();
+                let result = unsafe {
+                    ffi::archive_read_append_filter_program(self.handle, c_prog.as_ptr())
+                };
+                return self.appended(result);
+            }
+            ReadFilter::ProgramSignature(ref prog, cb, size) => {
+                let c_prog = CString::new(prog.as_str()).unwrap();
+                let result = unsafe {
+                    ffi::archive_read_append_filter_program_signature(
+                        self.handle,
+                        c_prog.as_ptr(),
+                        mem::transmute(cb),
+                        size,
+                    )
+                };
+                return self.appended(result);
+            }
+            ReadFilter::Rpm => ffi::ARCHIVE_FILTER_RPM,
+            ReadFilter::Uu => ffi::ARCHIVE_FILTER_UU,
+            ReadFilter::Xz => ffi::ARCHIVE_FILTER_XZ,
+            #[cfg(feature = "zstd")]
+            ReadFilter::Zstd => ffi::ARCHIVE_FILTER_ZSTD,
+        };
+        let result = unsafe { ffi::archive_read_append_filter(self.handle, code) };
+        self.appended(result)
+    }
+
+    fn appended(self, result: c_int) -> ArchiveResult<Self> {
+        match result {
+            ffi::ARCHIVE_OK => Ok(self),
+            _ => ArchiveResult::from(&self as &dyn Handle).map(|_| self),
+        }
+    }
+
+    // Enables each of `filters`, e.g. from an allow list in the application's configuration. All
+    // of them are tried; when some fail the error lists every failure.
+    pub fn support_filters(self, filters: &[ReadFilter]) -> ArchiveResult<Self> {
+        let errors = filters
+            .iter()
+            .filter_map(|filter| self.enable_filter(filter).err())
+            .collect();
+        combined(errors).map(|_| self)
+    }
+
+    // Enables each of `formats`, see `support_filters`.
+    pub fn support_formats(self, formats: &[ReadFormat]) -> ArchiveResult<Self> {
+        let errors = formats
+            .iter()
+            .filter_map(|format| self.enable_format(format).err())
+            .collect();
+        combined(errors).map(|_| self)
+    }
+
+    fn enable_filter(&self, filter: &ReadFilter) -> ArchiveResult<()> {
+        let result = match *filter {
             ReadFilter::All => unsafe { ffi::archive_read_support_filter_all(self.handle) },
             ReadFilter::Bzip2 => unsafe { ffi::archive_read_support_filter_bzip2(self.handle) },
             ReadFilter::Compress => unsafe {
@@ -354,16 +2161,17 @@ impl Builder {
             ReadFilter::Grzip => unsafe { ffi::archive_read_support_filter_grzip(self.handle) },
             ReadFilter::Gzip => unsafe { ffi::archive_read_support_filter_gzip(self.handle) },
             ReadFilter::Lrzip => unsafe { ffi::archive_read_support_filter_lrzip(self.handle) },
+            ReadFilter::Lz4 => unsafe { ffi::archive_read_support_filter_lz4(self.handle) },
             ReadFilter::Lzip => unsafe { ffi::archive_read_support_filter_lzip(self.handle) },
             ReadFilter::Lzma => unsafe { ffi::archive_read_support_filter_lzma(self.handle) },
             ReadFilter::Lzop => unsafe { ffi::archive_read_support_filter_lzop(self.handle) },
             ReadFilter::None => unsafe { ffi::archive_read_support_filter_none(self.handle) },
-            ReadFilter::Program(prog) => {
-                let c_prog = CString::new(prog).unwrap();
+            ReadFilter::Program(ref prog) => {
+                let c_prog = CString::new(prog.as_str()).unwrap();
                 unsafe { ffi::archive_read_support_filter_program(self.handle, c_prog.as_ptr()) }
             }
-            ReadFilter::ProgramSignature(prog, cb, size) => {
-                let c_prog = CString::new(prog).unwrap();
+            ReadFilter::ProgramSignature(ref prog, cb, size) => {
+                let c_prog = CString::new(prog.as_str()).unwrap();
                 unsafe {
                     ffi::archive_read_support_filter_program_signature(
                         self.handle,
@@ -376,15 +2184,20 @@ impl Builder {
             ReadFilter::Rpm => unsafe { ffi::archive_read_support_filter_rpm(self.handle) },
             ReadFilter::Uu => unsafe { ffi::archive_read_support_filter_uu(self.handle) },
             ReadFilter::Xz => unsafe { ffi::archive_read_support_filter_xz(self.handle) },
+            #[cfg(feature = "zstd")]
+            ReadFilter::Zstd => {
+                require_version("archive_read_support_filter_zstd", 3003003)?;
+                unsafe { ffi::archive_read_support_filter_zstd(self.handle) }
+            }
         };
         match result {
-            ffi::ARCHIVE_OK => Ok(self),
-            _ => ArchiveResult::from(&self as &dyn Handle).map(|_| self),
+            ffi::ARCHIVE_OK => Ok(()),
+            _ => ArchiveResult::from(self as &dyn Handle),
         }
     }
 
-    pub fn support_format(self, format: ReadFormat) -> ArchiveResult<Self> {
-        let result = match format {
+    fn enable_format(&self, format: &ReadFormat) -> ArchiveResult<()> {
+        let result = match *format {
             ReadFormat::SevenZip => unsafe { ffi::archive_read_support_format_7zip(self.handle()) },
             ReadFormat::All => unsafe { ffi::archive_read_support_format_all(self.handle()) },
             ReadFormat::Ar => unsafe { ffi::archive_read_support_format_ar(self.handle()) },
@@ -398,38 +2211,387 @@ impl Builder {
             ReadFormat::Lha => unsafe { ffi::archive_read_support_format_lha(self.handle()) },
             ReadFormat::Mtree => unsafe { ffi::archive_read_support_format_mtree(self.handle()) },
             ReadFormat::Rar => unsafe { ffi::archive_read_support_format_rar(self.handle()) },
+            #[cfg(feature = "rar5")]
+            ReadFormat::Rar5 => {
+                require_version("archive_read_support_format_rar5", 3004000)?;
+                unsafe { ffi::archive_read_support_format_rar5(self.handle()) }
+            }
             ReadFormat::Raw => unsafe { ffi::archive_read_support_format_raw(self.handle()) },
             ReadFormat::Tar => unsafe { ffi::archive_read_support_format_tar(self.handle()) },
+            ReadFormat::Warc => unsafe { ffi::archive_read_support_format_warc(self.handle()) },
             ReadFormat::Xar => unsafe { ffi::archive_read_support_format_xar(self.handle()) },
             ReadFormat::Zip => unsafe { ffi::archive_read_support_format_zip(self.handle()) },
         };
         match result {
-            ffi::ARCHIVE_OK => Ok(self),
-            _ => ArchiveResult::from(&self as &dyn Handle).map(|_| self),
+            ffi::ARCHIVE_OK => Ok(()),
+            _ => ArchiveResult::from(self as &dyn Handle),
+        }
+    }
+
+    // Charset the archive headers are encoded in, e.g. "UTF-8" or "CP932". Without it libarchive
+    // guesses based on the process locale, which is "C" unless the application called setlocale.
+    pub fn header_charset(mut self, charset: &str) -> Self {
+        self.header_charset = Some(charset.to_string());
+        self
+    }
+
+    // Whether to check entry data against the checksums stored with it, on by default. A mismatch
+    // fails the read with `ArchiveError::ChecksumMismatch`. Only the zip reader can be told to
+    // skip the check, e.g. to salvage what is left of a damaged download.
+    pub fn verify_checksums(mut self, verify: bool) -> Self {
+        self.verify_checksums = verify;
+        self
+    }
+
+    // Whether to read on past the end-of-archive marker of a tar archive, so archives concatenated
+    // with e.g. `cat a.tar b.tar` are read as one, off by default. Gzip streams made of several
+    // members are decompressed as one in any case, so `cat a.tar.gz b.tar.gz` needs just this too.
+    pub fn read_concatenated_archives(mut self, concatenated: bool) -> Self {
+        self.concatenated = concatenated;
+        self
+    }
+
+    // Sets option `key` of the format or filter called `module` to `value`, e.g.
+    // `set_option("iso9660", "joliet", "0")`; an empty `module` sets it for every module knowing
+    // it. Options are passed on when opening, which fails if the module is not enabled or does not
+    // know the option, or with `io::ErrorKind::InvalidInput` if any of the strings holds a NUL
+    // byte. See libarchive's archive_read_set_options(3) for the options there are.
+    pub fn set_option(self, module: &str, key: &str, value: &str) -> Self {
+        self.push_option(OptionScope::Any, module, key, value)
+    }
+
+    // Like `set_option`, only considering format modules.
+    pub fn set_format_option(self, module: &str, key: &str, value: &str) -> Self {
+        self.push_option(OptionScope::Format, module, key, value)
+    }
+
+    // Like `set_option`, only considering filter modules.
+    pub fn set_filter_option(self, module: &str, key: &str, value: &str) -> Self {
+        self.push_option(OptionScope::Filter, module, key, value)
+    }
+
+    // Sets one of the commonly used options, spelled out by the compiler rather than as strings.
+    pub fn read_option(self, option: ReadOption) -> Self {
+        let (module, key, value) = match option {
+            ReadOption::Hdrcharset(charset) => ("", "hdrcharset", charset),
+            ReadOption::Iso9660NoJoliet => ("iso9660", "joliet", "0".to_string()),
+            ReadOption::Iso9660NoRockridge => ("iso9660", "rockridge", "0".to_string()),
+            ReadOption::MtreeCheckFs => ("mtree", "checkfs", "1".to_string()),
+            ReadOption::TarMacExt => ("tar", "mac-ext", "1".to_string()),
+            ReadOption::TarReadConcatenated => {
+                ("tar", "read_concatenated_archives", "1".to_string())
+            }
+            ReadOption::ZipIgnoreCrc32 => ("zip", "ignorecrc32", "1".to_string()),
+            ReadOption::ZipMacExt => ("zip", "mac-ext", "1".to_string()),
+        };
+        self.push_option(OptionScope::Any, module, key, &value)
+    }
+
+    // Adds a passphrase for encrypted zip, 7-Zip and RAR entries. libarchive tries every passphrase
+    // added, in order, until one decrypts the entry. Passphrases containing a NUL byte fail with
+    // `io::ErrorKind::InvalidInput`.
+    pub fn add_passphrase(mut self, passphrase: &str) -> ArchiveResult<Self> {
+        require_version("archive_read_add_passphrase", 3002000)?;
+        match CString::new(passphrase) {
+            Ok(passphrase) => {
+                self.passphrases.push(passphrase);
+                Ok(self)
+            }
+            Err(_) => {
+                let msg = "passphrase contains a NUL byte";
+                Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into())
+            }
+        }
+    }
+
+    // Calls `callback` for a passphrase whenever an encrypted entry is hit and the passphrases
+    // added with `add_passphrase` don't decrypt it, e.g. to prompt the user. It is called again
+    // after a wrong passphrase; `None` gives up on the entry.
+    pub fn passphrase_callback<F>(mut self, callback: F) -> ArchiveResult<Self>
+    where
+        F: FnMut() -> Option<String> + Send + 'static,
+    {
+        require_version("archive_read_set_passphrase_callback", 3002000)?;
+        let mut passphrase = Box::new(PassphraseCallback {
+            callback: Box::new(callback),
+            current: None,
+        });
+        passphrase.register(self.handle);
+        self.passphrase = Some(passphrase);
+        Ok(self)
+    }
+
+    fn push_option(mut self, scope: OptionScope, module: &str, key: &str, value: &str) -> Self {
+        self.options.push(ReaderOption {
+            scope,
+            module: module.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+        self
+    }
+
+    // Accepts only archives of the format with the `ARCHIVE_FORMAT_*` code `code`, failing at the
+    // first header with `ArchiveError::WrongFormat` otherwise. A base code like
+    // `ARCHIVE_FORMAT_TAR` accepts every variant, `ARCHIVE_FORMAT_TAR_USTAR` just ustar. Every
+    // format is enabled for detection, so other formats are told apart instead of failing as
+    // unrecognized.
+    pub fn expect_format(mut self, code: i32) -> ArchiveResult<Self> {
+        self.enable_format(&ReadFormat::All)?;
+        self.expected_format = Some(code);
+        Ok(self)
+    }
+
+    // Limits checked against every header read from the archive.
+    pub fn limits(mut self, limits: HeaderLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    // Caps the decompressed size of each entry. Reading data beyond it fails with
+    // `ArchiveError::LimitExceeded`, whatever size the header declares, so a small archive
+    // expanding into one huge entry is stopped early. Applies to reads through `ArchiveEntry`,
+    // `ReaderHandle::read_all` and extraction with `Disk::write`.
+    pub fn max_entry_size(mut self, max: u64) -> Self {
+        self.max_entry_size = Some(max);
+        self
+    }
+
+    // Calls `callback` with the progress made after every header read and every
+    // `progress_interval` bytes of data decompressed, e.g. to update a progress bar.
+    pub fn on_progress<F: FnMut(Progress) + Send + 'static>(mut self, callback: F) -> Self {
+        self.progress = Some(ProgressCallback {
+            callback: Box::new(callback),
+            interval: self.progress_interval,
+            reported: 0,
+        });
+        self
+    }
+
+    // Decompressed bytes between calls to the `on_progress` callback while reading data, 1 MiB
+    // by default.
+    pub fn progress_interval(mut self, bytes: u64) -> Self {
+        self.progress_interval = bytes;
+        if let Some(ref mut progress) = self.progress {
+            progress.interval = bytes;
         }
+        self
+    }
+
+    // Stops reading with `ArchiveError::Cancelled` once `token` is set, e.g. from another thread.
+    // It is checked before every read from a stream and after every header and block of data,
+    // so setting it interrupts even a long read of a single entry.
+    pub fn cancel_token(mut self, token: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    // Caps the decompressed size of the archive as a whole, headers included, failing with
+    // `Limit::TotalSize` where `max_entry_size` would fail.
+    pub fn max_total_size(mut self, max: u64) -> Self {
+        self.max_total_size = Some(max);
+        self
+    }
+
+    // Caps how many decompressed bytes the archive may yield per byte read from the source,
+    // failing with `Limit::CompressionRatio` where `max_entry_size` would fail. Only checked
+    // after the first MiB of decompressed data, which small archives may well exceed the ratio
+    // with.
+    pub fn max_compression_ratio(mut self, max: u64) -> Self {
+        self.max_ratio = Some(max);
+        self
+    }
+
+    // Buffers to read entry data into with `ReaderHandle::read_all`. Give them back with
+    // `BufferPool::put` when done to have them reused by the next call.
+    pub fn buffer_pool(mut self, pool: BufferPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    // Bytes read from the file at a time by `open_file` and `open_owned_fd`, 10240 by default.
+    // Larger blocks mean fewer reads, which pays off on fast disks and network filesystems.
+    pub fn block_size(mut self, size: usize) -> Self {
+        self.block_size = size;
+        self
+    }
+
+    // Size of the buffer `open_stream` and `open_seekable_stream` read the source into, 8192 bytes
+    // by default. Each read callback hands libarchive at most this much, so streams with high
+    // latency per read do better with a larger one.
+    pub fn stream_buffer_size(mut self, size: usize) -> Self {
+        self.stream_buffer_size = size;
+        self
     }
 
-    pub fn open_file<T: AsRef<Path>>(mut self, file: T) -> ArchiveResult<ReaderHandle> {
+    pub fn open_file<T: AsRef<Path>>(mut self, file: T) -> ArchiveResult<ReaderHandle<'static>> {
+        let _span = span!("open", path = %file.as_ref().display());
         self.check_consumed()?;
+        self.apply_options()?;
 
         let c_file = CString::new(file.as_ref().to_string_lossy().as_bytes()).unwrap();
         unsafe {
-            match ffi::archive_read_open_filename(self.handle(), c_file.as_ptr(), BLOCK_SIZE) {
+            match ffi::archive_read_open_filename(self.handle(), c_file.as_ptr(), self.block_size) {
                 ffi::ARCHIVE_OK => {
                     self.consume();
-                    Ok(ReaderHandle::new_file(self.handle()))
+                    let mut reader = ReaderHandle::new_file(self.handle(), self.limits.clone());
+                    self.configure(&mut reader);
+                    reader.source = Some(file.as_ref().to_path_buf());
+                    reader.reopen = Some(self.reopen());
+                    Ok(reader)
+                }
+                _ => {
+                    let err = ArchiveError::from(&self as &dyn Handle);
+                    event!(ERROR, error = %err, "failed to open archive");
+                    Err(err)
                 }
-                _ => Err(ArchiveError::from(&self as &dyn Handle)),
             }
         }
         // FileReaderHandle::open(self, file)
     }
 
-    pub fn open_stream<T: Any + Read>(mut self, src: T) -> ArchiveResult<ReaderHandle> {
+    // Opens an archive already held in memory, e.g. a download or an asset embedded with
+    // `include_bytes!`. The reader keeps `data` until it is dropped, so borrowed data has to be
+    // `'static`; pass a `Vec<u8>` or other owned buffer otherwise.
+    pub fn open_memory<T: AsRef<[u8]> + Send + 'static>(
+        mut self,
+        data: T,
+    ) -> ArchiveResult<ReaderHandle<'static>> {
+        let _span = span!("open", source = "memory");
+        self.check_consumed()?;
+        self.apply_options()?;
+
+        // Boxed before taking the pointer, so buffers stored inline like arrays don't move.
+        let data: Box<dyn AsRef<[u8]> + Send> = Box::new(data);
+        let buf = (*data).as_ref();
+        unsafe {
+            match ffi::archive_read_open_memory(self.handle(), buf.as_ptr() as _, buf.len()) {
+                ffi::ARCHIVE_OK => {
+                    self.consume();
+                    let mut reader = ReaderHandle::new_file(self.handle(), self.limits.clone());
+                    self.configure(&mut reader);
+                    reader._memory = Some(data);
+                    reader.reopen = Some(self.reopen());
+                    Ok(reader)
+                }
+                _ => {
+                    let err = ArchiveError::from(&self as &dyn Handle);
+                    event!(ERROR, error = %err, "failed to open archive");
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    // Maps `file` into memory and reads the archive from there, sparing the read calls and the
+    // copy into libarchive's buffer. The file must not be truncated or modified while the reader
+    // is alive; accessing pages that went away kills the process with SIGBUS.
+    #[cfg(feature = "mmap")]
+    pub fn open_file_mmap<T: AsRef<Path>>(self, file: T) -> ArchiveResult<ReaderHandle<'static>> {
+        let _span = span!("open", path = %file.as_ref().display(), mmap = true);
+        self.check_consumed()?;
+        let map = unsafe { Mmap::map(&File::open(file)?)? };
+        self.open_memory(map)
+    }
+
+    // Reads the archive from `fd`, e.g. a pipe, socket or inherited descriptor, `block_size` bytes
+    // at a time. The descriptor is neither closed nor owned by the reader and has to stay open
+    // until it is dropped, see `open_owned_fd` otherwise.
+    #[cfg(unix)]
+    pub fn open_fd(mut self, fd: RawFd, block_size: usize) -> ArchiveResult<ReaderHandle<'static>> {
+        let _span = span!("open", fd = fd);
+        self.check_consumed()?;
+        self.apply_options()?;
+
+        unsafe {
+            match ffi::archive_read_open_fd(self.handle(), fd, block_size) {
+                ffi::ARCHIVE_OK => {
+                    self.consume();
+                    let mut reader = ReaderHandle::new_file(self.handle(), self.limits.clone());
+                    self.configure(&mut reader);
+                    Ok(reader)
+                }
+                _ => {
+                    let err = ArchiveError::from(&self as &dyn Handle);
+                    event!(ERROR, error = %err, "failed to open archive");
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    // Like `open_fd`, but the reader takes `fd` and closes it when dropped.
+    #[cfg(unix)]
+    pub fn open_owned_fd(self, fd: OwnedFd) -> ArchiveResult<ReaderHandle<'static>> {
+        let block_size = self.block_size;
+        let mut reader = self.open_fd(fd.as_raw_fd(), block_size)?;
+        reader._fd = Some(fd);
+        Ok(reader)
+    }
+
+    // Opens `file` decoded by an external program, e.g. a decompressor libarchive has no built-in
+    // support for. Unlike with `ReadFilter::Program` the program is supervised, see
+    // `FilterProgram`; its failures and timeouts surface as errors reading headers or data.
+    pub fn open_program<T: AsRef<Path>>(
+        self,
+        file: T,
+        program: &FilterProgram,
+    ) -> ArchiveResult<ReaderHandle<'static>> {
+        let input = File::open(file)?;
+        let output = ProgramOutput::spawn(program, Stdio::from(input))?;
+        self.open_stream(output)
+    }
+
+    pub fn open_stream<'a, T: Read + Send + 'a>(self, src: T) -> ArchiveResult<ReaderHandle<'a>> {
+        let _span = span!("open", source = "stream");
+        let pipe = Pipe::new(src, self.stream_buffer_size);
+        self.open_pipe(pipe)
+    }
+
+    // Reads the archive from a tokio `AsyncRead`, e.g. an upload streamed over hyper, without
+    // buffering it anywhere first. Must be called within the multi-threaded tokio runtime, and
+    // the reader blocks the thread using it while waiting for data; use it in `spawn_blocking`,
+    // or on a worker thread, which then hands its other tasks off to the rest of the runtime.
+    #[cfg(feature = "tokio")]
+    pub fn open_async<'a, R: tokio::io::AsyncRead + Unpin + Send + 'a>(
+        self,
+        src: R,
+    ) -> ArchiveResult<ReaderHandle<'a>> {
+        self.open_stream(AsyncSource::new(src)?)
+    }
+
+    // Like `open_stream`, but libarchive reads straight from the buffer of `src` instead of a copy
+    // of it, saving a copy of every byte read. `stream_buffer_size` has no effect here, the size
+    // of the chunks is up to `src`, e.g. `BufReader::with_capacity`.
+    pub fn open_buffered_stream<'a, T: BufRead + Send + 'a>(
+        self,
+        src: T,
+    ) -> ArchiveResult<ReaderHandle<'a>> {
+        let _span = span!("open", source = "buffered stream");
+        self.open_pipe(Pipe::new_buffered(src))
+    }
+
+    // Reads the archive from chunks of bytes as they arrive, e.g. from an iterator of `Bytes` or
+    // the receiving end of a channel, without gluing them into a `Read` first. Each chunk is
+    // handed to libarchive as is, without copying. The archive ends with the last chunk; for a
+    // channel that is once every sender has been dropped.
+    pub fn open_chunks<'a, I>(self, chunks: I) -> ArchiveResult<ReaderHandle<'a>>
+    where
+        I: IntoIterator,
+        I::IntoIter: Send + 'a,
+        I::Item: AsRef<[u8]> + Send + 'a,
+    {
+        let _span = span!("open", source = "chunks");
+        self.open_pipe(Pipe::new_chunks(chunks.into_iter()))
+    }
+
+    fn open_pipe<'a>(mut self, pipe: Pipe<'a>) -> ArchiveResult<ReaderHandle<'a>> {
         self.check_consumed()?;
+        self.apply_options()?;
 
         unsafe {
-            let mut pipe = Box::new(Pipe::new(src));
+            let mut pipe = Box::new(pipe);
+            pipe.cancel = self.cancel.clone();
             let pipe_ptr: *mut c_void = &mut *pipe as *mut Pipe as *mut c_void;
             match ffi::archive_read_open(
                 self.handle(),
@@ -440,14 +2602,144 @@ impl Builder {
             ) {
                 ffi::ARCHIVE_OK => {
                     self.consume();
-                    Ok(ReaderHandle::new_stream(self.handle(), pipe))
+                    let mut reader =
+                        ReaderHandle::new_stream(self.handle(), pipe, self.limits.clone());
+                    self.configure(&mut reader);
+                    Ok(reader)
                 }
                 _ => {
+                    let err = ArchiveError::from(&self as &dyn Handle);
+                    event!(ERROR, error = %err, "failed to open archive");
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    // Like `open_stream`, but lets libarchive skip and seek in `src`. Skipping avoids reading the
+    // data of entries that aren't read, and seeking lets formats keeping their index at the end,
+    // like zip and 7z, read it from there instead of scanning the whole archive.
+    pub fn open_seekable_stream<'a, T: Read + Seek + Send + 'a>(
+        mut self,
+        src: T,
+    ) -> ArchiveResult<ReaderHandle<'a>> {
+        let _span = span!("open", source = "seekable stream");
+        self.check_consumed()?;
+        self.apply_options()?;
+
+        unsafe {
+            let mut pipe = Box::new(Pipe::new_seekable(src, self.stream_buffer_size));
+            pipe.cancel = self.cancel.clone();
+            let pipe_ptr: *mut c_void = &mut *pipe as *mut Pipe as *mut c_void;
+            // The seek callback has no parameter in `archive_read_open2`, it must be set before.
+            ffi::archive_read_set_seek_callback(self.handle(), Some(stream_seek_callback));
+            match ffi::archive_read_open2(
+                self.handle(),
+                pipe_ptr,
+                None,
+                Some(stream_read_callback),
+                Some(stream_skip_callback),
+                None,
+            ) {
+                ffi::ARCHIVE_OK => {
                     self.consume();
-                    Err(ArchiveError::from(&self as &dyn Handle))
+                    let mut reader =
+                        ReaderHandle::new_stream(self.handle(), pipe, self.limits.clone());
+                    self.configure(&mut reader);
+                    Ok(reader)
+                }
+                _ => {
+                    let err = ArchiveError::from(&self as &dyn Handle);
+                    event!(ERROR, error = %err, "failed to open archive");
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    // Options only reach the formats registered when they are set, so they are applied right
+    // before opening rather than when configured.
+    fn apply_options(&self) -> ArchiveResult<()> {
+        if let Some(ref charset) = self.header_charset {
+            let c_opt = option_string(&format!("hdrcharset={}", charset))?;
+            match unsafe { ffi::archive_read_set_options(self.handle, c_opt.as_ptr()) } {
+                ffi::ARCHIVE_OK | ffi::ARCHIVE_WARN => (),
+                _ => return Err(ArchiveError::from(self as &dyn Handle)),
+            }
+        }
+        if !self.verify_checksums {
+            let c_opt = CString::new("zip:ignorecrc32").unwrap();
+            // Fails when zip support isn't enabled, in which case there is nothing to skip.
+            let res = unsafe { ffi::archive_read_set_options(self.handle, c_opt.as_ptr()) };
+            if res == ffi::ARCHIVE_FATAL {
+                return Err(ArchiveError::from(self as &dyn Handle));
+            }
+        }
+        if self.concatenated {
+            let c_opt = CString::new("tar:read_concatenated_archives").unwrap();
+            // Fails when tar support isn't enabled, in which case there is nothing to continue.
+            let res = unsafe { ffi::archive_read_set_options(self.handle, c_opt.as_ptr()) };
+            if res == ffi::ARCHIVE_FATAL {
+                return Err(ArchiveError::from(self as &dyn Handle));
+            }
+        }
+        for option in &self.options {
+            // An empty module name means every module.
+            let c_module = option_string(&option.module)?;
+            let module = match option.module.is_empty() {
+                true => ptr::null(),
+                false => c_module.as_ptr(),
+            };
+            let c_key = option_string(&option.key)?;
+            let c_value = option_string(&option.value)?;
+            let (key, value) = (c_key.as_ptr(), c_value.as_ptr());
+            let res = unsafe {
+                match option.scope {
+                    OptionScope::Any => {
+                        ffi::archive_read_set_option(self.handle, module, key, value)
+                    }
+                    OptionScope::Format => {
+                        ffi::archive_read_set_format_option(self.handle, module, key, value)
+                    }
+                    OptionScope::Filter => {
+                        ffi::archive_read_set_filter_option(self.handle, module, key, value)
+                    }
                 }
+            };
+            if res != ffi::ARCHIVE_OK {
+                return Err(ArchiveError::from(self as &dyn Handle));
+            }
+        }
+        for passphrase in &self.passphrases {
+            match unsafe { ffi::archive_read_add_passphrase(self.handle, passphrase.as_ptr()) } {
+                ffi::ARCHIVE_OK => (),
+                _ => return Err(ArchiveError::from(self as &dyn Handle)),
             }
         }
+        Ok(())
+    }
+
+    // Hands the settings kept by the reader over to it, once it is opened.
+    fn configure(&mut self, reader: &mut ReaderHandle) {
+        reader.max_entry_size = self.max_entry_size;
+        reader.max_total_size = self.max_total_size;
+        reader.max_ratio = self.max_ratio;
+        reader.cancel = self.cancel.clone();
+        reader.progress = self.progress.take().map(RefCell::new);
+        reader.expected_format = self.expected_format;
+        reader.pool = self.pool.take();
+        reader.passphrase = self.passphrase.take();
+    }
+
+    fn reopen(&self) -> Reopen {
+        Reopen {
+            header_charset: self.header_charset.clone(),
+            verify_checksums: self.verify_checksums,
+            concatenated: self.concatenated,
+            options: self.options.clone(),
+            passphrases: self.passphrases.clone(),
+            block_size: self.block_size,
+        }
     }
 
     fn check_consumed(&self) -> ArchiveResult<()> {
@@ -489,6 +2781,23 @@ impl Default for Builder {
             Builder {
                 handle,
                 consumed: false,
+                header_charset: None,
+                verify_checksums: true,
+                concatenated: false,
+                options: Vec::new(),
+                passphrases: Vec::new(),
+                passphrase: None,
+                limits: Default::default(),
+                max_entry_size: None,
+                max_total_size: None,
+                max_ratio: None,
+                cancel: None,
+                progress: None,
+                progress_interval: PROGRESS_INTERVAL,
+                expected_format: None,
+                pool: None,
+                block_size: BLOCK_SIZE,
+                stream_buffer_size: STREAM_BUFFER_SIZE,
             }
         }
     }
@@ -0,0 +1,57 @@
+use std::io::{self, Read};
+
+use libarchive3_sys::ffi;
+use libc::c_void;
+
+use super::{Builder, ReaderHandle};
+use crate::archive::{ReadFilter, ReadFormat};
+use crate::error::ArchiveResult;
+
+// Decompresses `source` through every filter libarchive recognizes, e.g. gzip, xz or zstd, without
+// looking for an archive inside: a `.tar.gz` comes out as the plain tar. Input no filter
+// recognizes comes out unchanged.
+pub fn decompress<'a, R: Read + Send + 'a>(source: R) -> ArchiveResult<impl Read + 'a> {
+    let reader = Builder::new()
+        .support_filter(ReadFilter::All)?
+        .support_format(ReadFormat::Raw)?
+        .open_stream(source)?;
+    Ok(Decompressed {
+        reader,
+        started: false,
+    })
+}
+
+// The data of the single entry the raw format reports.
+struct Decompressed<'a> {
+    reader: ReaderHandle<'a>,
+    started: bool,
+}
+
+impl Read for Decompressed<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.started {
+            self.started = true;
+            let found = self
+                .reader
+                .read_header()
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            if !found {
+                return Ok(0);
+            }
+        }
+        let size = unsafe {
+            ffi::archive_read_data(
+                self.reader.handle,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len(),
+            )
+        };
+        if size < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                self.reader.data_error(),
+            ));
+        }
+        Ok(size as usize)
+    }
+}
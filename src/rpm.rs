@@ -0,0 +1,43 @@
+use std::io::{self, Read};
+use std::path::Path;
+
+use libarchive3_sys::ffi;
+
+use crate::archive::{Handle, ReadFilter, ReadFormat};
+use crate::error::ArchiveResult;
+use crate::reader::{ArchiveIterator, Builder, ReaderHandle};
+
+// An RPM package is a lead and two headers in front of a compressed cpio archive, its payload,
+// holding the packaged files. libarchive's rpm filter skips the framing, so the payload is read
+// like any other archive. Paths are as rpm stores them, e.g. `./usr/bin/hello`.
+
+// Entries of the payload of the package at `path`.
+pub fn open_rpm<T: AsRef<Path>>(path: T) -> ArchiveResult<ArchiveIterator<'static>> {
+    check_rpm(builder()?.open_file(path)?)
+}
+
+// Entries of the payload of the package read from `src`.
+pub fn open_rpm_stream<'a, T: Read + Send + 'a>(src: T) -> ArchiveResult<ArchiveIterator<'a>> {
+    check_rpm(builder()?.open_stream(src)?)
+}
+
+fn builder() -> ArchiveResult<Builder> {
+    Builder::new()
+        .support_filter(ReadFilter::All)?
+        .support_format(ReadFormat::Cpio)
+}
+
+// Filters are picked when the archive is opened, so a plain cpio archive can be told apart before
+// reading any entry.
+fn check_rpm(reader: ReaderHandle<'_>) -> ArchiveResult<ArchiveIterator<'_>> {
+    let is_rpm = unsafe {
+        let handle = reader.handle();
+        (0..ffi::archive_filter_count(handle))
+            .any(|i| ffi::archive_filter_code(handle, i) == ffi::ARCHIVE_FILTER_RPM)
+    };
+    if !is_rpm {
+        let msg = "not an RPM package";
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg).into());
+    }
+    Ok(reader.into_iter())
+}
@@ -0,0 +1,85 @@
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::archive::ExtractOptions;
+use crate::error::ArchiveResult;
+use crate::probe::{probe, ArchiveInfo};
+use crate::reader::{ArchiveIterator, Builder, ReaderHandle};
+use crate::writer::Disk;
+
+// An archive file with its format detected up front. Every operation reopens the file, so they
+// may be called any number of times and in any order.
+pub struct Archive {
+    path: PathBuf,
+    info: ArchiveInfo,
+}
+
+impl Archive {
+    // Opens the archive at `path`, failing if no supported format or filter recognises it. A
+    // zero-byte file, e.g. an empty upload, opens as an archive without entries.
+    pub fn open<T: AsRef<Path>>(path: T) -> ArchiveResult<Archive> {
+        let info = probe(path.as_ref())?;
+        Ok(Archive {
+            path: path.as_ref().to_path_buf(),
+            info,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn info(&self) -> &ArchiveInfo {
+        &self.info
+    }
+
+    pub fn entries(&self) -> ArchiveResult<ArchiveIterator<'static>> {
+        Ok(self.reader()?.into_iter())
+    }
+
+    // Extracts every entry below `dir`, restoring modification times. Absolute and `..` paths are
+    // rewritten to stay inside `dir`, and entries that would escape it through a symlink are
+    // refused. Returns the bytes written.
+    pub fn extract_to<T: AsRef<Path>>(&self, dir: T) -> ArchiveResult<usize> {
+        let mut disk = Disk::new();
+        disk.set_sanitize_paths(true);
+        // Pathnames are joined onto `dir`, which may itself be absolute, so
+        // `secure_no_absolute_paths` can't be used.
+        let options = ExtractOptions::new()
+            .preserve_times(true)
+            .secure_symlinks(true)
+            .secure_no_dot_dot(true);
+        disk.set_options(&options)?;
+        let mut reader = self.reader()?;
+        let dir = dir.as_ref().to_string_lossy();
+        let bytes = disk.write(&mut reader, Some(&dir))?;
+        disk.close()?;
+        Ok(bytes)
+    }
+
+    // Contents of the first entry named `name`, or `None` if there is no such entry.
+    pub fn read_entry(&self, name: &str) -> ArchiveResult<Option<Vec<u8>>> {
+        for entry in self.entries()? {
+            let mut entry = entry?;
+            if entry.pathname().as_deref() == Some(name) {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data)?;
+                return Ok(Some(data));
+            }
+        }
+        Ok(None)
+    }
+
+    // Reads every entry through to the end without keeping the data, failing on the first
+    // damaged header or entry, e.g. a checksum mismatch.
+    pub fn verify(&self) -> ArchiveResult<()> {
+        for entry in self.entries()? {
+            io::copy(&mut entry?, &mut io::sink())?;
+        }
+        Ok(())
+    }
+
+    fn reader(&self) -> ArchiveResult<ReaderHandle<'static>> {
+        Builder::new().support_all()?.open_file(&self.path)
+    }
+}
@@ -0,0 +1,127 @@
+// Entries of an archive as a `futures::Stream`, for async applications. libarchive blocks while
+// reading, so the reader lives on a thread of its own and the stream only waits for its answers
+// instead of blocking a thread of the executor.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::task::{Context, Poll};
+use std::thread;
+
+use futures::channel::oneshot;
+use futures::{ready, Stream};
+
+use crate::error::{ArchiveError, ArchiveResult};
+use crate::reader::{EntryMetadata, ReaderHandle};
+
+enum Command {
+    Next(oneshot::Sender<Option<ArchiveResult<EntryMetadata>>>),
+    ReadChunk(oneshot::Sender<ArchiveResult<Option<Vec<u8>>>>),
+    ReadData(oneshot::Sender<ArchiveResult<Vec<u8>>>),
+}
+
+// Yields the metadata of each entry, e.g. `while let Some(entry) = stream.next().await`. The data
+// of the entry last yielded is read with `read_chunk` or `read_data`; data left unread is skipped.
+// The stream ends after an error reading a header.
+pub struct ArchiveEntryStream {
+    commands: Sender<Command>,
+    pending: Option<oneshot::Receiver<Option<ArchiveResult<EntryMetadata>>>>,
+    done: bool,
+}
+
+impl ArchiveEntryStream {
+    // Opens the reader by calling `open` on the reading thread, so opening doesn't block the
+    // caller either, e.g. `ArchiveEntryStream::open(move || Builder::new().support_all()?
+    // .open_file(path))`.
+    pub async fn open<F>(open: F) -> ArchiveResult<Self>
+    where
+        F: FnOnce() -> ArchiveResult<ReaderHandle<'static>> + Send + 'static,
+    {
+        let (commands, receiver) = mpsc::channel();
+        let (opened, result) = oneshot::channel();
+        thread::spawn(move || match open() {
+            Ok(reader) => {
+                let _ = opened.send(Ok(()));
+                serve(reader, receiver);
+            }
+            Err(err) => {
+                let _ = opened.send(Err(err));
+            }
+        });
+        result.await.map_err(|_| reader_exited())??;
+        Ok(ArchiveEntryStream {
+            commands,
+            pending: None,
+            done: false,
+        })
+    }
+
+    // The next chunk of the current entry's data, with holes of sparse entries filled in with
+    // zeros, or `None` once all of it has been read. Subject to `Builder::max_entry_size`.
+    pub async fn read_chunk(&mut self) -> ArchiveResult<Option<Vec<u8>>> {
+        let (reply, answer) = oneshot::channel();
+        self.send(Command::ReadChunk(reply))?;
+        answer.await.map_err(|_| reader_exited())?
+    }
+
+    // The rest of the current entry's data, see `ReaderHandle::read_entry_data`.
+    pub async fn read_data(&mut self) -> ArchiveResult<Vec<u8>> {
+        let (reply, answer) = oneshot::channel();
+        self.send(Command::ReadData(reply))?;
+        answer.await.map_err(|_| reader_exited())?
+    }
+
+    fn send(&self, command: Command) -> ArchiveResult<()> {
+        self.commands.send(command).map_err(|_| reader_exited())
+    }
+}
+
+impl Stream for ArchiveEntryStream {
+    type Item = ArchiveResult<EntryMetadata>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        if self.pending.is_none() {
+            let (reply, answer) = oneshot::channel();
+            if let Err(err) = self.send(Command::Next(reply)) {
+                self.done = true;
+                return Poll::Ready(Some(Err(err)));
+            }
+            self.pending = Some(answer);
+        }
+        let answer = ready!(Pin::new(self.pending.as_mut().unwrap()).poll(cx));
+        self.pending = None;
+        let next = answer.unwrap_or_else(|_| Some(Err(reader_exited())));
+        self.done = !matches!(next, Some(Ok(_)));
+        Poll::Ready(next)
+    }
+}
+
+// Runs on the reading thread until the stream is dropped.
+fn serve(mut reader: ReaderHandle<'static>, commands: Receiver<Command>) {
+    let mut position = 0;
+    for command in commands {
+        match command {
+            Command::Next(reply) => {
+                position = 0;
+                let _ = reply.send(reader.next_metadata().transpose());
+            }
+            Command::ReadChunk(reply) => {
+                let _ = reply.send(reader.read_chunk(&mut position));
+            }
+            Command::ReadData(reply) => {
+                let _ = reply.send(reader.read_entry_data());
+            }
+        }
+    }
+}
+
+fn reader_exited() -> ArchiveError {
+    ArchiveError::Io(io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "archive reader thread exited",
+    ))
+}
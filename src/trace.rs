@@ -0,0 +1,32 @@
+// Instrumentation hooks for the `tracing` feature. The macros expand to nothing when the feature is
+// disabled so call sites don't need their own cfg attributes; their arguments are not evaluated in
+// that case.
+
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($name:expr) => {
+        tracing::debug_span!($name).entered()
+    };
+    ($name:expr, $($field:tt)*) => {
+        tracing::debug_span!($name, $($field)*).entered()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($($arg:tt)*) => {
+        ()
+    };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! event {
+    ($level:ident, $($arg:tt)*) => {
+        tracing::event!(tracing::Level::$level, $($arg)*)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! event {
+    ($($arg:tt)*) => {};
+}
@@ -1,30 +1,907 @@
+use std::cell::{Cell, RefCell};
+#[cfg(feature = "manifest")]
+use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap};
 use std::default::Default;
-use std::ffi::CString;
-use std::path::Path;
+use std::ffi::{CStr, CString};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::process::Stdio;
 use std::ptr;
+use std::time::SystemTime;
 
 use libarchive3_sys::ffi;
+use libc::{c_char, c_void};
 
-use crate::archive::{Entry, ExtractOptions, Handle, WriteFilter, WriteFormat};
+use crate::archive::{
+    option_string, BootEmulation, Decision, Entry, EntryOrder, ExtractOptions, FileType,
+    FollowSymlinks, Handle, IsoBoot, Ownership, Progress, ReadFormat, WriteFilter, WriteFormat,
+};
+use crate::copy;
 use crate::error::{ArchiveError, ArchiveResult};
-use crate::reader::{ReaderEntryHandle, ReaderHandle};
+#[cfg(feature = "manifest")]
+use crate::manifest::Manifest;
+use crate::paths;
+#[cfg(unix)]
+use crate::program::FilterProgram;
+use crate::program::ProgramInput;
+use crate::reader::{self, ArchiveEntry, ReaderEntryHandle, ReaderHandle};
 
 pub struct Writer {
     handle: *mut ffi::Struct_archive,
+    progress: Option<Box<dyn Progress>>,
+    detect_hardlinks: bool,
+    follow_symlinks: FollowSymlinks,
+    ownership: Ownership,
+    order: EntryOrder,
+    links: Option<LinkResolver>,
+    program: Option<ProgramInput>,
+}
+
+// Remembers the (device, inode) pairs of files added from disk, so further links to the same file
+// can be written as hardlink entries.
+struct LinkResolver {
+    handle: *mut ffi::Struct_archive_entry_linkresolver,
+}
+
+// An entry built from scratch rather than read from an archive.
+struct NewEntry {
+    handle: *mut ffi::Struct_archive_entry,
+}
+
+// Walks a file or directory tree with libarchive's disk reader.
+struct DiskWalker {
+    handle: *mut ffi::Struct_archive,
+    entry: NewEntry,
+}
+
+// State of previously archived files, read from the mtree manifest written by
+// `Writer::append_incremental`. An empty baseline treats every file as changed.
+#[derive(Debug, Default)]
+pub struct Baseline {
+    files: HashMap<String, FileState>,
+}
+
+#[derive(Debug, PartialEq)]
+struct FileState {
+    size: i64,
+    mtime: (i64, i64),
+    sha256: Option<Vec<u8>>,
+}
+
+type CreateHook = Box<dyn FnMut(&Path, &ReaderEntryHandle) -> Decision>;
+type NameLookup = Box<dyn FnMut(&str, i64) -> i64>;
+
+unsafe extern "C" fn name_lookup_callback(data: *mut c_void, name: *const c_char, id: i64) -> i64 {
+    let lookup: &mut NameLookup = &mut *(data as *mut NameLookup);
+    if name.is_null() {
+        return id;
+    }
+    lookup(&CStr::from_ptr(name).to_string_lossy(), id)
+}
+
+// libarchive calls this once the lookup is replaced or the handle freed.
+unsafe extern "C" fn name_lookup_cleanup(data: *mut c_void) {
+    drop(Box::from_raw(data as *mut NameLookup));
 }
 
 pub struct Disk {
     handle: *mut ffi::Struct_archive,
+    skip_unreadable: bool,
+    skipped: RefCell<Vec<SkippedEntry>>,
+    permission_mask: u32,
+    fsync: bool,
+    sanitize_paths: bool,
+    progress: RefCell<Option<Box<dyn Progress>>>,
+    create_hook: RefCell<Option<CreateHook>>,
+    // `ExtractOptions::flags` last set, which decide whether data may bypass libarchive.
+    extract_flags: Cell<i32>,
+    #[cfg(feature = "manifest")]
+    manifest: RefCell<Option<Manifest>>,
+    // Manifest appended to by a resumable extraction, and the archive paths it already lists.
+    #[cfg(feature = "manifest")]
+    checkpoint: RefCell<Option<Manifest>>,
+    #[cfg(feature = "manifest")]
+    checkpointed: HashSet<String>,
+}
+
+// An entry left out of an extraction in skip-unreadable mode.
+#[derive(Debug)]
+pub struct SkippedEntry {
+    pub pathname: String,
+    pub error: ArchiveError,
 }
 
 pub struct Builder {
     handle: *mut ffi::Struct_archive,
     consumed: bool,
+    header_charset: Option<String>,
+    #[cfg(feature = "threads")]
+    threads: Option<u32>,
 }
 
 impl Writer {
     pub fn new(handle: *mut ffi::Struct_archive) -> Self {
-        Writer { handle }
+        Writer {
+            handle,
+            progress: None,
+            detect_hardlinks: true,
+            follow_symlinks: FollowSymlinks::Never,
+            ownership: Ownership::Preserve,
+            order: EntryOrder::Walk,
+            links: None,
+            program: None,
+        }
+    }
+
+    // Store files reached through more than one hard link once, and later links as hardlink
+    // entries, like tar does. On by default; when off every link is stored with its own copy of
+    // the data.
+    pub fn set_detect_hardlinks(&mut self, detect: bool) {
+        self.detect_hardlinks = detect;
+    }
+
+    // Owner information to store for entries added by `write_entries`, `append_path` and
+    // `append_incremental`.
+    pub fn set_ownership(&mut self, ownership: Ownership) {
+        self.ownership = ownership;
+    }
+
+    // Order in which `append_path` and `append_incremental` write the files found. Sorting makes
+    // the output independent of the order the filesystem lists directories in, at the cost of
+    // holding every entry's metadata in memory until the walk is done.
+    pub fn set_order(&mut self, order: EntryOrder) {
+        self.order = order;
+    }
+
+    // How symlinks are treated by `append_path` and `append_incremental`.
+    pub fn set_follow_symlinks(&mut self, follow: FollowSymlinks) {
+        self.follow_symlinks = follow;
+    }
+
+    // Reports each written entry and its data to `progress`.
+    pub fn set_progress<P: Progress + 'static>(&mut self, progress: P) {
+        self.progress = Some(Box::new(progress));
+    }
+
+    pub fn write_header<E: Entry>(&mut self, entry: &E) -> ArchiveResult<()> {
+        self.write_raw_header(unsafe { entry.entry() })
+    }
+
+    fn write_raw_header(&mut self, entry: *mut ffi::Struct_archive_entry) -> ArchiveResult<()> {
+        unsafe {
+            match ffi::archive_write_header(self.handle, entry) {
+                ffi::ARCHIVE_OK => Ok(()),
+                _ => ArchiveResult::from(self as &dyn Handle),
+            }
+        }
+    }
+
+    // Writes data for the entry whose header was written last. Formats that need the size up
+    // front truncate anything beyond the size in the header.
+    pub fn write_data(&mut self, data: &[u8]) -> ArchiveResult<usize> {
+        let written =
+            unsafe { ffi::archive_write_data(self.handle, data.as_ptr() as *const _, data.len()) };
+        if written < 0 {
+            return Err(ArchiveError::from(self as &dyn Handle));
+        }
+        if let Some(ref mut progress) = self.progress {
+            progress.bytes_processed(written as u64);
+        }
+        Ok(written as usize)
+    }
+
+    // Writes a regular file entry for each `(path, data)` pair, e.g. `("report.html", &html[..])`,
+    // without staging anything on disk. Each reader is buffered in memory since most formats need
+    // the entry size before its data. Entries get mode 0644 and the current time.
+    pub fn write_entries<I, P, R>(&mut self, entries: I) -> ArchiveResult<()>
+    where
+        I: IntoIterator<Item = (P, R)>,
+        P: AsRef<Path>,
+        R: Read,
+    {
+        for (path, mut data) in entries {
+            let mut buf = Vec::new();
+            data.read_to_end(&mut buf)?;
+            let mut entry = self.new_entry(path.as_ref(), FileType::RegularFile, 0o644);
+            entry.set_size(buf.len() as i64);
+
+            let pathname = entry.pathname().to_string();
+            if let Some(ref mut progress) = self.progress {
+                progress.entry_started(&pathname, Some(buf.len() as u64));
+            }
+            self.write_header(&entry)?;
+            self.write_all(&buf)?;
+            if let Some(ref mut progress) = self.progress {
+                progress.entry_finished(&pathname);
+            }
+        }
+        Ok(())
+    }
+
+    // Copies every remaining entry of `reader`, headers and data, converting between formats on the
+    // way, e.g. from a zip into a tar.xz. Returns the number of entries written.
+    pub fn append_archive(&mut self, reader: &mut ReaderHandle) -> ArchiveResult<usize> {
+        self.append_archive_with(reader, |_| ())
+    }
+
+    // Like `append_archive`, passing each entry to `transform` before it is written. It may change
+    // any metadata through the `Entry` setters but the size, e.g. to move entries below another
+    // directory, reset owners or clamp modification times. `set_ownership` applies afterwards.
+    pub fn append_archive_with<F>(
+        &mut self,
+        reader: &mut ReaderHandle,
+        mut transform: F,
+    ) -> ArchiveResult<usize>
+    where
+        F: FnMut(&mut ReaderEntryHandle),
+    {
+        let mut count = 0;
+        while let Some(entry) = reader.read_next()? {
+            transform(entry);
+            self.apply_ownership(unsafe { entry.entry() });
+            let pathname = entry.pathname().to_string();
+            let total = match unsafe { ffi::archive_entry_size_is_set(entry.entry()) } {
+                0 => None,
+                _ => Some(entry.size() as u64),
+            };
+            if let Some(ref mut progress) = self.progress {
+                progress.entry_started(&pathname, total);
+            }
+            self.write_header(entry)?;
+            self.copy_data(reader)?;
+            if let Some(ref mut progress) = self.progress {
+                progress.entry_finished(&pathname);
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // Writes the data of the current entry of `reader`. Holes in sparse entries are written out
+    // as zeros, as formats other than tar and pax can't represent them.
+    fn copy_data(&mut self, reader: &ReaderHandle) -> ArchiveResult<()> {
+        const ZEROS: [u8; 4096] = [0; 4096];
+        let mut position = 0;
+        loop {
+            let mut buff = ptr::null();
+            let mut size = 0;
+            let mut offset = 0;
+            let res = unsafe {
+                ffi::archive_read_data_block(reader.handle(), &mut buff, &mut size, &mut offset)
+            };
+            match res {
+                ffi::ARCHIVE_EOF => return Ok(()),
+                ffi::ARCHIVE_OK => {
+                    let offset = offset as u64;
+                    reader.check_data(offset + size as u64)?;
+                    while position < offset {
+                        let n = (offset - position).min(ZEROS.len() as u64);
+                        self.write_all(&ZEROS[..n as usize])?;
+                        position += n;
+                    }
+                    // The block ending a sparse entry with a hole may be empty, without a buffer.
+                    if size > 0 {
+                        let data = unsafe { std::slice::from_raw_parts(buff as *const u8, size) };
+                        self.write_all(data)?;
+                    }
+                    position += size as u64;
+                }
+                _ => return Err(reader.data_error()),
+            }
+        }
+    }
+
+    // Writes a directory that does not need to exist on disk.
+    pub fn write_directory<P: AsRef<Path>>(&mut self, path: P, perm: u32) -> ArchiveResult<()> {
+        let entry = self.new_entry(path.as_ref(), FileType::Directory, perm);
+        self.write_header(&entry)
+    }
+
+    // Writes a symlink to `target` that does not need to exist on disk.
+    pub fn write_symlink<P: AsRef<Path>, T: AsRef<Path>>(
+        &mut self,
+        path: P,
+        target: T,
+    ) -> ArchiveResult<()> {
+        let entry = self.new_entry(path.as_ref(), FileType::SymbolicLink, 0o777);
+        let target = path_string(target.as_ref())?;
+        unsafe { ffi::archive_entry_set_symlink(entry.entry(), target.as_ptr()) };
+        self.write_header(&entry)
+    }
+
+    // Writes a device node, `filetype` being `FileType::CharacterDevice` or
+    // `FileType::BlockDevice`. Creating the node on disk would need root, so this is how the
+    // nodes of an initramfs get into it: `WriteFormat::CpioNewc` with `Ownership::Fixed` for uid
+    // and gid 0, the nodes the kernel needs before mounting devtmpfs (e.g. `dev/console` as
+    // character device 5, 1), then the tree itself from `append_path`.
+    pub fn write_device<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        filetype: FileType,
+        major: u64,
+        minor: u64,
+        perm: u32,
+    ) -> ArchiveResult<()> {
+        match filetype {
+            FileType::CharacterDevice | FileType::BlockDevice => (),
+            _ => {
+                let msg = "device entries must be character or block devices";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into());
+            }
+        }
+        let entry = self.new_entry(path.as_ref(), filetype, perm);
+        unsafe {
+            ffi::archive_entry_set_rdevmajor(entry.entry(), major as _);
+            ffi::archive_entry_set_rdevminor(entry.entry(), minor as _);
+        }
+        self.write_header(&entry)
+    }
+
+    // An entry without data, modified now and owned as set with `set_ownership`.
+    fn new_entry(&self, path: &Path, filetype: FileType, perm: u32) -> NewEntry {
+        let nlink = match filetype {
+            FileType::Directory => 2,
+            _ => 1,
+        };
+        let mut entry = NewEntry::new();
+        entry.set_pathname(&path.to_path_buf());
+        unsafe { ffi::archive_entry_set_nlink(entry.entry(), nlink) };
+        entry.set_filetype(filetype);
+        entry.set_perm(perm);
+        entry.set_size(0);
+        entry.set_mtime(SystemTime::now());
+        self.apply_ownership(entry.handle);
+        entry
+    }
+
+    // Writes the `//` member GNU ar keeps names longer than 15 bytes in. With
+    // `WriteFormat::ArSvr4` it has to come before any member with such a name, and writing one
+    // without it fails. ar members are named by the last component of their path, so only that
+    // is recorded. Does nothing if none of `names` is long.
+    pub fn write_ar_name_table<I, S>(&mut self, names: I) -> ArchiveResult<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut table = String::new();
+        for name in names {
+            let name = name.as_ref().trim_end_matches('/');
+            let name = name.rsplit('/').next().unwrap_or(name);
+            if name.len() > 15 {
+                table.push_str(name);
+                table.push_str("/\n");
+            }
+        }
+        if table.is_empty() {
+            return Ok(());
+        }
+        let mut entry = NewEntry::new();
+        entry.set_pathname(&PathBuf::from("//"));
+        entry.set_filetype(FileType::RegularFile);
+        entry.set_size(table.len() as i64);
+        self.write_header(&entry)?;
+        self.write_all(table.as_bytes())
+    }
+
+    // Adds `path` and, if it is a directory, everything below it. Entries are named by the paths
+    // found while walking, so a relative `path` gives relative names. Returns the number of
+    // entries written.
+    pub fn append_path<T: AsRef<Path>>(&mut self, path: T) -> ArchiveResult<usize> {
+        let mut count = 0;
+        self.walk(path.as_ref(), |writer, entry, walker| {
+            count += 1;
+            writer.append_walked(entry, walker, None)
+        })?;
+        Ok(count)
+    }
+
+    // Like `append_path`, but only adds files that differ from `baseline` in size, modification
+    // time or, when built with both the `digest` and `manifest` features, SHA-256 checksum. An
+    // mtree manifest of every file found is written to `manifest`, to be loaded with
+    // `Baseline::open` as the baseline of the next run. Returns the number of entries written to
+    // the archive.
+    pub fn append_incremental<T: AsRef<Path>, M: AsRef<Path>>(
+        &mut self,
+        path: T,
+        baseline: &Baseline,
+        manifest: M,
+    ) -> ArchiveResult<usize> {
+        let builder = Builder::new();
+        builder.set_format(WriteFormat::Mtree)?;
+        let c_opt = CString::new("mtree:sha256").unwrap();
+        match unsafe { ffi::archive_write_set_options(builder.handle, c_opt.as_ptr()) } {
+            ffi::ARCHIVE_OK | ffi::ARCHIVE_WARN => (),
+            _ => return Err(ArchiveError::from(&builder as &dyn Handle)),
+        }
+        let mut manifest = builder.open_file(manifest)?;
+        // The manifest records every path with its own size, for comparing on the next run.
+        manifest.set_detect_hardlinks(false);
+
+        let mut count = 0;
+        self.walk(path.as_ref(), |writer, entry, walker| {
+            if baseline.changed(&ReaderEntryHandle::new(entry))? {
+                count += 1;
+                writer.append_walked(entry, walker, Some(&mut manifest))
+            } else {
+                manifest.append_walked(entry, walker, None)
+            }
+        })?;
+        manifest.close()?;
+        Ok(count)
+    }
+
+    // Walks `path`, passing each entry found to `visit` in the configured order. Entries are
+    // visited as they are found unless they need sorting; only then `visit` gets the walker to
+    // stream data from, otherwise the data has to be read from the entry's source path.
+    fn walk<F>(&mut self, path: &Path, mut visit: F) -> ArchiveResult<()>
+    where
+        F: FnMut(
+            &mut Writer,
+            *mut ffi::Struct_archive_entry,
+            Option<&mut DiskWalker>,
+        ) -> ArchiveResult<()>,
+    {
+        let mut walker = DiskWalker::open(path, self.follow_symlinks)?;
+        if let EntryOrder::Walk = self.order {
+            while walker.next()? {
+                let entry = walker.entry.handle;
+                visit(self, entry, Some(&mut walker))?;
+            }
+            return Ok(());
+        }
+
+        let mut entries = Vec::new();
+        while walker.next()? {
+            entries.push(NewEntry::clone_from(walker.entry.handle));
+        }
+        let by_path = |a: &NewEntry, b: &NewEntry| pathname_bytes(a).cmp(pathname_bytes(b));
+        match self.order {
+            EntryOrder::Walk => (),
+            EntryOrder::Path => entries.sort_by(by_path),
+            EntryOrder::Custom(ref compare) => entries.sort_by(|a, b| {
+                let a = String::from_utf8_lossy(pathname_bytes(a));
+                let b = String::from_utf8_lossy(pathname_bytes(b));
+                compare(&a, &b)
+            }),
+        }
+        for entry in &entries {
+            visit(self, entry.handle, None)?;
+        }
+        Ok(())
+    }
+
+    // Writes an entry found on disk and its data, also to `copy` if given. The data is streamed
+    // from `walker` when the entry is its current one, or read from the entry's source path.
+    fn append_walked(
+        &mut self,
+        found: *mut ffi::Struct_archive_entry,
+        walker: Option<&mut DiskWalker>,
+        mut copy: Option<&mut Writer>,
+    ) -> ArchiveResult<()> {
+        let found_entry = ReaderEntryHandle::new(found);
+        let pathname = found_entry.pathname().to_string();
+        if let Some(ref mut progress) = self.progress {
+            progress.entry_started(&pathname, Some(found_entry.size() as u64));
+        }
+        self.apply_ownership(found);
+        #[cfg(feature = "symlink-type")]
+        classify_symlink(found);
+        // The copy gets the entry as found, before it may be turned into a hardlink.
+        if let Some(ref mut copy) = copy {
+            copy.write_raw_header(found)?;
+        }
+        let is_file = matches!(found_entry.filetype(), FileType::RegularFile);
+        let mut own_data = false;
+        if self.detect_hardlinks {
+            // The resolver takes ownership of what it is given and may hold an entry back until
+            // its last link has been seen, handing back one deferred earlier instead.
+            let current = unsafe { ffi::archive_entry_clone(found) };
+            let (entry, spare) = self.linkify(current);
+            for entry in [entry, spare] {
+                if entry.is_null() {
+                    continue;
+                }
+                let result = self.write_raw_header(entry).and_then(|()| {
+                    if entry == current {
+                        own_data = is_file && unsafe { ffi::archive_entry_size(entry) } > 0;
+                        Ok(())
+                    } else {
+                        self.write_source_data(entry)
+                    }
+                });
+                unsafe { ffi::archive_entry_free(entry) };
+                result?;
+            }
+        } else {
+            self.write_raw_header(found)?;
+            own_data = is_file;
+        }
+        if is_file && (own_data || copy.is_some()) {
+            let mut sink = |data: &[u8]| {
+                if own_data {
+                    self.write_all(data)?;
+                }
+                if let Some(ref mut copy) = copy {
+                    copy.write_all(data)?;
+                }
+                Ok(())
+            };
+            match walker {
+                Some(walker) => walker.read_data(&mut sink)?,
+                None => read_source(found, &mut sink)?,
+            }
+        }
+        if let Some(ref mut progress) = self.progress {
+            progress.entry_finished(&pathname);
+        }
+        Ok(())
+    }
+
+    fn apply_ownership(&self, entry: *mut ffi::Struct_archive_entry) {
+        unsafe {
+            if let Ownership::Fixed { uid, gid } = self.ownership {
+                ffi::archive_entry_set_uid(entry, uid);
+                ffi::archive_entry_set_gid(entry, gid);
+            }
+            if self.ownership != Ownership::Preserve {
+                ffi::archive_entry_set_uname(entry, ptr::null());
+                ffi::archive_entry_set_gname(entry, ptr::null());
+            }
+        }
+    }
+
+    fn linkify(
+        &mut self,
+        mut entry: *mut ffi::Struct_archive_entry,
+    ) -> (
+        *mut ffi::Struct_archive_entry,
+        *mut ffi::Struct_archive_entry,
+    ) {
+        let mut spare = ptr::null_mut();
+        let format = unsafe { ffi::archive_format(self.handle) };
+        let links = self.links.get_or_insert_with(|| LinkResolver::new(format));
+        unsafe { ffi::archive_entry_linkify(links.handle, &mut entry, &mut spare) };
+        (entry, spare)
+    }
+
+    // Writes the data of an entry that is no longer current in the disk walk.
+    fn write_source_data(&mut self, entry: *mut ffi::Struct_archive_entry) -> ArchiveResult<()> {
+        unsafe {
+            if ffi::archive_entry_filetype(entry) as u32 != ffi::AE_IFREG
+                || ffi::archive_entry_size(entry) == 0
+            {
+                return Ok(());
+            }
+        }
+        read_source(entry, |data| self.write_all(data))
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> ArchiveResult<()> {
+        let mut offset = 0;
+        while offset < data.len() {
+            offset += self.write_data(&data[offset..])?;
+        }
+        Ok(())
+    }
+
+    // Finishes the archive, writing any trailer and flushing buffered data. Dropping the writer
+    // closes it too, but without a way to report errors.
+    pub fn close(&mut self) -> ArchiveResult<()> {
+        // Entries the link resolver is still holding back have to be written before the trailer.
+        if let Some(links) = self.links.take() {
+            loop {
+                let mut entry = ptr::null_mut();
+                let mut spare = ptr::null_mut();
+                unsafe { ffi::archive_entry_linkify(links.handle, &mut entry, &mut spare) };
+                if entry.is_null() {
+                    break;
+                }
+                let result = self
+                    .write_raw_header(entry)
+                    .and_then(|()| self.write_source_data(entry));
+                unsafe { ffi::archive_entry_free(entry) };
+                result?;
+            }
+        }
+        let result = unsafe { ffi::archive_write_close(self.handle) };
+        // A filter program that failed is the likely cause of any error closing, so it is
+        // reported first.
+        if let Some(ref mut program) = self.program {
+            program.finish()?;
+        }
+        match result {
+            ffi::ARCHIVE_OK => Ok(()),
+            _ => ArchiveResult::from(self as &dyn Handle),
+        }
+    }
+}
+
+// Reads the file an entry found on disk was created from, passing its data to `f` in chunks.
+fn read_source<F>(entry: *mut ffi::Struct_archive_entry, mut f: F) -> ArchiveResult<()>
+where
+    F: FnMut(&[u8]) -> ArchiveResult<()>,
+{
+    let source = unsafe { ffi::archive_entry_sourcepath(entry) };
+    if source.is_null() {
+        return Ok(());
+    }
+    let source = unsafe { std::ffi::CStr::from_ptr(source) };
+    let mut file = File::open(&*source.to_string_lossy())?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buf)? {
+            0 => return Ok(()),
+            size => f(&buf[..size])?,
+        }
+    }
+}
+
+// libarchive only tells file and directory symlinks apart when reading Windows trees. Elsewhere
+// the kind is taken from the target, so the archive extracts with the right kind on Windows.
+// Dangling symlinks stay undefined.
+#[cfg(feature = "symlink-type")]
+fn classify_symlink(entry: *mut ffi::Struct_archive_entry) {
+    if crate::archive::version_number() < 3006000 {
+        return;
+    }
+    unsafe {
+        if ffi::archive_entry_filetype(entry) != ffi::AE_IFLNK
+            || ffi::archive_entry_symlink_type(entry) != ffi::AE_SYMLINK_TYPE_UNDEFINED
+        {
+            return;
+        }
+        let source = ffi::archive_entry_sourcepath(entry);
+        if source.is_null() {
+            return;
+        }
+        let source = CStr::from_ptr(source).to_string_lossy();
+        let symlink_type = match std::fs::metadata(&*source) {
+            Ok(metadata) if metadata.is_dir() => ffi::AE_SYMLINK_TYPE_DIRECTORY,
+            Ok(_) => ffi::AE_SYMLINK_TYPE_FILE,
+            Err(_) => return,
+        };
+        ffi::archive_entry_set_symlink_type(entry, symlink_type);
+    }
+}
+
+fn pathname_bytes(entry: &NewEntry) -> &[u8] {
+    unsafe {
+        let ptr = ffi::archive_entry_pathname(entry.handle);
+        if ptr.is_null() {
+            return &[];
+        }
+        std::ffi::CStr::from_ptr(ptr).to_bytes()
+    }
+}
+
+impl LinkResolver {
+    fn new(format: i32) -> Self {
+        unsafe {
+            let handle = ffi::archive_entry_linkresolver_new();
+            if handle.is_null() {
+                panic!("Allocation error");
+            }
+            ffi::archive_entry_linkresolver_set_strategy(handle, format);
+            LinkResolver { handle }
+        }
+    }
+}
+
+impl Drop for LinkResolver {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::archive_entry_linkresolver_free(self.handle);
+        }
+    }
+}
+
+impl NewEntry {
+    fn new() -> Self {
+        unsafe {
+            let handle = ffi::archive_entry_new();
+            if handle.is_null() {
+                panic!("Allocation error");
+            }
+            NewEntry { handle }
+        }
+    }
+
+    fn clone_from(entry: *mut ffi::Struct_archive_entry) -> Self {
+        unsafe {
+            let handle = ffi::archive_entry_clone(entry);
+            if handle.is_null() {
+                panic!("Allocation error");
+            }
+            NewEntry { handle }
+        }
+    }
+}
+
+impl DiskWalker {
+    fn open(path: &Path, follow: FollowSymlinks) -> ArchiveResult<DiskWalker> {
+        unsafe {
+            let handle = ffi::archive_read_disk_new();
+            if handle.is_null() {
+                panic!("Allocation error");
+            }
+            let walker = DiskWalker {
+                handle,
+                entry: NewEntry::new(),
+            };
+            if ffi::archive_read_disk_set_standard_lookup(handle) != ffi::ARCHIVE_OK {
+                return Err(ArchiveError::from(&walker as &dyn Handle));
+            }
+            let res = match follow {
+                FollowSymlinks::Never => ffi::archive_read_disk_set_symlink_physical(handle),
+                FollowSymlinks::Always => ffi::archive_read_disk_set_symlink_logical(handle),
+                FollowSymlinks::CommandLineOnly => {
+                    ffi::archive_read_disk_set_symlink_hybrid(handle)
+                }
+            };
+            if res != ffi::ARCHIVE_OK {
+                return Err(ArchiveError::from(&walker as &dyn Handle));
+            }
+            let c_path = CString::new(path.to_string_lossy().as_bytes()).unwrap();
+            match ffi::archive_read_disk_open(handle, c_path.as_ptr()) {
+                ffi::ARCHIVE_OK => Ok(walker),
+                _ => Err(ArchiveError::from(&walker as &dyn Handle)),
+            }
+        }
+    }
+
+    // Moves to the next file, descending into directories as they are returned. Returns false
+    // once the whole tree has been walked.
+    fn next(&mut self) -> ArchiveResult<bool> {
+        unsafe {
+            ffi::archive_entry_clear(self.entry.handle);
+            match ffi::archive_read_next_header2(self.handle, self.entry.handle) {
+                ffi::ARCHIVE_OK | ffi::ARCHIVE_WARN => {
+                    ffi::archive_read_disk_descend(self.handle);
+                    // Holes in sparse files are read back as zeros rather than described to the
+                    // writer, so the data matches the size in the header.
+                    ffi::archive_entry_sparse_clear(self.entry.handle);
+                    Ok(true)
+                }
+                ffi::ARCHIVE_EOF => Ok(false),
+                _ => Err(ArchiveError::from(self as &dyn Handle)),
+            }
+        }
+    }
+
+    fn read_data<F>(&mut self, mut f: F) -> ArchiveResult<()>
+    where
+        F: FnMut(&[u8]) -> ArchiveResult<()>,
+    {
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let size = unsafe {
+                ffi::archive_read_data(self.handle, buf.as_mut_ptr() as *mut _, buf.len())
+            };
+            match size {
+                0 => return Ok(()),
+                size if size < 0 => return Err(ArchiveError::from(self as &dyn Handle)),
+                size => f(&buf[..size as usize])?,
+            }
+        }
+    }
+}
+
+impl Handle for DiskWalker {
+    unsafe fn handle(&self) -> *mut ffi::Struct_archive {
+        self.handle
+    }
+}
+
+impl Drop for DiskWalker {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::archive_read_free(self.handle);
+        }
+    }
+}
+
+impl Baseline {
+    pub fn new() -> Self {
+        Baseline::default()
+    }
+
+    // Loads the manifest written by a previous `Writer::append_incremental`.
+    pub fn open<T: AsRef<Path>>(mtree: T) -> ArchiveResult<Baseline> {
+        let reader = reader::Builder::new()
+            .support_format(ReadFormat::Mtree)?
+            .open_file(mtree)?;
+        let mut files = HashMap::new();
+        let mut entry = ptr::null_mut();
+        loop {
+            match unsafe { ffi::archive_read_next_header(reader.handle(), &mut entry) } {
+                ffi::ARCHIVE_OK | ffi::ARCHIVE_WARN => (),
+                ffi::ARCHIVE_EOF => return Ok(Baseline { files }),
+                _ => return Err(ArchiveError::from(&reader as &dyn Handle)),
+            }
+            let entry = ReaderEntryHandle::new(entry);
+            files.insert(
+                baseline_key(entry.pathname()),
+                FileState::from_entry(&entry)?,
+            );
+        }
+    }
+
+    fn changed<E: Entry>(&self, entry: &E) -> ArchiveResult<bool> {
+        let previous = match self.files.get(&baseline_key(entry.pathname())) {
+            Some(previous) => previous,
+            None => return Ok(true),
+        };
+        let current = FileState::from_entry(entry)?;
+        // Directory sizes depend on the filesystem and aren't recorded by mtree.
+        let is_file = matches!(entry.filetype(), FileType::RegularFile);
+        if is_file && current.size != previous.size {
+            return Ok(true);
+        }
+        if current.mtime == previous.mtime {
+            return Ok(false);
+        }
+        // Touched but possibly unmodified; compare contents when a checksum was recorded.
+        #[cfg(all(feature = "digest", feature = "manifest"))]
+        if let (Some(ref sha256), true) = (&previous.sha256, is_file) {
+            use sha2::{Digest, Sha256};
+
+            let source = unsafe { ffi::archive_entry_sourcepath(entry.entry()) };
+            if !source.is_null() {
+                let source = unsafe { std::ffi::CStr::from_ptr(source) };
+                let mut file = File::open(&*source.to_string_lossy())?;
+                let mut hasher = Sha256::new();
+                io::copy(&mut file, &mut hasher)?;
+                return Ok(hasher.finalize().as_slice() != sha256.as_slice());
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl FileState {
+    fn from_entry<E: Entry>(entry: &E) -> ArchiveResult<FileState> {
+        let (size, mtime) = unsafe {
+            let entry = entry.entry();
+            (
+                ffi::archive_entry_size(entry),
+                (
+                    ffi::archive_entry_mtime(entry) as i64,
+                    ffi::archive_entry_mtime_nsec(entry) as i64,
+                ),
+            )
+        };
+        #[cfg(feature = "digest")]
+        let sha256 = entry.digest(crate::archive::DigestType::Sha256)?;
+        #[cfg(not(feature = "digest"))]
+        let sha256 = None;
+        Ok(FileState {
+            size,
+            mtime,
+            sha256,
+        })
+    }
+}
+
+// mtree stores every path relative, with a leading `./`, so `/a/b` and `./a/b` are the same file.
+fn baseline_key(pathname: &str) -> String {
+    let key = pathname.strip_prefix("./").unwrap_or(pathname);
+    key.trim_matches('/').to_string()
+}
+
+impl Entry for NewEntry {
+    unsafe fn entry(&self) -> *mut ffi::Struct_archive_entry {
+        self.handle
+    }
+}
+
+impl Drop for NewEntry {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::archive_entry_free(self.handle);
+        }
     }
 }
 
@@ -82,7 +959,10 @@ impl Disk {
     pub fn set_options(&self, eopt: &ExtractOptions) -> ArchiveResult<()> {
         unsafe {
             match ffi::archive_write_disk_set_options(self.handle, eopt.flags) {
-                ffi::ARCHIVE_OK => Ok(()),
+                ffi::ARCHIVE_OK => {
+                    self.extract_flags.set(eopt.flags);
+                    Ok(())
+                }
                 _ => ArchiveResult::from(self as &dyn Handle),
             }
         }
@@ -101,16 +981,136 @@ impl Disk {
         }
     }
 
-    // * Failures - HeaderPosition
+    // Resolves entry user names to uids with `lookup` instead of the system user database, for
+    // extracting into containers or chroots with a passwd file of their own. `lookup` gets the
+    // name and the uid stored in the archive, and returns the uid to use.
+    pub fn set_user_lookup<F>(&self, lookup: F) -> ArchiveResult<()>
+    where
+        F: FnMut(&str, i64) -> i64 + 'static,
+    {
+        let data = Box::into_raw(Box::new(Box::new(lookup) as NameLookup));
+        unsafe {
+            match ffi::archive_write_disk_set_user_lookup(
+                self.handle,
+                data as *mut c_void,
+                Some(name_lookup_callback),
+                Some(name_lookup_cleanup),
+            ) {
+                ffi::ARCHIVE_OK => Ok(()),
+                _ => ArchiveResult::from(self as &dyn Handle),
+            }
+        }
+    }
+
+    // Resolves entry group names to gids with `lookup`. See `set_user_lookup`.
+    pub fn set_group_lookup<F>(&self, lookup: F) -> ArchiveResult<()>
+    where
+        F: FnMut(&str, i64) -> i64 + 'static,
+    {
+        let data = Box::into_raw(Box::new(Box::new(lookup) as NameLookup));
+        unsafe {
+            match ffi::archive_write_disk_set_group_lookup(
+                self.handle,
+                data as *mut c_void,
+                Some(name_lookup_callback),
+                Some(name_lookup_cleanup),
+            ) {
+                ffi::ARCHIVE_OK => Ok(()),
+                _ => ArchiveResult::from(self as &dyn Handle),
+            }
+        }
+    }
+
+    // Keep extracting when an entry can't be written or its data can't be read, instead of failing
+    // the whole extraction. Failed entries are collected for `take_skipped`. Whether the entries
+    // after a corrupt one are reachable depends on the format; extraction still ends at the first
+    // header that can't be read.
+    pub fn set_skip_unreadable(&mut self, skip: bool) {
+        self.skip_unreadable = skip;
+    }
+
+    // Permission bits to clear on every extracted object, applied like a umask on top of whatever
+    // `ExtractOptions` restore. For example `0o6022` strips setuid and setgid and keeps files from
+    // being group or world writable.
+    pub fn set_permission_mask(&mut self, mask: u32) {
+        self.permission_mask = mask;
+    }
+
+    // Rewrite entry pathnames and hardlink targets with `paths::sanitize` before extracting, so
+    // absolute paths and `..` components end up below the prefix instead of escaping it.
+    pub fn set_sanitize_paths(&mut self, sanitize: bool) {
+        self.sanitize_paths = sanitize;
+    }
+
+    // Flush every extracted file and directory, and the directories containing them, to stable
+    // storage before `write` returns. An error while syncing fails the extraction.
+    pub fn set_fsync(&mut self, fsync: bool) {
+        self.fsync = fsync;
+    }
+
+    // Reports each extracted entry and the bytes written for it to `progress`.
+    pub fn set_progress<P: Progress + 'static>(&mut self, progress: P) {
+        self.progress = RefCell::new(Some(Box::new(progress)));
+    }
+
+    // Calls `hook` right before each entry is created on disk, with the path it is created at
+    // (after sanitizing and prefixing) and the entry, to allow, skip or abort it. Lets applications
+    // enforce policies of their own, e.g. rejecting device files or paths outside an allow list.
+    pub fn set_create_hook<F>(&mut self, hook: F)
+    where
+        F: FnMut(&Path, &ReaderEntryHandle) -> Decision + 'static,
+    {
+        self.create_hook = RefCell::new(Some(Box::new(hook)));
+    }
+
+    // Writes a `sha256sum -c` compatible manifest of the regular files extracted by `write` to
+    // `out`, hashing data as it is written. Paths are as stored in the archive, i.e. relative to
+    // the extraction prefix.
+    #[cfg(feature = "manifest")]
+    pub fn set_manifest<W: io::Write + 'static>(&mut self, out: W) {
+        self.manifest = RefCell::new(Some(Manifest::new(Box::new(out))));
+    }
+
+    // Makes `write` resumable. Each regular file is recorded in the checkpoint at `path`, a
+    // manifest like the one `set_manifest` writes, as soon as it is fully extracted. Running
+    // `write` over the same archive again with the same checkpoint skips the files it lists that
+    // still exist, so an extraction interrupted by a crash or cancellation picks up where it
+    // stopped. Other entries are cheap to create and always extracted again.
+    #[cfg(feature = "manifest")]
+    pub fn set_checkpoint<P: AsRef<Path>>(&mut self, path: P) -> ArchiveResult<()> {
+        let (checkpoint, checkpointed) = Manifest::resume(path.as_ref())?;
+        self.checkpoint = RefCell::new(Some(checkpoint));
+        self.checkpointed = checkpointed;
+        Ok(())
+    }
+
+    // Entries skipped by `write` in skip-unreadable mode since the last call.
+    pub fn take_skipped(&self) -> Vec<SkippedEntry> {
+        self.skipped.take()
+    }
+
+    // * Failures - HeaderPosition, unless the reader is at its first entry, e.g. after
+    //   `ReaderHandle::rewind`
     pub fn write(&self, reader: &mut ReaderHandle, prefix: Option<&str>) -> ArchiveResult<usize> {
+        let _span = span!("extract", prefix = ?prefix);
         if reader.header_position() != 0 {
             return Err(ArchiveError::HeaderPosition);
         }
         let mut bytes: usize = 0;
         let mut write_pending: bool = false;
+        let mut written: Vec<PathBuf> = Vec::new();
         loop {
-            {
+            let (pathname, size) = {
                 if let Some(entry) = reader.next_header() {
+                    #[cfg(feature = "manifest")]
+                    let archive_pathname = entry.pathname().to_string();
+                    if self.sanitize_paths {
+                        entry.set_pathname(&sanitized(entry.pathname()));
+                        if let Some(target) = entry.hardlink() {
+                            let target = sanitized(target);
+                            entry.set_link(&target);
+                        }
+                    }
                     if let Some(pfx) = prefix {
                         let path = Path::new(pfx).join(entry.pathname());
                         entry.set_pathname(&path);
@@ -119,31 +1119,115 @@ impl Disk {
                             entry.set_link(&path);
                         }
                     }
+                    if self.permission_mask != 0 {
+                        let perm = entry.perm();
+                        entry.set_perm(perm & !self.permission_mask);
+                    }
+                    let pathname = entry.pathname().to_string();
+                    #[cfg(feature = "manifest")]
+                    if self.checkpointed.contains(&archive_pathname) && is_file(&pathname) {
+                        event!(DEBUG, pathname = %pathname, "already extracted");
+                        // The entry's data is skipped when the next header is read.
+                        continue;
+                    }
+                    if let Some(hook) = self.create_hook.borrow_mut().as_mut() {
+                        match hook(Path::new(&pathname), entry) {
+                            Decision::Allow => (),
+                            // The entry's data is skipped when the next header is read.
+                            Decision::Skip => continue,
+                            Decision::Abort => return Err(ArchiveError::Aborted(pathname)),
+                        }
+                    }
+                    event!(DEBUG, pathname = entry.pathname(), "extracting entry");
                     match self.write_header(entry) {
                         Ok(()) => (),
-                        Err(e) => return Err(e),
+                        Err(e) => {
+                            event!(ERROR, pathname = entry.pathname(), error = %e, "failed to write header");
+                            // The entry's data is skipped when the next header is read.
+                            self.skip_or_fail(&pathname, e)?;
+                            continue;
+                        }
+                    }
+                    if self.fsync && entry.hardlink().is_none() {
+                        if let FileType::RegularFile | FileType::Directory = entry.filetype() {
+                            written.push(PathBuf::from(&pathname));
+                        }
+                    }
+                    let total = match unsafe { ffi::archive_entry_size_is_set(entry.entry()) } {
+                        0 => None,
+                        _ => Some(entry.size() as u64),
+                    };
+                    self.report(|p| p.entry_started(&pathname, total));
+                    #[cfg(feature = "manifest")]
+                    if entry.hardlink().is_none() {
+                        if let FileType::RegularFile = entry.filetype() {
+                            self.with_manifest(|m| {
+                                m.start(&archive_pathname);
+                                Ok(())
+                            })?;
+                        }
                     }
                     if entry.size() > 0 {
                         write_pending = true
+                    } else {
+                        self.finish_entry(&pathname, 0)?;
                     }
+                    (pathname, entry.size())
                 } else {
                     break;
                 }
-            }
+            };
             if write_pending {
-                bytes += self.write_data(reader)?;
+                let result =
+                    self.copy_stored_data(reader, &pathname)
+                        .and_then(|copied| match copied {
+                            Some(written) => Ok(written),
+                            None => self.write_data(reader),
+                        });
+                match result {
+                    Ok(written) => {
+                        bytes += written;
+                        self.finish_entry(&pathname, size)?;
+                    }
+                    Err(e) => {
+                        event!(ERROR, pathname = %pathname, error = %e, "failed to write data");
+                        self.skip_or_fail(&pathname, e)?;
+                    }
+                }
                 write_pending = false;
             }
         }
 
         unsafe {
-            match ffi::archive_write_finish_entry(self.handle()) {
-                ffi::ARCHIVE_OK => Ok(bytes),
-                _ => Err(ArchiveError::from(self as &dyn Handle)),
+            if ffi::archive_write_finish_entry(self.handle()) != ffi::ARCHIVE_OK {
+                return Err(ArchiveError::from(self as &dyn Handle));
             }
         }
+        #[cfg(feature = "manifest")]
+        self.with_manifest(|m| m.flush())?;
+        if self.fsync {
+            sync_all(&written)?;
+        }
+        Ok(bytes)
     }
 
+    // Extracts a single entry, header and data, in one call to libarchive with all the options,
+    // lookups and security flags set on this writer. The entry is written as it is named in the
+    // archive: unlike `write`, no prefix, path sanitizing, permission mask, hook, progress or
+    // manifest applies.
+    pub fn extract_entry(&self, entry: &ArchiveEntry) -> ArchiveResult<()> {
+        let ptr = entry.entry_ptr();
+        unsafe {
+            match ffi::archive_read_extract2(entry.handle(), ptr, self.handle) {
+                ffi::ARCHIVE_OK => Ok(()),
+                _ => Err(ArchiveError::from(entry as &dyn Handle)),
+            }
+        }
+    }
+
+    // Finishes extraction. Permissions and times of directories are restored only now, after
+    // everything inside them was written, so errors doing that are reported here. Dropping the
+    // writer closes it too, but without a way to report errors.
     pub fn close(&self) -> ArchiveResult<()> {
         unsafe {
             match ffi::archive_write_close(self.handle()) {
@@ -153,6 +1237,90 @@ impl Disk {
         }
     }
 
+    #[cfg_attr(not(feature = "manifest"), allow(unused_variables))]
+    fn finish_entry(&self, pathname: &str, size: i64) -> ArchiveResult<()> {
+        #[cfg(feature = "manifest")]
+        self.with_manifest(|m| m.finish(size as u64))?;
+        self.report(|p| p.entry_finished(pathname));
+        Ok(())
+    }
+
+    // Applies `f` to the manifest and the checkpoint, whichever are set.
+    #[cfg(feature = "manifest")]
+    fn with_manifest<F: FnMut(&mut Manifest) -> io::Result<()>>(&self, mut f: F) -> io::Result<()> {
+        if let Some(manifest) = self.manifest.borrow_mut().as_mut() {
+            f(manifest)?;
+        }
+        if let Some(checkpoint) = self.checkpoint.borrow_mut().as_mut() {
+            f(checkpoint)?;
+        }
+        Ok(())
+    }
+
+    fn report<F: FnOnce(&mut dyn Progress)>(&self, f: F) {
+        if let Some(progress) = self.progress.borrow_mut().as_mut() {
+            f(progress.as_mut());
+        }
+    }
+
+    fn skip_or_fail(&self, pathname: &str, error: ArchiveError) -> ArchiveResult<()> {
+        if !self.skip_unreadable {
+            return Err(error);
+        }
+        self.skipped.borrow_mut().push(SkippedEntry {
+            pathname: pathname.to_string(),
+            error,
+        });
+        Ok(())
+    }
+
+    // Copies the data of an entry stored as is in the archive file (see
+    // `ReaderHandle::stored_range`) into the file just created for it inside the kernel, leaving
+    // libarchive to skip over it. Returns `None` when that isn't possible, before anything is
+    // copied, and the data has to be written through libarchive instead.
+    fn copy_stored_data(
+        &self,
+        reader: &ReaderHandle,
+        pathname: &str,
+    ) -> ArchiveResult<Option<usize>> {
+        // Safe writes create a temporary file in place of `pathname`, and sparse extraction and
+        // HFS+ compression need to see the data.
+        let flags = ffi::ARCHIVE_EXTRACT_SAFE_WRITES
+            | ffi::ARCHIVE_EXTRACT_SPARSE
+            | ffi::ARCHIVE_EXTRACT_HFS_COMPRESSION_FORCED;
+        if self.extract_flags.get() & flags != 0 {
+            return Ok(None);
+        }
+        #[cfg(feature = "manifest")]
+        if self.manifest.borrow().is_some() || self.checkpoint.borrow().is_some() {
+            return Ok(None);
+        }
+        let (source, offset, len) = match reader.stored_range() {
+            Some(range) => range,
+            None => return Ok(None),
+        };
+        reader.check_data(len)?;
+        let mut options = OpenOptions::new();
+        options.write(true);
+        // The file was created by libarchive moments ago, don't follow a symlink put in its place.
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, libc::O_NOFOLLOW);
+        let (src, dst) = match (File::open(source), options.open(pathname)) {
+            (Ok(src), Ok(dst)) => (src, dst),
+            _ => return Ok(None),
+        };
+        if !copy::copy_range(&src, offset, &dst, len)? {
+            return Ok(None);
+        }
+        unsafe {
+            if ffi::archive_read_data_skip(reader.handle()) != ffi::ARCHIVE_OK {
+                return Err(ArchiveError::from(reader as &dyn Handle));
+            }
+        }
+        self.report(|p| p.bytes_processed(len));
+        Ok(Some(len as usize))
+    }
+
     fn write_data(&self, reader: &ReaderHandle) -> ArchiveResult<usize> {
         let mut total_size = 0;
         let mut buff = ptr::null();
@@ -172,13 +1340,26 @@ impl Disk {
                         return Ok(total_size + size);
                     }
                     ffi::ARCHIVE_OK => {
+                        reader.check_data(offset as u64 + size as u64)?;
                         if ffi::archive_write_data_block(self.handle, buff, size, offset)
                             != ffi::ARCHIVE_OK as isize
                         {
                             return Err(ArchiveError::from(self as &dyn Handle));
                         }
+                        self.report(|p| p.bytes_processed(size as u64));
+                        // The last block may be empty, without a buffer.
+                        #[cfg(feature = "manifest")]
+                        if size > 0 {
+                            self.with_manifest(|m| {
+                                m.update(
+                                    offset as u64,
+                                    std::slice::from_raw_parts(buff as *const u8, size),
+                                );
+                                Ok(())
+                            })?;
+                        }
                     }
-                    _ => return Err(ArchiveError::from(reader as &dyn Handle)),
+                    _ => return Err(reader.data_error()),
                 }
             }
         }
@@ -194,6 +1375,49 @@ impl Disk {
     }
 }
 
+// Whether `path` is a regular file, not following symlinks.
+#[cfg(feature = "manifest")]
+fn is_file(path: &str) -> bool {
+    std::fs::symlink_metadata(path).is_ok_and(|m| m.is_file())
+}
+
+// `path` as libarchive takes it, on unix the bytes as they are whatever their encoding. Paths
+// holding a NUL byte fail with `io::ErrorKind::InvalidInput`.
+fn path_string(path: &Path) -> ArchiveResult<CString> {
+    #[cfg(unix)]
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()).to_vec();
+    #[cfg(not(unix))]
+    let bytes = path.to_string_lossy().into_owned().into_bytes();
+    CString::new(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err).into())
+}
+
+fn sanitized(path: &str) -> PathBuf {
+    match paths::sanitize(path) {
+        ref clean if clean.is_empty() => PathBuf::from("."),
+        clean => PathBuf::from(clean),
+    }
+}
+
+// Syncs the given files and directories, then each distinct parent directory so the directory
+// entries pointing at them are durable too.
+fn sync_all(paths: &[PathBuf]) -> io::Result<()> {
+    let mut parents = BTreeSet::new();
+    for path in paths {
+        File::open(path)?.sync_all()?;
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parents.insert(parent.to_path_buf()),
+            _ => parents.insert(PathBuf::from(".")),
+        };
+    }
+    // Directories can't be opened for syncing on Windows.
+    if cfg!(unix) {
+        for parent in parents {
+            File::open(parent)?.sync_all()?;
+        }
+    }
+    Ok(())
+}
+
 impl Handle for Disk {
     unsafe fn handle(&self) -> *mut ffi::Struct_archive {
         self.handle
@@ -207,7 +1431,23 @@ impl Default for Disk {
             if handle.is_null() {
                 panic!("Allocation error");
             }
-            Disk { handle: handle }
+            Disk {
+                handle,
+                skip_unreadable: false,
+                skipped: RefCell::new(Vec::new()),
+                permission_mask: 0,
+                fsync: false,
+                sanitize_paths: false,
+                progress: RefCell::new(None),
+                create_hook: RefCell::new(None),
+                extract_flags: Cell::new(0),
+                #[cfg(feature = "manifest")]
+                manifest: RefCell::new(None),
+                #[cfg(feature = "manifest")]
+                checkpoint: RefCell::new(None),
+                #[cfg(feature = "manifest")]
+                checkpointed: HashSet::new(),
+            }
         }
     }
 }
@@ -245,6 +1485,8 @@ impl Builder {
             }
             WriteFilter::UuEncode => unsafe { ffi::archive_write_add_filter_uuencode(self.handle) },
             WriteFilter::Xz => unsafe { ffi::archive_write_add_filter_xz(self.handle) },
+            #[cfg(feature = "zstd")]
+            WriteFilter::Zstd => unsafe { ffi::archive_write_add_filter_zstd(self.handle) },
         };
         match result {
             ffi::ARCHIVE_OK => Ok(()),
@@ -286,10 +1528,83 @@ impl Builder {
         }
     }
 
+    // Makes the ISO bootable. Needs `WriteFormat::Iso9660` to be set first.
+    pub fn set_iso_boot(&self, boot: &IsoBoot) -> ArchiveResult<()> {
+        self.set_format_option("iso9660", "boot", Some(&boot.image))?;
+        if let Some(ref catalog) = boot.catalog {
+            self.set_format_option("iso9660", "boot-catalog", Some(catalog))?;
+        }
+        let emulation = match boot.emulation {
+            BootEmulation::NoEmulation => "no-emulation",
+            BootEmulation::Floppy => "fd",
+            BootEmulation::HardDisk => "hard-disk",
+        };
+        self.set_format_option("iso9660", "boot-type", Some(emulation))?;
+        if let Some(load_size) = boot.load_size {
+            self.set_format_option("iso9660", "boot-load-size", Some(&load_size.to_string()))?;
+        }
+        if boot.info_table {
+            self.set_format_option("iso9660", "boot-info-table", Some("1"))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "threads")]
+    fn set_filter_option(&self, module: &str, key: &str, value: &str) -> ArchiveResult<()> {
+        let c_module = option_string(module)?;
+        let c_key = option_string(key)?;
+        let c_value = option_string(value)?;
+        let result = unsafe {
+            ffi::archive_write_set_filter_option(
+                self.handle,
+                c_module.as_ptr(),
+                c_key.as_ptr(),
+                c_value.as_ptr(),
+            )
+        };
+        match result {
+            ffi::ARCHIVE_OK => Ok(()),
+            _ => ArchiveResult::from(self as &dyn Handle),
+        }
+    }
+
+    fn set_format_option(&self, module: &str, key: &str, value: Option<&str>) -> ArchiveResult<()> {
+        let c_module = option_string(module)?;
+        let c_key = option_string(key)?;
+        let c_value = value.map(option_string).transpose()?;
+        let result = unsafe {
+            ffi::archive_write_set_format_option(
+                self.handle,
+                c_module.as_ptr(),
+                c_key.as_ptr(),
+                c_value.as_ref().map_or(ptr::null(), |value| value.as_ptr()),
+            )
+        };
+        match result {
+            ffi::ARCHIVE_OK => Ok(()),
+            _ => ArchiveResult::from(self as &dyn Handle),
+        }
+    }
+
+    // Charset to encode entry pathnames, user and group names in. Applied when the archive is
+    // opened, so it may be set before or after `set_format`.
+    pub fn set_header_charset(&mut self, charset: &str) {
+        self.header_charset = Some(charset.to_string());
+    }
+
+    // Number of threads the xz and zstd filters compress with, 0 for one per CPU. Filters for which
+    // `WriteFilter::supports_threads` is false compress with a single thread regardless. Applied
+    // when the archive is opened, so it may be set before or after `add_filter`.
+    #[cfg(feature = "threads")]
+    pub fn set_threads(&mut self, threads: u32) {
+        self.threads = Some(threads);
+    }
+
     pub fn open_file<T: AsRef<Path>>(mut self, file: T) -> ArchiveResult<Writer> {
         if self.consumed {
             return Err(ArchiveError::Consumed);
         }
+        self.apply_options()?;
         let c_file = CString::new(file.as_ref().to_string_lossy().as_bytes()).unwrap();
         let res = unsafe { ffi::archive_write_open_filename(self.handle, c_file.as_ptr()) };
         match res {
@@ -300,6 +1615,58 @@ impl Builder {
             _ => Err(ArchiveError::from(&self as &dyn Handle)),
         }
     }
+
+    // Writes to `file` through an external program, e.g. a compressor libarchive has no built-in
+    // support for. Unlike with `WriteFilter::Program` the program is supervised, see
+    // `FilterProgram`; `Writer::close` fails if it does not exit successfully.
+    #[cfg(unix)]
+    pub fn open_program<T: AsRef<Path>>(
+        mut self,
+        file: T,
+        program: &FilterProgram,
+    ) -> ArchiveResult<Writer> {
+        if self.consumed {
+            return Err(ArchiveError::Consumed);
+        }
+        self.apply_options()?;
+        let output = File::create(file)?;
+        let input = ProgramInput::spawn(program, Stdio::from(output))?;
+        match unsafe { ffi::archive_write_open_fd(self.handle, input.fd()) } {
+            ffi::ARCHIVE_OK => {
+                self.consumed = true;
+                let mut writer = Writer::new(self.handle);
+                writer.program = Some(input);
+                Ok(writer)
+            }
+            _ => Err(ArchiveError::from(&self as &dyn Handle)),
+        }
+    }
+
+    fn apply_options(&self) -> ArchiveResult<()> {
+        if let Some(ref charset) = self.header_charset {
+            let c_opt = option_string(&format!("hdrcharset={}", charset))?;
+            match unsafe { ffi::archive_write_set_options(self.handle, c_opt.as_ptr()) } {
+                ffi::ARCHIVE_OK | ffi::ARCHIVE_WARN => (),
+                _ => return Err(ArchiveError::from(self as &dyn Handle)),
+            }
+        }
+        #[cfg(feature = "threads")]
+        if let Some(threads) = self.threads {
+            let count = unsafe { ffi::archive_filter_count(self.handle) };
+            for i in 0..count {
+                let (filter, module) = match unsafe { ffi::archive_filter_code(self.handle, i) } {
+                    ffi::ARCHIVE_FILTER_XZ => (WriteFilter::Xz, "xz"),
+                    #[cfg(feature = "zstd")]
+                    ffi::ARCHIVE_FILTER_ZSTD => (WriteFilter::Zstd, "zstd"),
+                    _ => continue,
+                };
+                if filter.supports_threads() {
+                    self.set_filter_option(module, "threads", &threads.to_string())?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Default for Builder {
@@ -310,8 +1677,11 @@ impl Default for Builder {
                 panic!("Allocation error");
             }
             Builder {
-                handle: handle,
+                handle,
                 consumed: false,
+                header_charset: None,
+                #[cfg(feature = "threads")]
+                threads: None,
             }
         }
     }
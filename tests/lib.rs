@@ -2,11 +2,12 @@ extern crate libarchive;
 
 pub mod util;
 
-use libarchive::archive;
+use libarchive::archive::{self, Entry};
+use libarchive::error::{ArchiveError, ErrCode, Limit};
 use libarchive::reader::{self};
 use libarchive::writer;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
 use std::panic::{catch_unwind, AssertUnwindSafe};
 
 fn assert_string(string: &str) {
@@ -66,6 +67,459 @@ fn read_archive_from_stream() {
     }
 }
 
+#[test]
+fn reading_from_memory() {
+    let data = std::fs::read(util::path::fixture("sample.tar.gz")).unwrap();
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_memory(data)
+        .unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+
+    let embedded: &'static [u8] = include_bytes!("fixtures/sample.zip");
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_memory(embedded)
+        .unwrap();
+    let entry = reader.next_header().unwrap();
+    assert_eq!(entry.pathname(), "hello.txt");
+    assert_string(std::str::from_utf8(&reader.read_entry_data().unwrap()).unwrap());
+}
+
+#[test]
+fn reading_from_borrowed_stream() {
+    let mut file = File::open(util::path::fixture("sample.tar.gz")).unwrap();
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_stream(&mut file)
+        .unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+    drop(reader);
+
+    // The reader is done with the file, which can be reused.
+    file.seek(SeekFrom::Start(0)).unwrap();
+    let boxed: Box<dyn std::io::Read + Send + '_> = Box::new(&mut file);
+    let entries = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_stream(boxed)
+        .unwrap()
+        .into_iter()
+        .count();
+    assert_eq!(entries, 1);
+}
+
+#[test]
+fn setting_reader_options() {
+    let mut data = std::fs::read(util::path::fixture("sample.tar.gz")).unwrap();
+    data.extend_from_within(..);
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .set_format_option("tar", "read_concatenated_archives", "1")
+        .open_memory(data)
+        .unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 2);
+
+    let unknown = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .set_option("tar", "no_such_option", "1")
+        .open_file(util::path::fixture("sample.tar.gz"));
+    assert!(unknown.is_err());
+
+    let disabled = reader::Builder::new()
+        .support_format(archive::ReadFormat::Zip)
+        .unwrap()
+        .set_option("iso9660", "joliet", "0")
+        .open_file(util::path::fixture("sample.zip"));
+    assert!(disabled.is_err());
+
+    let nul = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .set_format_option("tar", "read_concatenated_archives", "1\0")
+        .open_file(util::path::fixture("sample.tar.gz"));
+    match nul {
+        Err(ArchiveError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput),
+        _ => panic!("expected an error for a NUL in the option value"),
+    }
+}
+
+#[test]
+fn expecting_a_format() {
+    let open = |code| {
+        reader::Builder::new()
+            .support_filter(archive::ReadFilter::All)
+            .and_then(|builder| builder.expect_format(code))
+            .and_then(|builder| builder.open_file(util::path::fixture("sample.tar.gz")))
+    };
+    // ARCHIVE_FORMAT_TAR accepts any tar variant.
+    let mut reader = open(0x30000).unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+
+    // ARCHIVE_FORMAT_ZIP
+    let mut reader = open(0x50000).unwrap();
+    assert!(matches!(
+        reader.list_entries(),
+        Err(ArchiveError::WrongFormat {
+            expected: 0x50000,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn setting_typed_reader_options() {
+    let mut entry = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .read_option(archive::ReadOption::ZipIgnoreCrc32)
+        .open_file(util::path::fixture("corrupt.zip"))
+        .unwrap()
+        .into_iter()
+        .next()
+        .unwrap()
+        .unwrap();
+    let mut data = Vec::new();
+    entry.read_to_end(&mut data).unwrap();
+    assert_eq!(data.len(), 14);
+
+    let mut data = std::fs::read(util::path::fixture("sample.tar.gz")).unwrap();
+    data.extend_from_within(..);
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .read_option(archive::ReadOption::TarReadConcatenated)
+        .read_option(archive::ReadOption::Hdrcharset("UTF-8".to_string()))
+        .open_memory(data)
+        .unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 2);
+}
+
+#[test]
+fn reading_concatenated_archives() {
+    let mut data = std::fs::read(util::path::fixture("sample.tar.gz")).unwrap();
+    data.extend_from_within(..);
+
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_memory(data.clone())
+        .unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .read_concatenated_archives(true)
+        .open_memory(data)
+        .unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 2);
+}
+
+#[test]
+fn reading_from_chunks() {
+    let data = std::fs::read(util::path::fixture("sample.tar.gz")).unwrap();
+    let chunks: Vec<Vec<u8>> = data.chunks(100).map(|chunk| chunk.to_vec()).collect();
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_chunks(chunks)
+        .unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+
+    let (sender, receiver) = std::sync::mpsc::channel::<&[u8]>();
+    let zip: &'static [u8] = include_bytes!("fixtures/sample.zip");
+    for chunk in zip.chunks(7) {
+        sender.send(chunk).unwrap();
+        // Empty chunks don't end the archive early.
+        sender.send(&[]).unwrap();
+    }
+    drop(sender);
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_chunks(receiver)
+        .unwrap();
+    let entry = reader.next_header().unwrap();
+    assert_eq!(entry.pathname(), "hello.txt");
+    assert_string(std::str::from_utf8(&reader.read_entry_data().unwrap()).unwrap());
+}
+
+#[test]
+fn reading_from_buffered_stream() {
+    let file = File::open(util::path::fixture("sample.tar.gz")).unwrap();
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_buffered_stream(std::io::BufReader::with_capacity(100, file))
+        .unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+
+    let zip: &'static [u8] = include_bytes!("fixtures/sample.zip");
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_buffered_stream(zip)
+        .unwrap();
+    let entry = reader.next_header().unwrap();
+    assert_eq!(entry.pathname(), "hello.txt");
+    assert_string(std::str::from_utf8(&reader.read_entry_data().unwrap()).unwrap());
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test(flavor = "multi_thread")]
+async fn reading_from_async_source() {
+    let file = tokio::fs::File::open(util::path::fixture("sample.tar.gz"))
+        .await
+        .unwrap();
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_async(file)
+        .unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+
+    let file = tokio::fs::File::open(util::path::fixture("sample.zip"))
+        .await
+        .unwrap();
+    let data = tokio::task::spawn_blocking(move || {
+        let mut reader = reader::Builder::new()
+            .support_all()
+            .unwrap()
+            .open_async(file)
+            .unwrap();
+        reader.next_header().unwrap();
+        reader.read_entry_data().unwrap()
+    })
+    .await
+    .unwrap();
+    assert_string(std::str::from_utf8(&data).unwrap());
+}
+
+#[cfg(feature = "futures")]
+#[tokio::test]
+async fn streaming_entries() {
+    use futures::StreamExt;
+    use libarchive::ArchiveEntryStream;
+
+    let mut stream = ArchiveEntryStream::open(|| {
+        reader::Builder::new()
+            .support_all()?
+            .open_file(util::path::fixture("sample.zip"))
+    })
+    .await
+    .unwrap();
+    let entry = stream.next().await.unwrap().unwrap();
+    assert_eq!(entry.pathname.as_deref(), Some("hello.txt"));
+    let mut data = Vec::new();
+    while let Some(chunk) = stream.read_chunk().await.unwrap() {
+        data.extend_from_slice(&chunk);
+    }
+    assert_string(std::str::from_utf8(&data).unwrap());
+
+    let mut stream = ArchiveEntryStream::open(|| {
+        reader::Builder::new()
+            .support_all()?
+            .open_file(util::path::fixture("sample.tar.gz"))
+    })
+    .await
+    .unwrap();
+    let mut count = 0;
+    while let Some(entry) = stream.next().await {
+        entry.unwrap();
+        count += 1;
+    }
+    assert_eq!(count, 1);
+
+    let missing = ArchiveEntryStream::open(|| {
+        reader::Builder::new()
+            .support_all()?
+            .open_file(util::path::fixture("missing.zip"))
+    })
+    .await;
+    assert!(missing.is_err());
+}
+
+#[test]
+fn reading_from_seekable_stream() {
+    let zip = std::io::Cursor::new(include_bytes!("fixtures/sample.zip").to_vec());
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_seekable_stream(zip)
+        .unwrap();
+    let entry = reader.next_header().unwrap();
+    assert_eq!(entry.pathname(), "hello.txt");
+    assert_string(std::str::from_utf8(&reader.read_entry_data().unwrap()).unwrap());
+
+    let tar = File::open(util::path::fixture("sample.tar.gz")).unwrap();
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_seekable_stream(tar)
+        .unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn reading_from_mmap() {
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file_mmap(util::path::fixture("sample.tar.gz"))
+        .unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+}
+
+#[test]
+fn reading_with_custom_buffer_sizes() {
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .block_size(512)
+        .open_file(util::path::fixture("sample.tar.gz"))
+        .unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+
+    let file = File::open(util::path::fixture("sample.tar.gz")).unwrap();
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .stream_buffer_size(1 << 20)
+        .open_stream(file)
+        .unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+}
+
+#[test]
+fn enabling_formats_by_code_and_name() {
+    let open = |builder: reader::Builder| {
+        builder
+            .support_filter(archive::ReadFilter::All)
+            .unwrap()
+            .open_file(util::path::fixture("sample.tar.gz"))
+            .and_then(|mut reader| reader.list_entries())
+    };
+    let by_name = reader::Builder::new()
+        .support_format_by_name("TAR")
+        .unwrap();
+    assert_eq!(open(by_name).unwrap().len(), 1);
+    let by_code = reader::Builder::new()
+        // ARCHIVE_FORMAT_TAR_USTAR
+        .support_format_by_code(0x30001)
+        .unwrap();
+    assert_eq!(open(by_code).unwrap().len(), 1);
+    let zip_only = reader::Builder::new()
+        .support_format_by_name("zip")
+        .unwrap();
+    assert!(open(zip_only).is_err());
+
+    match reader::Builder::new().support_format_by_name("tarball") {
+        Err(ArchiveError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput),
+        _ => panic!("expected an error for an unknown format"),
+    }
+}
+
+#[test]
+fn appending_filters() {
+    let open = |filter| {
+        reader::Builder::new()
+            .append_filter(filter)?
+            .support_format(archive::ReadFormat::Tar)?
+            .open_file(util::path::fixture("sample.tar.gz"))
+            .and_then(|mut reader| reader.list_entries())
+    };
+    assert_eq!(open(archive::ReadFilter::Gzip).unwrap().len(), 1);
+    assert!(open(archive::ReadFilter::Bzip2).is_err());
+    assert!(open(archive::ReadFilter::All).is_err());
+}
+
+#[test]
+fn reading_warc_records() {
+    let warc = concat!(
+        "WARC/1.0\r\n",
+        "WARC-Type: resource\r\n",
+        "WARC-Target-URI: file:///hello.txt\r\n",
+        "WARC-Date: 2020-01-01T00:00:00Z\r\n",
+        "WARC-Record-ID: <urn:uuid:f0b7d1a4-2c67-4b49-9b0e-6d3f8c1d2e01>\r\n",
+        "Content-Type: text/plain\r\n",
+        "Content-Length: 5\r\n",
+        "\r\n",
+        "hello\r\n\r\n",
+    );
+    let mut reader = reader::Builder::new()
+        .support_format(archive::ReadFormat::Warc)
+        .unwrap()
+        .open_memory(warc.as_bytes())
+        .unwrap();
+    let entries = reader.read_all(None).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].0.mtime, 1577836800);
+    assert_eq!(entries[0].1, b"hello");
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn reading_with_explicit_filters() {
+    let tempfile = tempfile::tempdir().unwrap();
+    for (write, read, name) in [(
+        archive::WriteFilter::Zstd,
+        archive::ReadFilter::Zstd,
+        "explicit.tar.zst",
+    )] {
+        let path = tempfile.path().join(name);
+        let mut builder = writer::Builder::new();
+        builder.add_filter(write).unwrap();
+        builder.set_format(archive::WriteFormat::Pax).unwrap();
+        let mut writer = builder.open_file(&path).unwrap();
+        writer
+            .write_entries(vec![("hello.txt", &b"hello"[..])])
+            .unwrap();
+        writer.close().unwrap();
+
+        let mut reader = reader::Builder::new()
+            .support_filter(read)
+            .unwrap()
+            .support_format(archive::ReadFormat::Tar)
+            .unwrap()
+            .open_file(&path)
+            .unwrap();
+        let data = reader.read_all(None).unwrap();
+        assert_eq!(data[0].1, b"hello");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn reading_from_fd() {
+    use std::os::unix::io::{AsRawFd, OwnedFd};
+
+    let file = File::open(util::path::fixture("sample.tar.gz")).unwrap();
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_fd(file.as_raw_fd(), 10240)
+        .unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+    drop(reader);
+
+    let file = File::open(util::path::fixture("sample.zip")).unwrap();
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_owned_fd(OwnedFd::from(file))
+        .unwrap();
+    let entry = reader.next_header().unwrap();
+    assert_eq!(entry.pathname(), "hello.txt");
+    assert_string(std::str::from_utf8(&reader.read_entry_data().unwrap()).unwrap());
+}
+
 #[test]
 fn extracting_from_file() {
     let tar = util::path::fixture("sample.tar.gz");
@@ -80,14 +534,47 @@ fn extracting_from_file() {
     assert_eq!(4, 4)
 }
 
+#[test]
+fn rewinding_a_reader() {
+    let mut reader = reader();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+    reader.rewind().unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+
+    let writer = writer::Disk::new();
+    let zip: &'static [u8] = include_bytes!("fixtures/sample.zip");
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_memory(zip)
+        .unwrap();
+    for _ in 0..2 {
+        let tempfile = tempfile::tempdir().unwrap();
+        writer.write(&mut reader, tempfile.path().to_str()).unwrap();
+        assert!(tempfile.path().join("hello.txt").is_file());
+        reader.rewind().unwrap();
+    }
+
+    let file = File::open(util::path::fixture("sample.tar.gz")).unwrap();
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_stream(file)
+        .unwrap();
+    reader.next_header().unwrap();
+    match reader.rewind() {
+        Err(ArchiveError::NotRewindable) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+}
+
 #[test]
 fn extracting_an_archive_with_options() {
     let tar = util::path::fixture("sample.tar.gz");
     let builder = reader::Builder::new().support_all().unwrap();
     let mut reader = builder.open_file(tar).ok().unwrap();
     println!("{:?}", reader.header_position());
-    let mut opts = archive::ExtractOptions::new();
-    opts.add(archive::ExtractOption::Time);
+    let opts = archive::ExtractOptions::new().preserve_times(true);
     let writer = writer::Disk::new();
     writer.set_options(&opts).ok();
     let tempfile = tempfile::tempdir().unwrap();
@@ -154,7 +641,34 @@ fn panic_on_prev_iter() {
     .expect_err("should panic");
 }
 
-fn reader() -> reader::ReaderHandle {
+#[test]
+fn stale_entry_errors() {
+    let mut iter = reader().into_iter();
+    let hello = iter.next().unwrap().unwrap();
+    assert_eq!(hello.try_size().unwrap(), 14);
+    // Reaching the end moves on from the only entry too.
+    assert!(iter.next().is_none());
+
+    assert!(matches!(
+        hello.ensure_current(),
+        Err(ArchiveError::StaleEntry)
+    ));
+    assert!(matches!(
+        hello.try_pathname(),
+        Err(ArchiveError::StaleEntry)
+    ));
+    assert!(matches!(hello.try_size(), Err(ArchiveError::StaleEntry)));
+    assert!(matches!(
+        hello.try_filetype(),
+        Err(ArchiveError::StaleEntry)
+    ));
+    assert!(matches!(
+        hello.try_metadata(),
+        Err(ArchiveError::StaleEntry)
+    ));
+}
+
+fn reader() -> reader::ReaderHandle<'static> {
     reader::Builder::new()
         .support_all()
         .unwrap()
@@ -162,6 +676,29 @@ fn reader() -> reader::ReaderHandle {
         .unwrap()
 }
 
+// Writes a pax archive of `count` entries named `0.txt`, `1.txt` and so on to `dir`, each holding
+// its own name, for tests that need more entries than the fixtures have.
+fn numbered_archive(dir: &tempfile::TempDir, count: usize) -> std::path::PathBuf {
+    let path = dir.path().join("numbered.tar");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    let names: Vec<_> = (0..count).map(|i| format!("{}.txt", i)).collect();
+    writer
+        .write_entries(names.iter().map(|name| (name, name.as_bytes())))
+        .unwrap();
+    writer.close().unwrap();
+    path
+}
+
+fn open_file<P: AsRef<std::path::Path>>(path: P) -> reader::ReaderHandle<'static> {
+    reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(path)
+        .unwrap()
+}
+
 #[test]
 fn multiple_pathname_call() {
     let mut iter = reader().into_iter();
@@ -174,3 +711,2236 @@ fn multiple_pathname_call() {
         assert_eq!(hello.pathname().unwrap().as_str(), "hello.txt");
     };
 }
+
+#[test]
+fn linked_version() {
+    assert!(archive::version_number() >= 3000000);
+    assert!(archive::version_string().starts_with('3'));
+}
+
+#[test]
+fn reading_with_header_charset() {
+    assert!(matches!(
+        archive::set_ctype_locale("C\0UTF-8"),
+        Err(ArchiveError::Locale(_))
+    ));
+    match reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .header_charset("ISO\08859-1")
+        .open_file(util::path::fixture("latin1.tar"))
+    {
+        Err(ArchiveError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput),
+        _ => panic!("expected an error for a NUL in the charset"),
+    }
+
+    archive::set_ctype_locale("C.UTF-8").unwrap();
+    let mut iter = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .header_charset("ISO-8859-1")
+        .open_file(util::path::fixture("latin1.tar"))
+        .unwrap()
+        .into_iter();
+
+    let mut entry = iter.next().unwrap().unwrap();
+    assert_eq!(entry.pathname().unwrap().as_str(), "café.txt");
+
+    let mut string = String::new();
+    entry.read_to_string(&mut string).unwrap();
+    assert_string(&string);
+}
+
+#[test]
+fn read_all_in_memory() {
+    let entries = reader().read_all(None).unwrap();
+    assert_eq!(entries.len(), 1);
+
+    let (metadata, data) = &entries[0];
+    assert_eq!(metadata.pathname.as_deref(), Some("hello.txt"));
+    assert_eq!(metadata.size, 14);
+    assert_eq!(metadata.filetype, reader::ArchiveEntryFiletype::RegularFile);
+    assert_string(std::str::from_utf8(data).unwrap());
+
+    match reader().read_all(Some(4)) {
+        Err(ArchiveError::LimitExceeded(Limit::TotalSize(4))) => (),
+        other => panic!(
+            "expected size limit error, got {:?}",
+            other.map(|it| it.len())
+        ),
+    }
+}
+
+#[test]
+fn probing_format() {
+    let info = libarchive::probe(util::path::fixture("sample.tar.gz")).unwrap();
+    assert!(info.format_name.contains("tar"));
+    assert_eq!(info.filters.len(), 1);
+    assert_eq!(info.filters[0].name, "gzip");
+
+    let f = File::open(util::path::fixture("latin1.tar")).unwrap();
+    let info = libarchive::probe_stream(f).unwrap();
+    assert!(info.format_name.contains("tar"));
+    assert!(info.filters.is_empty());
+}
+
+#[test]
+fn rejecting_long_pathnames() {
+    let limits = reader::HeaderLimits {
+        max_pathname: Some(4),
+        ..Default::default()
+    };
+    let mut iter = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .limits(limits)
+        .open_file(util::path::fixture("sample.tar.gz"))
+        .unwrap()
+        .into_iter();
+
+    match iter.next() {
+        Some(Err(ArchiveError::LimitExceeded(Limit::PathnameLength(4)))) => (),
+        _ => panic!("expected pathname limit error"),
+    }
+}
+
+#[test]
+fn extracting_past_unreadable_entries() {
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(util::path::fixture("corrupt.zip"))
+        .unwrap();
+    let tempfile = tempfile::tempdir().unwrap();
+    let mut writer = writer::Disk::new();
+    writer.set_skip_unreadable(true);
+    writer.write(&mut reader, tempfile.path().to_str()).unwrap();
+
+    let skipped = writer.take_skipped();
+    assert_eq!(skipped.len(), 1);
+    assert!(skipped[0].pathname.ends_with("bad.txt"));
+    assert!(matches!(
+        skipped[0].error,
+        ArchiveError::ChecksumMismatch { ref path } if path.ends_with("bad.txt")
+    ));
+    assert_fixture(&tempfile);
+}
+
+#[test]
+fn verifying_checksums() {
+    let open = |verify| {
+        reader::Builder::new()
+            .support_all()
+            .unwrap()
+            .verify_checksums(verify)
+            .open_file(util::path::fixture("corrupt.zip"))
+            .unwrap()
+    };
+    let mut entry = open(true).into_iter().next().unwrap().unwrap();
+    let err = entry.read_to_end(&mut Vec::new()).unwrap_err();
+    match err
+        .into_inner()
+        .unwrap()
+        .downcast::<ArchiveError>()
+        .map(|e| *e)
+    {
+        Ok(ArchiveError::ChecksumMismatch { path }) => assert_eq!(path, "bad.txt"),
+        other => panic!("unexpected error: {:?}", other),
+    }
+
+    let mut entry = open(false).into_iter().next().unwrap().unwrap();
+    let mut data = Vec::new();
+    entry.read_to_end(&mut data).unwrap();
+    assert_eq!(data.len(), 14);
+}
+
+#[test]
+fn seeking_unsupported_format() {
+    let mut iter = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(util::path::fixture("sample.zip"))
+        .unwrap()
+        .into_iter();
+    let mut hello = iter.next().unwrap().unwrap();
+
+    hello
+        .seek(SeekFrom::Start(7))
+        .expect_err("zip does not support seeking entry data");
+
+    let mut string = String::new();
+    hello.read_to_string(&mut string).unwrap();
+    assert_string(&string);
+}
+
+#[test]
+fn format_capabilities() {
+    let mut iter = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(util::path::fixture("sample.zip"))
+        .unwrap()
+        .into_iter();
+    iter.next().unwrap().unwrap();
+    let caps = iter.reader().format_capabilities();
+    assert!(caps.contains(reader::Capabilities::ENCRYPT_DATA));
+    let both = reader::Capabilities::ENCRYPT_DATA | reader::Capabilities::ENCRYPT_METADATA;
+    assert!(both.contains(reader::Capabilities::ENCRYPT_METADATA));
+    assert!(!reader::Capabilities::ENCRYPT_DATA.contains(both));
+
+    let mut iter = reader().into_iter();
+    iter.next().unwrap().unwrap();
+    assert!(iter.reader().format_capabilities().is_empty());
+}
+
+#[test]
+fn format_and_filter_introspection() {
+    let mut iter = reader().into_iter();
+    iter.next().unwrap().unwrap();
+    let reader = iter.reader();
+    assert_eq!(reader.format_code() & 0xff0000, 0x30000);
+    assert!(reader.format_name().unwrap().contains("tar"));
+    assert_eq!(reader.filter_count(), 2);
+    assert_eq!(reader.filter_name(0).as_deref(), Some("gzip"));
+    assert_eq!(reader.filter_name(1).as_deref(), Some("none"));
+    assert_eq!(reader.filter_name(2), None);
+}
+
+#[test]
+fn decompressing_raw() {
+    let file = std::fs::File::open(util::path::fixture("sample.tar.gz")).unwrap();
+    let mut tar = Vec::new();
+    reader::raw::decompress(file)
+        .unwrap()
+        .read_to_end(&mut tar)
+        .unwrap();
+    assert_eq!(&tar[257..262], b"ustar");
+
+    let plain = std::fs::read(util::path::fixture("escaping.tar")).unwrap();
+    let mut data = Vec::new();
+    reader::raw::decompress(&plain[..])
+        .unwrap()
+        .read_to_end(&mut data)
+        .unwrap();
+    assert_eq!(data, plain);
+}
+
+#[test]
+fn adding_passphrases() {
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .and_then(|builder| builder.add_passphrase("secret"))
+        .and_then(|builder| builder.add_passphrase("other"))
+        .and_then(|builder| builder.open_file(util::path::fixture("sample.zip")))
+        .unwrap();
+    assert!(!reader.list_entries().unwrap().is_empty());
+
+    let err = reader::Builder::new()
+        .add_passphrase("nul\0byte")
+        .err()
+        .unwrap();
+    assert!(matches!(err, ArchiveError::Io(ref e) if e.kind() == std::io::ErrorKind::InvalidInput));
+}
+
+#[test]
+fn passphrase_callback_only_called_for_encrypted_entries() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .and_then(|builder| {
+            builder.passphrase_callback(move || {
+                counter.fetch_add(1, Ordering::Relaxed);
+                None
+            })
+        })
+        .and_then(|builder| builder.open_file(util::path::fixture("sample.zip")))
+        .unwrap();
+    assert!(!reader.read_all(None).unwrap().is_empty());
+    assert_eq!(calls.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn detecting_encrypted_entries() {
+    let mut iter = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(util::path::fixture("sample.zip"))
+        .unwrap()
+        .into_iter();
+    let entry = iter.next().unwrap().unwrap();
+    assert!(!entry.is_data_encrypted());
+    assert!(!entry.is_metadata_encrypted());
+    drop(entry);
+    assert_eq!(iter.reader().has_encrypted_entries(), Some(false));
+
+    let mut iter = reader().into_iter();
+    iter.next().unwrap().unwrap();
+    assert_eq!(iter.reader().has_encrypted_entries(), None);
+}
+
+#[test]
+fn reading_entry_blocks() {
+    let mut iter = reader().into_iter();
+    let mut hello = iter.next().unwrap().unwrap();
+    assert_eq!(hello.pathname().unwrap().as_str(), "hello.txt");
+
+    let mut data = Vec::new();
+    let mut blocks = hello.blocks();
+    while let Some(block) = blocks.next_block() {
+        let (offset, block) = block.unwrap();
+        assert_eq!(offset, data.len() as u64);
+        data.extend_from_slice(block);
+    }
+    assert_string(std::str::from_utf8(&data).unwrap());
+}
+
+#[test]
+fn skipping_entry_data() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = numbered_archive(&tempfile, 3);
+
+    let mut iter = open_file(&path).into_iter();
+    let mut first = iter.next().unwrap().unwrap();
+    first.read_exact(&mut [0; 2]).unwrap();
+    first.skip().unwrap();
+    drop(first);
+    let mut second = iter.next().unwrap().unwrap();
+    assert_eq!(second.pathname().unwrap(), "1.txt");
+    let mut data = String::new();
+    second.read_to_string(&mut data).unwrap();
+    assert_eq!(data, "1.txt");
+    drop(second);
+    assert_eq!(iter.count(), 1);
+
+    let mut reader = open_file(&path);
+    assert!(reader.skip_to("1.txt").unwrap().is_some());
+    reader.skip_data().unwrap();
+    let entry = reader.next_header().unwrap();
+    assert_eq!(entry.pathname(), "2.txt");
+    assert_eq!(reader.read_entry_data().unwrap(), b"2.txt");
+}
+
+#[cfg(unix)]
+#[test]
+fn copying_entry_data_to_a_file() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let path = tempdir.path().join("hello.txt");
+    let file = File::create(&path).unwrap();
+
+    let mut iter = reader().into_iter();
+    let mut hello = iter.next().unwrap().unwrap();
+    hello.copy_to_file(&file).unwrap();
+    drop(file);
+    assert_string(&std::fs::read_to_string(&path).unwrap());
+}
+
+#[test]
+fn extracting_a_single_entry() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let path = tempdir.path().join("nested").join("greeting.txt");
+
+    let mut reader = reader();
+    assert!(reader.skip_to("hello.txt").unwrap().is_some());
+    reader
+        .extract_entry_to(&path, &archive::ExtractOptions::new())
+        .unwrap();
+    assert_string(&std::fs::read_to_string(&path).unwrap());
+    assert!(!tempdir.path().join("hello.txt").exists());
+}
+
+#[test]
+fn reading_entry_data_to_vec() {
+    let mut iter = reader().into_iter();
+    let mut hello = iter.next().unwrap().unwrap();
+    let data = hello.read_to_vec(Some(1024)).unwrap();
+    assert_string(std::str::from_utf8(&data).unwrap());
+    drop(hello);
+
+    let mut iter = reader().into_iter();
+    let mut hello = iter.next().unwrap().unwrap();
+    assert!(matches!(
+        hello.read_to_vec(Some(4)),
+        Err(ArchiveError::LimitExceeded(Limit::EntrySize(4)))
+    ));
+    drop(hello);
+
+    let mut iter = reader().into_iter();
+    let mut hello = iter.next().unwrap().unwrap();
+    hello.read_exact(&mut [0; 4]).unwrap();
+    match hello.read_to_vec(None) {
+        Err(ArchiveError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput),
+        _ => panic!("expected an error for a partly read entry"),
+    }
+}
+
+#[test]
+fn counting_bytes_read() {
+    let size = std::fs::metadata(util::path::fixture("sample.tar.gz"))
+        .unwrap()
+        .len();
+    let mut reader = reader();
+    reader.list_entries().unwrap();
+    assert_eq!(reader.bytes_read_raw(), size);
+    assert!(reader.bytes_read_uncompressed() > reader.bytes_read_raw());
+}
+
+#[test]
+fn guarding_against_decompression_bombs() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = tempfile.path().join("bomb.tar.gz");
+    let mut builder = writer::Builder::new();
+    builder.add_filter(archive::WriteFilter::Gzip).unwrap();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    let zeros = vec![0; 8 << 20];
+    writer.write_entries(vec![("zeros", &zeros[..])]).unwrap();
+    writer.close().unwrap();
+
+    let open = |builder: reader::Builder| {
+        builder
+            .support_all()
+            .and_then(|builder| builder.open_file(&path))
+            .unwrap()
+    };
+    let mut reader = open(reader::Builder::new().max_compression_ratio(100));
+    assert!(matches!(
+        reader.read_all(None),
+        Err(ArchiveError::LimitExceeded(Limit::CompressionRatio(100)))
+    ));
+
+    let mut reader = open(reader::Builder::new().max_total_size(1 << 20));
+    assert!(matches!(
+        reader.read_all(None),
+        Err(ArchiveError::LimitExceeded(Limit::TotalSize(_)))
+    ));
+
+    let mut reader = open(reader::Builder::new().max_total_size(16 << 20));
+    assert_eq!(reader.read_all(None).unwrap()[0].1.len(), 8 << 20);
+}
+
+#[test]
+fn cancelling_reads() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = numbered_archive(&tempfile, 3);
+    let token = Arc::new(AtomicBool::new(false));
+    let mut iter = reader::Builder::new()
+        .cancel_token(token.clone())
+        .support_all()
+        .and_then(|builder| builder.open_file(&path))
+        .unwrap()
+        .into_iter();
+    iter.next().unwrap().unwrap();
+    token.store(true, Ordering::Relaxed);
+    // Checked when the header of the next entry is read.
+    assert!(matches!(iter.next(), Some(Err(ArchiveError::Cancelled))));
+
+    // And while reading data, even of a single entry.
+    let token = Arc::new(AtomicBool::new(false));
+    let mut iter = reader::Builder::new()
+        .cancel_token(token.clone())
+        .support_all()
+        .and_then(|builder| builder.open_file(&path))
+        .unwrap()
+        .into_iter();
+    let mut first = iter.next().unwrap().unwrap();
+    token.store(true, Ordering::Relaxed);
+    let err = first.read_to_end(&mut Vec::new()).unwrap_err();
+    assert!(matches!(
+        err.into_inner().unwrap().downcast_ref::<ArchiveError>(),
+        Some(ArchiveError::Cancelled)
+    ));
+
+    let token = Arc::new(AtomicBool::new(true));
+    let file = File::open(util::path::fixture("sample.tar.gz")).unwrap();
+    let res = reader::Builder::new()
+        .cancel_token(token)
+        .support_all()
+        .and_then(|builder| builder.open_stream(file));
+    assert!(matches!(res, Err(ArchiveError::Cancelled)));
+}
+
+#[test]
+fn reporting_progress() {
+    let read = |path: &std::path::Path, interval| {
+        let reports = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = reports.clone();
+        let mut reader = reader::Builder::new()
+            .on_progress(move |progress| log.lock().unwrap().push(progress))
+            .progress_interval(interval)
+            .support_all()
+            .and_then(|builder| builder.open_file(path))
+            .unwrap();
+        reader.read_all(None).unwrap();
+        let reports = std::mem::take(&mut *reports.lock().unwrap());
+        let last = reports.last().unwrap();
+        assert!(last.bytes_read_uncompressed <= reader.bytes_read_uncompressed());
+        assert!(reports
+            .windows(2)
+            .all(|w| w[0].bytes_read_uncompressed <= w[1].bytes_read_uncompressed));
+        reports
+    };
+    let tempfile = tempfile::tempdir().unwrap();
+
+    // Every header is reported.
+    let reports = read(&numbered_archive(&tempfile, 14), 1);
+    assert!(reports.len() >= 14);
+    assert_eq!(reports.last().unwrap().entries, 14);
+
+    // Reading data is reported once another `interval` bytes came in.
+    let path = tempfile.path().join("large.tar");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    writer
+        .write_entries(vec![("large.bin", &vec![7u8; 256 * 1024][..])])
+        .unwrap();
+    writer.close().unwrap();
+    let reports = read(&path, 1);
+    assert!(reports.len() > 2);
+    assert_eq!(reports.last().unwrap().entries, 1);
+    assert_eq!(read(&path, 1 << 30).len(), 1);
+}
+
+#[test]
+fn borrowing_entries() {
+    let mut reader = reader();
+    let mut entries = reader.entries();
+    let mut hello = entries.next_entry().unwrap().unwrap();
+    assert_eq!(hello.pathname(), "hello.txt");
+    let mut string = String::new();
+    hello.read_to_string(&mut string).unwrap();
+    assert_string(&string);
+    let mut count = 1;
+    while let Some(entry) = entries.next_entry() {
+        entry.unwrap();
+        count += 1;
+    }
+    assert_eq!(count, 1);
+
+    reader.rewind().unwrap();
+    assert_eq!(reader.header_position(), 0);
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+}
+
+#[test]
+fn entry_metadata_outlives_the_entry() {
+    let metadata: Vec<_> = reader()
+        .into_iter()
+        .map(|entry| entry.unwrap().metadata())
+        .collect();
+    assert_eq!(metadata.len(), 1);
+    assert_eq!(metadata[0].pathname.as_deref(), Some("hello.txt"));
+    assert_eq!(metadata[0].size, 14);
+    assert!(matches!(
+        metadata[0].filetype,
+        reader::ArchiveEntryFiletype::RegularFile
+    ));
+}
+
+#[test]
+fn listing_with_list() {
+    let entries = reader().list().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].pathname.as_deref(), Some("hello.txt"));
+}
+
+#[test]
+fn finding_an_entry() {
+    let mut reader = reader();
+    let mut hello = reader
+        .find_entry(|metadata| metadata.pathname.as_deref() == Some("hello.txt"))
+        .unwrap()
+        .unwrap();
+    let mut string = String::new();
+    hello.read_to_string(&mut string).unwrap();
+    assert_string(&string);
+
+    assert!(reader.find_entry(|_| false).unwrap().is_none());
+}
+
+#[test]
+fn random_access_to_entries() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = numbered_archive(&tempfile, 14);
+    let mut reader = open_file(&path);
+    assert_eq!(reader.build_index().unwrap(), 14);
+
+    for index in [5, 2, 13, 0, 0] {
+        let mut entry = reader.entry_at(index).unwrap().unwrap();
+        let name = format!("{}.txt", index);
+        assert_eq!(entry.pathname(), name);
+        let mut data = String::new();
+        entry.read_to_string(&mut data).unwrap();
+        assert_eq!(data, name);
+    }
+    assert!(reader.entry_at(14).unwrap().is_none());
+
+    let mut entry = reader.entry_by_name("7.txt").unwrap().unwrap();
+    let mut data = String::new();
+    entry.read_to_string(&mut data).unwrap();
+    assert_eq!(data, "7.txt");
+    assert!(reader.entry_by_name("missing").unwrap().is_none());
+
+    let file = File::open(&path).unwrap();
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_stream(file)
+        .unwrap();
+    assert_eq!(reader.entry_at(3).unwrap().unwrap().pathname(), "3.txt");
+    assert_eq!(reader.entry_at(8).unwrap().unwrap().pathname(), "8.txt");
+    assert!(matches!(
+        reader.entry_at(2),
+        Err(ArchiveError::NotRewindable)
+    ));
+}
+
+#[test]
+fn telling_the_end_from_errors() {
+    let mut reader = reader();
+    let mut count = 0;
+    while reader.try_next_header().unwrap().is_some() {
+        count += 1;
+    }
+    assert_eq!(count, 1);
+
+    // Cut the archive in the middle of the second header, after the first entry is complete.
+    let tempfile = tempfile::tempdir().unwrap();
+    let mut data = std::fs::read(numbered_archive(&tempfile, 3)).unwrap();
+    let second = data.windows(5).position(|name| name == b"1.txt").unwrap();
+    data.truncate(second + 100);
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_memory(data)
+        .unwrap();
+    let mut count = 0;
+    let res = loop {
+        match reader.try_next_header() {
+            Ok(Some(_)) => count += 1,
+            Ok(None) => break Ok(()),
+            Err(err) => break Err(err),
+        }
+    };
+    assert_eq!(count, 1);
+    assert!(res.is_err());
+}
+
+#[test]
+fn skipping_entries() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = numbered_archive(&tempfile, 14);
+
+    let mut iter = open_file(&path).into_iter();
+    let entry = iter.nth(3).unwrap().unwrap();
+    assert_eq!(entry.pathname().unwrap(), "3.txt");
+    drop(entry);
+    assert_eq!(iter.skip_entries(5).unwrap(), 5);
+    let entry = iter.next().unwrap().unwrap();
+    assert_eq!(entry.pathname().unwrap(), "9.txt");
+    drop(entry);
+    assert_eq!(iter.skip_entries(10).unwrap(), 4);
+    assert!(iter.next().is_none());
+
+    assert!(open_file(&path).into_iter().nth(14).is_none());
+}
+
+#[test]
+fn reading_on_another_thread() {
+    let mut reader = reader();
+    assert!(reader.skip_to("hello.txt").unwrap().is_some());
+    let data = std::thread::spawn(move || reader.read_entry_data().unwrap())
+        .join()
+        .unwrap();
+    assert_string(std::str::from_utf8(&data).unwrap());
+}
+
+#[test]
+fn taking_warnings() {
+    let mut reader = reader();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+    assert!(reader.take_warnings().is_empty());
+
+    // The pax attributes before hello.txt are malformed, so they are ignored but the entry is
+    // still read.
+    let mut reader = open_file(util::path::fixture("malformed_pax.tar"));
+    let entries = reader.list_entries().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].pathname.as_deref(), Some("hello.txt"));
+    let warnings = reader.take_warnings();
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].message.contains("pax"), "{}", warnings[0]);
+    assert!(reader.take_warnings().is_empty());
+}
+
+#[test]
+fn entry_timestamps() {
+    let at = |secs, nanos| std::time::UNIX_EPOCH + std::time::Duration::new(secs, nanos);
+    let (mtime, atime, ctime) = (
+        at(1_600_000_000, 123_456_789),
+        at(1_600_000_100, 1),
+        at(1_600_000_200, 999_999_999),
+    );
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = tempfile.path().join("times.tar");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    writer
+        .append_archive_with(&mut reader(), |entry| {
+            entry.set_mtime(mtime);
+            entry.set_atime(atime);
+            entry.set_ctime(ctime);
+        })
+        .unwrap();
+    writer.close().unwrap();
+    let open = || {
+        reader::Builder::new()
+            .support_all()
+            .unwrap()
+            .open_file(&path)
+            .unwrap()
+    };
+
+    let mut times = open();
+    let header = times.next_header().unwrap();
+    assert_eq!(header.mtime(), Some(mtime));
+    assert_eq!(header.atime(), Some(atime));
+    assert_eq!(header.ctime(), Some(ctime));
+    assert_eq!(header.birthtime(), None);
+
+    let mut iter = open().into_iter();
+    let entry = iter.next().unwrap().unwrap();
+    assert_eq!(entry.mtime(), Some(mtime));
+    assert_eq!(entry.atime(), Some(atime));
+    assert_eq!(entry.ctime(), Some(ctime));
+    assert_eq!(entry.birthtime(), None);
+}
+
+#[test]
+fn entry_modes() {
+    let mut sample = reader();
+    let header = sample.next_header().unwrap();
+    assert_eq!(header.mode(), 0o100644);
+    assert_eq!(header.perm(), 0o644);
+    assert_eq!(header.mode_string(), "-rw-r--r--");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(header.permissions().mode(), 0o644);
+    }
+    let metadata = reader().list_entries().unwrap().remove(0);
+    assert_eq!(metadata.mode, 0o100644);
+    assert_eq!(metadata.perm, 0o644);
+
+    let mut iter = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(util::path::fixture("setuid.tar"))
+        .unwrap()
+        .into_iter();
+    let entry = iter.next().unwrap().unwrap();
+    assert_eq!(entry.mode(), 0o104775);
+    assert_eq!(entry.perm(), 0o4775);
+    assert_eq!(entry.mode_string(), "-rwsrwxr-x");
+}
+
+#[test]
+fn extract_options_flags() {
+    use archive::{ExtractOptions, Overwrite};
+
+    let opts = ExtractOptions::new()
+        .preserve_times(true)
+        .overwrite(Overwrite::Never)
+        .overwrite(Overwrite::IfOlder);
+    let expected = ExtractOptions::new()
+        .preserve_times(true)
+        .overwrite(Overwrite::IfOlder);
+    assert_eq!(opts.flags, expected.flags);
+    assert_eq!(ExtractOptions::new().secure(true).secure(false).flags, 0);
+    assert_ne!(ExtractOptions::new().create_parent_dirs(false).flags, 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn extracting_with_permission_mask() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(util::path::fixture("setuid.tar"))
+        .unwrap();
+    let tempfile = tempfile::tempdir().unwrap();
+    let mut writer = writer::Disk::new();
+    writer
+        .set_options(&archive::ExtractOptions::new().preserve_permissions(true))
+        .unwrap();
+    writer.set_permission_mask(0o6022);
+    writer.write(&mut reader, tempfile.path().to_str()).unwrap();
+
+    let metadata = std::fs::metadata(tempfile.path().join("hello.txt")).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o7777, 0o755);
+    assert_fixture(&tempfile);
+}
+
+#[test]
+fn extracting_with_fsync() {
+    let mut reader = reader();
+    let tempfile = tempfile::tempdir().unwrap();
+    let mut writer = writer::Disk::new();
+    writer.set_fsync(true);
+    let bytes = writer.write(&mut reader, tempfile.path().to_str()).unwrap();
+    assert_eq!(bytes, 14);
+    assert_fixture(&tempfile);
+}
+
+#[test]
+fn unified_archive() {
+    let archive = libarchive::Archive::open(util::path::fixture("sample.tar.gz")).unwrap();
+    assert_eq!(archive.info().filters.len(), 1);
+    assert_eq!(archive.entries().unwrap().count(), 1);
+    assert_string(std::str::from_utf8(&archive.read_entry("hello.txt").unwrap().unwrap()).unwrap());
+    assert!(archive.read_entry("missing.txt").unwrap().is_none());
+    archive.verify().unwrap();
+
+    let tempfile = tempfile::tempdir().unwrap();
+    assert_eq!(archive.extract_to(tempfile.path()).unwrap(), 14);
+    assert_fixture(&tempfile);
+
+    let corrupt = libarchive::Archive::open(util::path::fixture("corrupt.zip")).unwrap();
+    assert!(corrupt.verify().is_err());
+}
+
+#[test]
+fn opening_empty_files() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = tempfile.path().join("upload.bin");
+    File::create(&path).unwrap();
+
+    let archive = libarchive::Archive::open(&path).unwrap();
+    assert_eq!(archive.info().format_name, "Empty file");
+    assert_eq!(archive.entries().unwrap().count(), 0);
+    assert_eq!(archive.extract_to(tempfile.path().join("out")).unwrap(), 0);
+    archive.verify().unwrap();
+    assert!(archive.read_entry("a.txt").unwrap().is_none());
+
+    // Empty files named like an archive open the same way.
+    let named = tempfile.path().join("upload.tar.gz");
+    File::create(&named).unwrap();
+    let mut reader = reader::Builder::new()
+        .support_for_path(&named)
+        .unwrap()
+        .open_file(&named)
+        .unwrap();
+    assert!(reader.list_entries().unwrap().is_empty());
+}
+
+#[test]
+fn listing_entries() {
+    let mut entries = reader().into_iter();
+    let entry = entries.next().unwrap().unwrap();
+    assert_eq!(
+        entry.listing().to_string(),
+        "-rw-r--r--  0 reset  staff      14 Dec 10  2015 hello.txt"
+    );
+
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(util::path::fixture("setuid.tar"))
+        .unwrap();
+    let entry = reader.next_header().unwrap();
+    assert_eq!(
+        entry.listing().to_string(),
+        "-rwsrwxr-x  0 0      0          14 Dec 10  2022 hello.txt"
+    );
+}
+
+#[test]
+fn extracting_with_progress() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl archive::Progress for Recorder {
+        fn entry_started(&mut self, pathname: &str, total: Option<u64>) {
+            let name = std::path::Path::new(pathname).file_name().unwrap();
+            let event = format!("start {} {:?}", name.to_string_lossy(), total);
+            self.0.borrow_mut().push(event);
+        }
+
+        fn bytes_processed(&mut self, bytes: u64) {
+            self.0.borrow_mut().push(format!("bytes {}", bytes));
+        }
+
+        fn entry_finished(&mut self, _pathname: &str) {
+            self.0.borrow_mut().push("finish".to_string());
+        }
+    }
+
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let mut reader = reader();
+    let tempfile = tempfile::tempdir().unwrap();
+    let mut writer = writer::Disk::new();
+    writer.set_progress(Recorder(events.clone()));
+    writer.write(&mut reader, tempfile.path().to_str()).unwrap();
+    assert_eq!(
+        *events.borrow(),
+        vec!["start hello.txt Some(14)", "bytes 14", "finish"]
+    );
+}
+
+#[cfg(feature = "manifest")]
+#[test]
+fn extracting_with_manifest() {
+    let mut reader = reader();
+    let tempfile = tempfile::tempdir().unwrap();
+    let manifest = tempfile.path().join("SHA256SUMS");
+    let mut writer = writer::Disk::new();
+    writer.set_manifest(File::create(&manifest).unwrap());
+    writer.write(&mut reader, tempfile.path().to_str()).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(manifest).unwrap(),
+        "4dca0fd5f424a31b03ab807cbae77eb32bf2d089eed1cee154b3afed458de0dc  hello.txt\n"
+    );
+}
+
+#[cfg(feature = "manifest")]
+#[test]
+fn resuming_extraction() {
+    use std::io::Write;
+
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = tempfile.path().join("resumable.tar");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    writer
+        .write_entries(vec![("a.txt", &b"a"[..]), ("b.txt", &b"b"[..])])
+        .unwrap();
+    writer.close().unwrap();
+
+    let out = tempfile.path().join("out");
+    let checkpoint = tempfile.path().join("checkpoint");
+    let extract = || {
+        let mut disk = writer::Disk::new();
+        disk.set_checkpoint(&checkpoint).unwrap();
+        let mut reader = reader::Builder::new()
+            .support_all()
+            .unwrap()
+            .open_file(&path)
+            .unwrap();
+        disk.write(&mut reader, out.to_str()).unwrap()
+    };
+    assert_eq!(extract(), 2);
+
+    // A line cut short by a crash is dropped, and files gone since are extracted again.
+    let mut file = File::options().append(true).open(&checkpoint).unwrap();
+    file.write_all(b"ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb  c.t")
+        .unwrap();
+    std::fs::write(out.join("a.txt"), "kept").unwrap();
+    std::fs::remove_file(out.join("b.txt")).unwrap();
+    assert_eq!(extract(), 1);
+    assert_eq!(std::fs::read_to_string(out.join("a.txt")).unwrap(), "kept");
+    assert_eq!(std::fs::read_to_string(out.join("b.txt")).unwrap(), "b");
+    let lines: Vec<String> = std::fs::read_to_string(&checkpoint)
+        .unwrap()
+        .lines()
+        .map(|line| line.split_once("  ").unwrap().1.to_string())
+        .collect();
+    assert_eq!(lines, vec!["a.txt", "b.txt", "b.txt"]);
+}
+
+#[test]
+fn closing_readers() {
+    let mut reader = reader();
+    reader.list_entries().unwrap();
+    reader.close().unwrap();
+    assert!(reader.list_entries().is_err());
+}
+
+#[test]
+fn supporting_from_lists() {
+    use archive::{ReadFilter, ReadFormat};
+
+    let mut reader = reader::Builder::new()
+        .support_formats(&[ReadFormat::Tar, ReadFormat::Zip])
+        .unwrap()
+        .support_filters(&[ReadFilter::Gzip])
+        .unwrap()
+        .open_file(util::path::fixture("sample.tar.gz"))
+        .unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+
+    // libarchive has no built-in lrzip or grzip decoder and warns it will run the programs.
+    let filters = [ReadFilter::Lrzip, ReadFilter::Gzip, ReadFilter::Grzip];
+    match reader::Builder::new().support_filters(&filters) {
+        Err(ArchiveError::Multiple(errors)) => assert_eq!(errors.len(), 2),
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("expected an error"),
+    }
+}
+
+#[test]
+fn supporting_formats_by_path() {
+    let open = |path: &std::path::Path| {
+        reader::Builder::new()
+            .support_for_path(path)
+            .unwrap()
+            .open_file(path)
+    };
+    let mut reader = open(&util::path::fixture("sample.tar.gz")).unwrap();
+    assert_eq!(reader.list_entries().unwrap().len(), 1);
+
+    // A zip named like a tarball is not recognized, one without an extension still is.
+    let tempfile = tempfile::tempdir().unwrap();
+    let misnamed = tempfile.path().join("sample.tgz");
+    std::fs::copy(util::path::fixture("sample.zip"), &misnamed).unwrap();
+    assert!(open(&misnamed).and_then(|mut r| r.list_entries()).is_err());
+    let bare = tempfile.path().join("sample");
+    std::fs::copy(util::path::fixture("sample.zip"), &bare).unwrap();
+    assert!(!open(&bare).unwrap().list_entries().unwrap().is_empty());
+
+    // Filters the name implies are enabled like through `support_filter`.
+    #[cfg(feature = "zstd")]
+    {
+        let zst = tempfile.path().join("sample.tzst");
+        let mut builder = writer::Builder::new();
+        builder.add_filter(archive::WriteFilter::Zstd).unwrap();
+        builder.set_format(archive::WriteFormat::Pax).unwrap();
+        let mut writer = builder.open_file(&zst).unwrap();
+        writer
+            .write_entries(vec![("hello.txt", &b"hello"[..])])
+            .unwrap();
+        writer.close().unwrap();
+        assert_eq!(open(&zst).unwrap().read_all(None).unwrap()[0].1, b"hello");
+    }
+}
+
+#[test]
+fn skipping_to_entries() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = tempfile.path().join("skipping.tar");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    writer
+        .write_entries(vec![
+            ("a.txt", &b"a"[..]),
+            ("b.txt", &b"b"[..]),
+            ("c.txt", &b"c"[..]),
+            ("d.txt", &b"d"[..]),
+        ])
+        .unwrap();
+    writer.close().unwrap();
+
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(&path)
+        .unwrap();
+    let entry = reader.skip_to("b.txt").unwrap().unwrap();
+    assert_eq!(entry.pathname(), "b.txt");
+    assert_eq!(reader.read_entry_data().unwrap(), b"b");
+    let entry = reader.skip_to(3).unwrap().unwrap();
+    assert_eq!(entry.pathname(), "d.txt");
+    // Entries already passed are not found again.
+    assert!(reader.skip_to(2).unwrap().is_none());
+    assert!(reader.skip_to("a.txt").unwrap().is_none());
+}
+
+#[test]
+fn detecting_mime() {
+    let mime = libarchive::detect_mime(util::path::fixture("sample.tar.gz")).unwrap();
+    assert_eq!(mime.content_type, "application/x-tar");
+    assert_eq!(mime.content_encoding, vec!["gzip"]);
+
+    let zip = File::open(util::path::fixture("sample.zip")).unwrap();
+    let mime = libarchive::detect_mime_stream(zip).unwrap();
+    assert_eq!(mime.content_type, "application/zip");
+    assert!(mime.content_encoding.is_empty());
+}
+
+#[test]
+fn sanitizing_paths() {
+    use libarchive::paths;
+
+    assert_eq!(paths::sanitize("/etc/passwd"), "etc/passwd");
+    assert_eq!(paths::sanitize("C:\\Windows\\win.ini"), "Windows/win.ini");
+    assert_eq!(paths::sanitize("a/./b/../../../c"), "c");
+    assert_eq!(paths::sanitize("../.."), "");
+
+    let root = tempfile::tempdir().unwrap();
+    assert!(paths::is_safe(root.path(), "a/b/../c"));
+    assert!(!paths::is_safe(root.path(), "a/../../c"));
+    assert!(!paths::is_safe(root.path(), "/etc/passwd"));
+    assert!(!paths::is_safe(root.path(), "D:evil"));
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink("/tmp", root.path().join("link")).unwrap();
+        assert!(!paths::is_safe(root.path(), "link/file"));
+        assert!(paths::is_safe(root.path(), "link"));
+    }
+}
+
+#[test]
+fn extracting_with_sanitized_paths() {
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(util::path::fixture("escaping.tar"))
+        .unwrap();
+    let tempfile = tempfile::tempdir().unwrap();
+    let mut writer = writer::Disk::new();
+    writer.set_sanitize_paths(true);
+    writer.write(&mut reader, tempfile.path().to_str()).unwrap();
+    assert!(tempfile.path().join("tmp/abs.txt").is_file());
+    assert!(tempfile.path().join("up.txt").is_file());
+}
+
+#[test]
+fn writing_entries_from_memory() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = tempfile.path().join("generated.tar.gz");
+    let mut builder = writer::Builder::new();
+    builder.add_filter(archive::WriteFilter::Gzip).unwrap();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    let report = String::from("<h1>report</h1>\n");
+    writer
+        .write_entries(vec![
+            ("report.html", report.as_bytes()),
+            ("out/empty.txt", &b""[..]),
+        ])
+        .unwrap();
+    writer.close().unwrap();
+
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(&path)
+        .unwrap();
+    let entries = reader.read_all(None).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].0.pathname.as_deref(), Some("report.html"));
+    assert_eq!(entries[0].1, report.as_bytes());
+    assert_eq!(entries[1].0.pathname.as_deref(), Some("out/empty.txt"));
+    assert!(entries[1].1.is_empty());
+}
+
+#[test]
+fn archiving_incrementally() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let src = tempfile.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    std::fs::write(src.join("a.txt"), "unchanged\n").unwrap();
+    std::fs::write(src.join("b.txt"), "before\n").unwrap();
+
+    let incremental = |name: &str, baseline: &writer::Baseline| {
+        let builder = writer::Builder::new();
+        builder.set_format(archive::WriteFormat::Pax).unwrap();
+        let mut writer = builder
+            .open_file(tempfile.path().join(format!("{}.tar", name)))
+            .unwrap();
+        let manifest = tempfile.path().join(format!("{}.mtree", name));
+        let count = writer
+            .append_incremental(&src, baseline, &manifest)
+            .unwrap();
+        writer.close().unwrap();
+        (count, manifest)
+    };
+
+    let (count, manifest) = incremental("full", &writer::Baseline::new());
+    assert_eq!(count, 3);
+
+    std::fs::write(src.join("b.txt"), "after the change\n").unwrap();
+    let baseline = writer::Baseline::open(&manifest).unwrap();
+    let (count, _manifest) = incremental("incremental", &baseline);
+    assert_eq!(count, 1);
+
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(tempfile.path().join("incremental.tar"))
+        .unwrap();
+    let entries = reader.read_all(None).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert!(entries[0]
+        .0
+        .pathname
+        .as_ref()
+        .unwrap()
+        .ends_with("src/b.txt"));
+    assert_eq!(entries[0].1, b"after the change\n");
+
+    // A touched file with the same contents is skipped when checksums are compared.
+    #[cfg(all(feature = "digest", feature = "manifest"))]
+    {
+        let touched = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1 << 30);
+        File::options()
+            .write(true)
+            .open(src.join("a.txt"))
+            .unwrap()
+            .set_modified(touched)
+            .unwrap();
+        let baseline = writer::Baseline::open(&_manifest).unwrap();
+        let (count, _) = incremental("touched", &baseline);
+        assert_eq!(count, 0);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn archiving_hardlinks() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let src = tempfile.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    std::fs::write(src.join("a.txt"), "hello, world!\n").unwrap();
+    std::fs::hard_link(src.join("a.txt"), src.join("b.txt")).unwrap();
+
+    let archive = |format: archive::WriteFormat, detect: bool| {
+        let path = tempfile.path().join("links.archive");
+        let builder = writer::Builder::new();
+        builder.set_format(format).unwrap();
+        let mut writer = builder.open_file(&path).unwrap();
+        writer.set_detect_hardlinks(detect);
+        writer.append_path(&src).unwrap();
+        writer.close().unwrap();
+        let mut reader = reader::Builder::new()
+            .support_all()
+            .unwrap()
+            .open_file(&path)
+            .unwrap();
+        reader.read_all(None).unwrap()
+    };
+
+    let entries = archive(archive::WriteFormat::Pax, true);
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries.iter().filter(|e| e.0.hardlink.is_some()).count(), 1);
+    assert_eq!(entries.iter().map(|e| e.1.len()).sum::<usize>(), 14);
+
+    let entries = archive(archive::WriteFormat::Pax, false);
+    assert!(entries.iter().all(|e| e.0.hardlink.is_none()));
+    assert_eq!(entries.iter().map(|e| e.1.len()).sum::<usize>(), 28);
+
+    // newc stores the data with the last link, which is only known once the walk is done.
+    let entries = archive(archive::WriteFormat::CpioNewc, true);
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries.iter().map(|e| e.1.len()).sum::<usize>(), 14);
+}
+
+#[cfg(unix)]
+#[test]
+fn archiving_symlinks() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let src = tempfile.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    std::fs::write(src.join("real.txt"), "hello, world!\n").unwrap();
+    std::os::unix::fs::symlink("real.txt", src.join("link.txt")).unwrap();
+    let top = tempfile.path().join("top");
+    std::os::unix::fs::symlink(&src, &top).unwrap();
+
+    let archive = |follow: archive::FollowSymlinks| {
+        let path = tempfile.path().join("links.tar");
+        let builder = writer::Builder::new();
+        builder.set_format(archive::WriteFormat::Pax).unwrap();
+        let mut writer = builder.open_file(&path).unwrap();
+        writer.set_follow_symlinks(follow);
+        writer.append_path(&top).unwrap();
+        writer.close().unwrap();
+        let mut reader = reader::Builder::new()
+            .support_all()
+            .unwrap()
+            .open_file(&path)
+            .unwrap();
+        reader
+            .read_all(None)
+            .unwrap()
+            .into_iter()
+            .map(|(metadata, _)| metadata.filetype)
+            .collect::<Vec<_>>()
+    };
+
+    use libarchive::reader::ArchiveEntryFiletype::*;
+    assert_eq!(archive(archive::FollowSymlinks::Never), vec![SymbolicLink]);
+    let mut types = archive(archive::FollowSymlinks::CommandLineOnly);
+    types.sort_by_key(|t| format!("{:?}", t));
+    assert_eq!(types, vec![Directory, RegularFile, SymbolicLink]);
+    let mut types = archive(archive::FollowSymlinks::Always);
+    types.sort_by_key(|t| format!("{:?}", t));
+    assert_eq!(types, vec![Directory, RegularFile, RegularFile]);
+}
+
+#[test]
+fn archiving_with_fixed_ownership() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let src = tempfile.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    std::fs::write(src.join("a.txt"), "hello, world!\n").unwrap();
+
+    let path = tempfile.path().join("owned.tar");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    writer.set_ownership(archive::Ownership::Fixed { uid: 0, gid: 0 });
+    writer.append_path(&src).unwrap();
+    writer.close().unwrap();
+
+    let reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(&path)
+        .unwrap();
+    for entry in reader {
+        let listing = entry.unwrap().listing().to_string();
+        assert!(listing.contains(" 0      0   "), "{}", listing);
+    }
+}
+
+#[test]
+fn archiving_in_path_order() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let src = tempfile.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    for name in ["c.txt", "a.txt", "b.txt"] {
+        std::fs::write(src.join(name), name).unwrap();
+    }
+    std::fs::create_dir(src.join("b")).unwrap();
+    std::fs::write(src.join("b").join("z.txt"), "z").unwrap();
+
+    let names = |order| {
+        let path = tempfile.path().join("ordered.tar");
+        let builder = writer::Builder::new();
+        builder.set_format(archive::WriteFormat::Pax).unwrap();
+        let mut writer = builder.open_file(&path).unwrap();
+        writer.set_order(order);
+        writer.append_path(&src).unwrap();
+        writer.close().unwrap();
+
+        let reader = reader::Builder::new()
+            .support_all()
+            .unwrap()
+            .open_file(&path)
+            .unwrap();
+        let prefix = src.to_str().unwrap().trim_start_matches('/').to_string();
+        reader
+            .into_iter()
+            .map(|entry| {
+                let name = entry.unwrap().pathname().unwrap();
+                let name = name.trim_start_matches('/');
+                name.trim_start_matches(prefix.as_str()).to_string()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        names(archive::EntryOrder::Path),
+        vec!["/", "/a.txt", "/b/", "/b.txt", "/b/z.txt", "/c.txt"]
+    );
+    let reversed = archive::EntryOrder::Custom(Box::new(|a, b| b.cmp(a)));
+    assert_eq!(
+        names(reversed),
+        vec!["/c.txt", "/b/z.txt", "/b.txt", "/b/", "/a.txt", "/"]
+    );
+}
+
+#[test]
+fn writing_ar_archives() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let long = "a-rather-long-member-name.txt";
+    let names = |path: &std::path::Path| {
+        let reader = reader::Builder::new()
+            .support_all()
+            .unwrap()
+            .open_file(path)
+            .unwrap();
+        reader
+            .into_iter()
+            .map(|entry| entry.unwrap().pathname().unwrap())
+            .collect::<Vec<_>>()
+    };
+    let entries = || vec![("debian-binary", &b"2.0\n"[..]), (long, &b"long\n"[..])];
+
+    let path = tempfile.path().join("gnu.a");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::ArSvr4).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    writer.write_ar_name_table(["debian-binary", long]).unwrap();
+    writer.write_entries(entries()).unwrap();
+    writer.close().unwrap();
+    assert_eq!(names(&path), vec!["//", "debian-binary", long]);
+
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::ArSvr4).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    assert!(writer.write_entries(entries()).is_err());
+
+    let path = tempfile.path().join("bsd.a");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::ArBsd).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    writer.write_entries(entries()).unwrap();
+    writer.close().unwrap();
+    assert_eq!(names(&path), vec!["debian-binary", long]);
+}
+
+#[cfg(feature = "threads")]
+#[test]
+fn compressing_with_threads() {
+    assert!(!archive::WriteFilter::Gzip.supports_threads());
+    let tempfile = tempfile::tempdir().unwrap();
+    for (filter, name) in [
+        (archive::WriteFilter::Xz, "threaded.tar.xz"),
+        #[cfg(feature = "zstd")]
+        (archive::WriteFilter::Zstd, "threaded.tar.zst"),
+    ] {
+        let path = tempfile.path().join(name);
+        let mut builder = writer::Builder::new();
+        builder.set_threads(2);
+        builder.add_filter(filter).unwrap();
+        builder.set_format(archive::WriteFormat::Pax).unwrap();
+        let mut writer = builder.open_file(&path).unwrap();
+        writer
+            .write_entries(vec![("data.bin", &[7u8; 1 << 20][..])])
+            .unwrap();
+        writer.close().unwrap();
+
+        let mut reader = reader::Builder::new()
+            .support_all()
+            .unwrap()
+            .open_file(&path)
+            .unwrap();
+        let data = reader.read_all(None).unwrap();
+        assert_eq!(data.len(), 1);
+        assert_eq!(data[0].1, vec![7u8; 1 << 20]);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn writing_shell_archives() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let write = |format, name: &str| {
+        let path = tempfile.path().join(name);
+        let builder = writer::Builder::new();
+        builder.set_format(format).unwrap();
+        let mut writer = builder.open_file(&path).unwrap();
+        writer.write_directory("docs", 0o755).unwrap();
+        writer
+            .write_entries(vec![("docs/readme.txt", &b"hello shar\n"[..])])
+            .unwrap();
+        writer.close().unwrap();
+        std::fs::read_to_string(path).unwrap()
+    };
+
+    let script = write(archive::WriteFormat::Shar, "plain.shar");
+    assert!(script.starts_with("#!/bin/sh"));
+    let out = tempfile.path().join("out");
+    std::fs::create_dir(&out).unwrap();
+    let status = std::process::Command::new("sh")
+        .arg(tempfile.path().join("plain.shar"))
+        .current_dir(&out)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(
+        std::fs::read_to_string(out.join("docs/readme.txt")).unwrap(),
+        "hello shar\n"
+    );
+
+    let dump = write(archive::WriteFormat::SharDump, "dump.shar");
+    assert!(dump.contains("uudecode"));
+    assert!(!dump.contains("hello shar"));
+}
+
+#[test]
+fn transcoding_archives() {
+    let epoch = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = tempfile.path().join("transcoded.tar.gz");
+    let mut builder = writer::Builder::new();
+    builder.add_filter(archive::WriteFilter::Gzip).unwrap();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    let mut zip = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(util::path::fixture("sample.zip"))
+        .unwrap();
+    let count = writer
+        .append_archive_with(&mut zip, |entry| {
+            let moved = std::path::Path::new("root").join(entry.pathname());
+            entry.set_pathname(&moved);
+            entry.set_uid(0);
+            if entry.mtime().is_none_or(|mtime| mtime > epoch) {
+                entry.set_mtime(epoch);
+            }
+        })
+        .unwrap();
+    writer.close().unwrap();
+    assert_eq!(count, 1);
+
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(&path)
+        .unwrap();
+    let entry = reader.next_header().unwrap();
+    assert_eq!(entry.pathname(), "root/hello.txt");
+    assert_eq!(entry.mtime(), Some(epoch));
+    assert_eq!(reader.read_entry_data().unwrap(), b"hello, world!\n");
+}
+
+#[test]
+fn restoring_timestamps() {
+    let mtime = std::time::UNIX_EPOCH + std::time::Duration::new(1_600_000_000, 123_456_789);
+    let birthtime = std::time::UNIX_EPOCH + std::time::Duration::new(1_500_000_000, 987_654_321);
+    let whole = |time: std::time::SystemTime| {
+        let since = time.duration_since(std::time::UNIX_EPOCH).unwrap();
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(since.as_secs())
+    };
+    let tempfile = tempfile::tempdir().unwrap();
+    let source = tempfile.path().join("source.tar");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let mut writer = builder.open_file(&source).unwrap();
+    writer.write_entries(vec![("a.txt", &b"a"[..])]).unwrap();
+    writer.close().unwrap();
+
+    // Formats without sub-second times keep whole seconds, and only pax records a birthtime.
+    for (name, format, expected_mtime, expected_birthtime) in [
+        ("pax", archive::WriteFormat::Pax, mtime, Some(birthtime)),
+        ("gnutar", archive::WriteFormat::Gnutar, whole(mtime), None),
+        ("ustar", archive::WriteFormat::Ustar, whole(mtime), None),
+    ] {
+        let path = tempfile.path().join("times.tar");
+        let builder = writer::Builder::new();
+        builder.set_format(format).unwrap();
+        let mut writer = builder.open_file(&path).unwrap();
+        let mut reader = reader::Builder::new()
+            .support_all()
+            .unwrap()
+            .open_file(&source)
+            .unwrap();
+        writer
+            .append_archive_with(&mut reader, |entry| {
+                entry.set_mtime(mtime);
+                entry.set_birthtime(birthtime);
+            })
+            .unwrap();
+        writer.close().unwrap();
+
+        let mut reader = reader::Builder::new()
+            .support_all()
+            .unwrap()
+            .open_file(&path)
+            .unwrap();
+        let entry = reader.next_header().unwrap();
+        assert_eq!(entry.mtime(), Some(expected_mtime));
+        assert_eq!(entry.birthtime(), expected_birthtime);
+
+        let out = tempfile.path().join(name);
+        let disk = writer::Disk::new();
+        disk.set_options(&archive::ExtractOptions::new().preserve_times(true))
+            .unwrap();
+        let mut reader = reader::Builder::new()
+            .support_all()
+            .unwrap()
+            .open_file(&path)
+            .unwrap();
+        disk.write(&mut reader, out.to_str()).unwrap();
+        disk.close().unwrap();
+        let metadata = std::fs::metadata(out.join("a.txt")).unwrap();
+        assert_eq!(metadata.modified().unwrap(), expected_mtime);
+        #[cfg(any(target_os = "macos", target_os = "freebsd", windows))]
+        if let Some(expected) = expected_birthtime {
+            assert_eq!(metadata.created().unwrap(), expected);
+        }
+    }
+}
+
+#[cfg(all(unix, feature = "symlink-type"))]
+#[test]
+fn recording_symlink_types() {
+    use libarchive::archive::SymlinkType;
+
+    let tempfile = tempfile::tempdir().unwrap();
+    let tree = tempfile.path().join("tree");
+    std::fs::create_dir_all(tree.join("dir")).unwrap();
+    std::fs::write(tree.join("file"), "a").unwrap();
+    std::os::unix::fs::symlink("dir", tree.join("to_dir")).unwrap();
+    std::os::unix::fs::symlink("file", tree.join("to_file")).unwrap();
+    std::os::unix::fs::symlink("missing", tree.join("dangling")).unwrap();
+
+    let path = tempfile.path().join("links.tar");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    writer.append_path(&tree).unwrap();
+    writer.close().unwrap();
+
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(&path)
+        .unwrap();
+    let mut types = std::collections::HashMap::new();
+    while let Some(entry) = reader.next_header() {
+        if let Some(name) = entry.pathname().strip_prefix(tree.to_str().unwrap()) {
+            types.insert(name.to_string(), entry.symlink_type().unwrap());
+        }
+    }
+    assert_eq!(types["/to_dir"], SymlinkType::Directory);
+    assert_eq!(types["/to_file"], SymlinkType::File);
+    assert_eq!(types["/dangling"], SymlinkType::Undefined);
+    assert_eq!(types["/file"], SymlinkType::Undefined);
+}
+
+#[test]
+fn round_tripping_acls() {
+    use libarchive::{AclEntry, AclEntryType, AclPerms, AclTag, AclType};
+
+    let tempfile = tempfile::tempdir().unwrap();
+    let source = tempfile.path().join("source.tar");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Ustar).unwrap();
+    let mut writer = builder.open_file(&source).unwrap();
+    writer
+        .write_entries(vec![("nfs4.txt", &b"a"[..]), ("posix.txt", &b"b"[..])])
+        .unwrap();
+    writer.close().unwrap();
+
+    let staff = AclEntry {
+        entry_type: AclEntryType::Allow,
+        tag: AclTag::Group,
+        perms: AclPerms::READ_DATA | AclPerms::WRITE_DATA | AclPerms::FILE_INHERIT,
+        id: Some(50),
+        name: Some("staff".to_string()),
+    };
+    let path = tempfile.path().join("acls.tar");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(&source)
+        .unwrap();
+    writer
+        .append_archive_with(&mut reader, |entry| {
+            if entry.pathname() == "nfs4.txt" {
+                entry.add_acl_entry(&staff).unwrap();
+                entry
+                    .add_acl_text(AclType::Nfs4, "everyone@:r-----a-R-c--s:-------:deny")
+                    .unwrap();
+            } else {
+                entry
+                    .add_acl_text(
+                        AclType::Posix1e,
+                        "user::rw-\ngroup::r--\nother::---\nuser:bin:rwx:2\nmask::rwx",
+                    )
+                    .unwrap();
+            }
+        })
+        .unwrap();
+    writer.close().unwrap();
+
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(&path)
+        .unwrap();
+    let entry = reader.next_header().unwrap();
+    let acl = entry.acl(AclType::Nfs4);
+    assert_eq!(acl.len(), 2);
+    assert!(acl.contains(&staff));
+    assert!(acl.iter().any(|ace| ace.entry_type == AclEntryType::Deny
+        && ace.tag == AclTag::Everyone
+        && ace.perms.contains(AclPerms::READ_DATA)));
+    assert!(entry.acl(AclType::Posix1e).is_empty());
+    assert_eq!(entry.acl_text(AclType::Posix1e), None);
+    let text = entry.acl_text(AclType::Nfs4).unwrap();
+    assert!(
+        text.contains("group:staff:rw------------:f------:allow:50"),
+        "{}",
+        text
+    );
+
+    let entry = reader.next_header().unwrap();
+    assert!(entry.acl(AclType::Nfs4).is_empty());
+    let acl = entry.acl(AclType::Access);
+    assert!(acl.iter().any(|ace| ace.tag == AclTag::User
+        && ace.name.as_deref() == Some("bin")
+        && ace.id == Some(2)
+        && ace.perms == AclPerms::READ | AclPerms::WRITE | AclPerms::EXECUTE));
+    assert!(entry.add_acl_text(AclType::Nfs4, "not an acl").is_err());
+}
+
+#[test]
+fn writing_initramfs() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = tempfile.path().join("initramfs.cpio");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::CpioNewc).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    writer.set_ownership(archive::Ownership::Fixed { uid: 0, gid: 0 });
+    writer.write_directory("dev", 0o755).unwrap();
+    writer
+        .write_device(
+            "dev/console",
+            archive::FileType::CharacterDevice,
+            5,
+            1,
+            0o600,
+        )
+        .unwrap();
+    writer.write_symlink("init", "bin/sh").unwrap();
+    match writer.write_symlink("sh", "bin/\0sh") {
+        Err(ArchiveError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput),
+        _ => panic!("expected an error for a NUL in the symlink target"),
+    }
+    assert!(writer
+        .write_device("dev/null", archive::FileType::RegularFile, 1, 3, 0o666)
+        .is_err());
+    writer.close().unwrap();
+
+    let reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(&path)
+        .unwrap();
+    let listings = reader
+        .into_iter()
+        .map(|entry| entry.unwrap().listing().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(listings.len(), 3);
+    assert!(
+        listings[0].starts_with("drwxr-xr-x  2 0      0 "),
+        "{}",
+        listings[0]
+    );
+    assert!(
+        listings[1].starts_with("crw-------  1 0      0 "),
+        "{}",
+        listings[1]
+    );
+    assert!(listings[1].contains(" 5,1 "), "{}", listings[1]);
+    assert!(listings[1].ends_with(" dev/console"), "{}", listings[1]);
+    assert!(listings[2].ends_with(" init -> bin/sh"), "{}", listings[2]);
+
+    // Targets are written as the bytes they are, even if they aren't valid UTF-8.
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let path = tempfile.path().join("latin1.cpio");
+        let builder = writer::Builder::new();
+        builder.set_format(archive::WriteFormat::CpioNewc).unwrap();
+        let mut writer = builder.open_file(&path).unwrap();
+        let target = std::ffi::OsStr::from_bytes(b"caf\xe9");
+        writer.write_symlink("link", target).unwrap();
+        writer.close().unwrap();
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.windows(4).any(|bytes| bytes == b"caf\xe9"));
+    }
+}
+
+#[test]
+fn writing_bootable_iso() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = tempfile.path().join("boot.iso");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Iso9660).unwrap();
+    let mut boot = archive::IsoBoot::new("boot/loader.bin");
+    boot.catalog = Some("boot/boot.cat".to_string());
+    boot.load_size = Some(4);
+    match builder.set_iso_boot(&archive::IsoBoot::new("boot/\0loader.bin")) {
+        Err(ArchiveError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput),
+        _ => panic!("expected an error for a NUL in the boot image path"),
+    }
+    builder.set_iso_boot(&boot).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    let loader = vec![0u8; 2048];
+    writer
+        .write_entries(vec![("boot/loader.bin", &loader[..])])
+        .unwrap();
+    writer.close().unwrap();
+
+    // The boot record volume descriptor follows the primary one in sector 16.
+    let iso = std::fs::read(&path).unwrap();
+    let record = &iso[17 * 2048..18 * 2048];
+    assert!(record.starts_with(b"\0CD001\x01EL TORITO SPECIFICATION"));
+}
+
+#[test]
+fn reading_rpm_payload() {
+    let entries = libarchive::open_rpm(util::path::fixture("hello.rpm"))
+        .unwrap()
+        .map(|entry| entry.unwrap().pathname().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        entries,
+        vec!["./usr/share/doc/hello/README", "./usr/bin/hello"]
+    );
+
+    let mut entries = libarchive::open_rpm(util::path::fixture("hello.rpm")).unwrap();
+    let mut readme = String::new();
+    entries
+        .next()
+        .unwrap()
+        .unwrap()
+        .read_to_string(&mut readme)
+        .unwrap();
+    assert_eq!(readme, "hello, world!\n");
+
+    assert!(libarchive::open_rpm(util::path::fixture("sample.tar.gz")).is_err());
+}
+
+#[test]
+fn dumping_metadata() {
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(util::path::fixture("setuid.tar"))
+        .unwrap();
+    let mut out = Vec::new();
+    let count = reader
+        .dump_metadata(&mut out, archive::DumpFormat::JsonLines)
+        .unwrap();
+    assert_eq!(count, 1);
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.ends_with("}\n"), "{}", out);
+    assert_eq!(out.lines().count(), 1);
+    assert!(
+        out.starts_with(
+            "{\"path\":\"hello.txt\",\"type\":\"file\",\"size\":14,\"mode\":35325,\"mtime\":1670630400,"
+        ),
+        "{}",
+        out
+    );
+    assert!(out.contains(",\"link\":null}"), "{}", out);
+}
+
+#[test]
+fn auditing_archives() {
+    use libarchive::Finding;
+
+    let audit = |path: &std::path::Path, max_size| {
+        reader::Builder::new()
+            .support_all()
+            .unwrap()
+            .open_file(path)
+            .unwrap()
+            .audit(max_size)
+            .unwrap()
+            .into_iter()
+            .map(|issue| (issue.pathname, issue.finding))
+            .collect::<Vec<_>>()
+    };
+
+    let escaping = audit(&util::path::fixture("escaping.tar"), None);
+    assert_eq!(
+        escaping,
+        vec![
+            ("/tmp/abs.txt".to_string(), Finding::AbsolutePath),
+            ("../up.txt".to_string(), Finding::ParentTraversal),
+        ]
+    );
+    let setuid = audit(&util::path::fixture("setuid.tar"), Some(10));
+    assert_eq!(
+        setuid,
+        vec![
+            ("hello.txt".to_string(), Finding::Setuid),
+            ("hello.txt".to_string(), Finding::Oversized(14)),
+        ]
+    );
+
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = tempfile.path().join("suspicious.tar");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    writer.write_symlink("etc", "../../etc").unwrap();
+    writer.write_symlink("lib/ok", "../lib64").unwrap();
+    writer
+        .write_entries(vec![
+            ("etc/passwd", &b"root"[..]),
+            ("a.txt", &b"a"[..]),
+            ("a.txt", &b"b"[..]),
+        ])
+        .unwrap();
+    writer
+        .write_device("dev/null", archive::FileType::CharacterDevice, 1, 3, 0o666)
+        .unwrap();
+    writer.close().unwrap();
+    assert_eq!(
+        audit(&path, None),
+        vec![
+            (
+                "etc".to_string(),
+                Finding::EscapingLink("../../etc".to_string())
+            ),
+            ("etc/passwd".to_string(), Finding::BelowSymlink),
+            ("a.txt".to_string(), Finding::Duplicate),
+            ("dev/null".to_string(), Finding::Device),
+        ]
+    );
+}
+
+#[test]
+fn limiting_entry_size() {
+    let open = || {
+        reader::Builder::new()
+            .support_all()
+            .unwrap()
+            .max_entry_size(10)
+            .open_file(util::path::fixture("setuid.tar"))
+            .unwrap()
+    };
+
+    let mut entry = open().into_iter().next().unwrap().unwrap();
+    let mut data = Vec::new();
+    let err = entry.read_to_end(&mut data).unwrap_err();
+    assert!(
+        err.to_string().contains("entry size of 10 bytes"),
+        "{}",
+        err
+    );
+
+    match open().read_all(None) {
+        Err(ArchiveError::LimitExceeded(Limit::EntrySize(10))) => (),
+        other => panic!(
+            "unexpected result: {:?}",
+            other.map(|entries| entries.len())
+        ),
+    }
+
+    let tempfile = tempfile::tempdir().unwrap();
+    let disk = writer::Disk::new();
+    match disk.write(&mut open(), tempfile.path().to_str()) {
+        Err(ArchiveError::LimitExceeded(Limit::EntrySize(10))) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn filtering_through_programs() {
+    use libarchive::FilterProgram;
+    use std::time::{Duration, Instant};
+
+    let gunzip = FilterProgram::new("gzip").arg("-dc");
+    let reader = reader::Builder::new()
+        .support_format(archive::ReadFormat::All)
+        .unwrap()
+        .open_program(util::path::fixture("sample.tar.gz"), &gunzip)
+        .unwrap();
+    let entries = reader.into_iter().collect::<Result<Vec<_>, _>>().unwrap();
+    assert!(!entries.is_empty());
+
+    // Failures show up when opening or, if the program is slow to fail, when reading.
+    let first_error = |program: &FilterProgram| {
+        let result = reader::Builder::new()
+            .support_format(archive::ReadFormat::All)
+            .unwrap()
+            .open_program(util::path::fixture("sample.tar.gz"), program);
+        match result {
+            Ok(reader) => reader
+                .into_iter()
+                .find_map(|entry| entry.err())
+                .expect("no error"),
+            Err(err) => err,
+        }
+    };
+    let err = first_error(&FilterProgram::new("sh").arg("-c").arg("exit 3"));
+    assert!(err.to_string().contains("exit status: 3"), "{}", err);
+
+    let started = Instant::now();
+    let hanging = FilterProgram::new("sleep")
+        .arg("10")
+        .timeout(Duration::from_millis(100));
+    let err = first_error(&hanging);
+    assert!(err.to_string().contains("timed out"), "{}", err);
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = tempfile.path().join("filtered.tar.gz");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let gzip = FilterProgram::new("gzip").arg("-c");
+    let mut writer = builder.open_program(&path, &gzip).unwrap();
+    writer
+        .write_entries(vec![("hello.txt", &b"hello, world!\n"[..])])
+        .unwrap();
+    writer.close().unwrap();
+    let archive = libarchive::Archive::open(&path).unwrap();
+    assert_eq!(
+        archive.read_entry("hello.txt").unwrap().unwrap(),
+        b"hello, world!\n"
+    );
+
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let failing = FilterProgram::new("sh")
+        .arg("-c")
+        .arg("cat >/dev/null; exit 3");
+    let mut writer = builder.open_program(&path, &failing).unwrap();
+    writer
+        .write_entries(vec![("hello.txt", &b"hello, world!\n"[..])])
+        .unwrap();
+    let err = writer.close().unwrap_err();
+    assert!(err.to_string().contains("exit status: 3"), "{}", err);
+}
+
+#[test]
+fn extracting_with_create_hook() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = tempfile.path().join("hooked.tar");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    writer
+        .write_entries(vec![("a.txt", &b"a"[..]), ("b.txt", &b"b"[..])])
+        .unwrap();
+    writer
+        .write_device("null", archive::FileType::CharacterDevice, 1, 3, 0o666)
+        .unwrap();
+    writer.write_entries(vec![("c.txt", &b"c"[..])]).unwrap();
+    writer.close().unwrap();
+
+    let out = tempfile.path().join("out");
+    let seen = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let mut disk = writer::Disk::new();
+    let hook_seen = seen.clone();
+    disk.set_create_hook(move |path, entry| {
+        hook_seen.borrow_mut().push(path.to_path_buf());
+        match entry.filetype() {
+            archive::FileType::CharacterDevice => archive::Decision::Abort,
+            _ if path.ends_with("b.txt") => archive::Decision::Skip,
+            _ => archive::Decision::Allow,
+        }
+    });
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(&path)
+        .unwrap();
+    match disk.write(&mut reader, out.to_str()) {
+        Err(ArchiveError::Aborted(pathname)) => assert!(pathname.ends_with("null")),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(
+        *seen.borrow(),
+        vec![out.join("a.txt"), out.join("b.txt"), out.join("null")]
+    );
+    assert!(out.join("a.txt").exists());
+    assert!(!out.join("b.txt").exists());
+    assert!(!out.join("c.txt").exists());
+}
+
+#[test]
+fn listing_entry_metadata() {
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(util::path::fixture("setuid.tar"))
+        .unwrap();
+    let entries = reader.list_entries().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].pathname.as_deref(), Some("hello.txt"));
+    assert_eq!(entries[0].size, 14);
+    assert_eq!(
+        entries[0].filetype,
+        reader::ArchiveEntryFiletype::RegularFile
+    );
+    assert_eq!(entries[0].mode, 0o104775);
+    assert_eq!(entries[0].perm, 0o4775);
+    assert_eq!(entries[0].mtime, 1670630400);
+    assert_eq!(entries[0].hardlink, None);
+}
+
+#[test]
+fn extracting_stored_data() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = tempfile.path().join("stored.tar");
+    let long = format!("{}/file.bin", "d".repeat(120));
+    let big = (0..100_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let files = vec![
+        ("a.txt".to_string(), b"hello, world!\n".to_vec()),
+        (long, big.clone()),
+        ("b.bin".to_string(), big[..513].to_vec()),
+    ];
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    writer
+        .write_entries(files.iter().map(|(name, data)| (name, &data[..])))
+        .unwrap();
+    writer.close().unwrap();
+
+    let out = tempfile.path().join("out");
+    let disk = writer::Disk::new();
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(&path)
+        .unwrap();
+    let bytes = disk.write(&mut reader, out.to_str()).unwrap();
+    assert_eq!(bytes, 14 + 100_000 + 513);
+    for (name, data) in files {
+        assert_eq!(std::fs::read(out.join(name)).unwrap(), data);
+    }
+}
+
+#[test]
+fn reading_into_pooled_buffers() {
+    let pool = libarchive::BufferPool::new(1);
+    let read = || {
+        reader::Builder::new()
+            .support_all()
+            .unwrap()
+            .buffer_pool(pool.clone())
+            .open_file(util::path::fixture("sample.tar.gz"))
+            .unwrap()
+            .read_all(None)
+            .unwrap()
+    };
+
+    let mut entries = read();
+    assert_eq!(entries.len(), 1);
+    let (_, data) = entries.remove(0);
+    let capacity = data.capacity();
+    pool.put(data);
+    assert_eq!(pool.available(), 1);
+
+    // The pool is full, so this buffer is dropped instead of replacing the one kept.
+    pool.put(Vec::with_capacity(capacity + 4096));
+    assert_eq!(pool.available(), 1);
+
+    let entries = read();
+    assert_eq!(pool.available(), 0);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].1.capacity(), capacity);
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn reading_into_bytes() {
+    let entries = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(util::path::fixture("setuid.tar"))
+        .unwrap()
+        .read_all_bytes(None)
+        .unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(&entries[0].1[..], b"hello, world!\n");
+}
+
+#[test]
+fn reading_from_archive_source() {
+    let reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(util::path::fixture("sample.tar.gz"))
+        .unwrap();
+    let mut source: Box<dyn archive::ArchiveSource> = Box::new(reader);
+    let entry = source.next_entry().unwrap().unwrap();
+    assert_eq!(entry.metadata.pathname.as_deref(), Some("hello.txt"));
+    assert_eq!(entry.data, b"hello, world!\n");
+    assert!(source.next_entry().unwrap().is_none());
+}
+
+#[test]
+fn extracting_single_entries() {
+    let tempfile = tempfile::tempdir().unwrap();
+    let path = tempfile.path().join("entries.tar");
+    let builder = writer::Builder::new();
+    builder.set_format(archive::WriteFormat::Pax).unwrap();
+    let mut writer = builder.open_file(&path).unwrap();
+    let out = tempfile.path().join("out");
+    let a = out.join("a.txt");
+    let b = out.join("b.txt");
+    writer
+        .write_entries(vec![(&a, &b"a"[..]), (&b, &b"b"[..])])
+        .unwrap();
+    writer.close().unwrap();
+
+    let disk = writer::Disk::new();
+    disk.set_options(&archive::ExtractOptions::new().preserve_times(true))
+        .unwrap();
+    let reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(&path)
+        .unwrap();
+    for entry in reader {
+        let entry = entry.unwrap();
+        if entry.pathname().unwrap().ends_with("b.txt") {
+            disk.extract_entry(&entry).unwrap();
+        }
+    }
+    assert!(!a.exists());
+    assert_eq!(std::fs::read(&b).unwrap(), b"b");
+}
+
+#[test]
+fn extracting_with_name_lookups() {
+    let looked_up = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let disk = writer::Disk::new();
+    disk.set_options(&archive::ExtractOptions::new().preserve_owner(true))
+        .unwrap();
+    let users = looked_up.clone();
+    disk.set_user_lookup(move |name, uid| {
+        users.borrow_mut().push((name.to_owned(), uid));
+        4242
+    })
+    .unwrap();
+    let groups = looked_up.clone();
+    disk.set_group_lookup(move |name, gid| {
+        groups.borrow_mut().push((name.to_owned(), gid));
+        4343
+    })
+    .unwrap();
+
+    let mut reader = reader::Builder::new()
+        .support_all()
+        .unwrap()
+        .open_file(util::path::fixture("owned.tar"))
+        .unwrap();
+    let tempfile = tempfile::tempdir().unwrap();
+    // Changing ownership needs privileges. Without them the file is still written, and libarchive
+    // reports the failed chown once the entry is finished.
+    match disk.write(&mut reader, tempfile.path().to_str()) {
+        Ok(bytes) => assert_eq!(bytes, 14),
+        Err(ArchiveError::Sys(ErrCode(code), message)) => {
+            let error = std::io::Error::from_raw_os_error(code);
+            assert_eq!(
+                error.kind(),
+                std::io::ErrorKind::PermissionDenied,
+                "{}",
+                message
+            );
+            assert!(message.contains("Can't set user"), "{}", message);
+        }
+        Err(e) => panic!("unexpected error: {}", e),
+    }
+    assert_eq!(
+        std::fs::read(tempfile.path().join("owned.txt")).unwrap(),
+        b"owned by user\n"
+    );
+    let mut looked_up = looked_up.borrow().clone();
+    looked_up.sort();
+    assert_eq!(
+        looked_up,
+        vec![("builder".to_owned(), 1000), ("staff".to_owned(), 1000)]
+    );
+}